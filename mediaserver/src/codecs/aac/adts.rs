@@ -0,0 +1,66 @@
+use crate::codecs::aac::config::Config;
+use bytes::Bytes;
+
+const ADTS_HEADER_SIZE: usize = 7;
+
+pub struct AdtsFrame {
+    pub config: Config,
+    pub payload: Bytes,
+}
+
+// ADTS(ISO/IEC 13818-7 6.2) 로 감싸진 AAC frame 들을 분리한다.
+pub fn split_frames(data: &Bytes) -> anyhow::Result<Vec<AdtsFrame>> {
+    let mut frames = Vec::new();
+    let mut offset = 0;
+    while offset + ADTS_HEADER_SIZE <= data.len() {
+        let header = &data[offset..];
+        if header[0] != 0xFF || header[1] & 0xF0 != 0xF0 {
+            anyhow::bail!("invalid adts sync word");
+        }
+        let protection_absent = header[1] & 0x01;
+        let profile = header[2] >> 6;
+        let frequency_index = (header[2] >> 2) & 0x0F;
+        let channel_config = ((header[2] & 0x01) << 2) | (header[3] >> 6);
+        let frame_length = (((header[3] & 0x03) as usize) << 11)
+            | ((header[4] as usize) << 3)
+            | ((header[5] as usize) >> 5);
+        let header_size = if protection_absent == 1 {
+            ADTS_HEADER_SIZE
+        } else {
+            ADTS_HEADER_SIZE + 2
+        };
+        if frame_length < header_size || offset + frame_length > data.len() {
+            anyhow::bail!("short adts frame");
+        }
+
+        // AudioSpecificConfig: object_type(5) frequency_index(4) channel_config(4)
+        let object_type = profile + 1;
+        let asc = [
+            (object_type << 3) | (frequency_index >> 1),
+            ((frequency_index & 0x01) << 7) | (channel_config << 3),
+        ];
+        frames.push(AdtsFrame {
+            config: Config::from(&asc)?,
+            payload: data.slice(offset + header_size..offset + frame_length),
+        });
+        offset += frame_length;
+    }
+    Ok(frames)
+}
+
+// raw AAC frame 앞에 붙일 ADTS header. CRC 는 쓰지 않는다. (protection_absent = 1)
+pub fn make_header(config: &Config, payload_len: usize) -> [u8; ADTS_HEADER_SIZE] {
+    let frame_length = payload_len + ADTS_HEADER_SIZE;
+    let profile = config.object_type.saturating_sub(1) & 0x03;
+    [
+        0xFF,
+        0xF1,
+        (profile << 6)
+            | ((config.frequency_index & 0x0F) << 2)
+            | ((config.channel_config >> 2) & 0x01),
+        ((config.channel_config & 0x03) << 6) | ((frame_length >> 11) & 0x03) as u8,
+        ((frame_length >> 3) & 0xFF) as u8,
+        (((frame_length & 0x07) << 5) as u8) | 0x1F,
+        0xFC,
+    ]
+}
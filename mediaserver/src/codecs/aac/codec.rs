@@ -0,0 +1,55 @@
+use crate::codecs::aac::config::Config;
+use crate::utils::types::types;
+use webrtc::rtp_transceiver::rtp_codec::RTCRtpCodecCapability;
+
+#[derive(Debug, Clone, Hash, Eq, PartialEq)]
+pub struct AacCodec {
+    config: Config,
+}
+
+impl AacCodec {
+    pub fn new(config: Config) -> AacCodec {
+        AacCodec { config }
+    }
+
+    pub fn kind(&self) -> types::MediaKind {
+        types::MediaKind::Audio
+    }
+
+    pub fn codec_string(&self) -> String {
+        format!("mp4a.40.{}", self.config.object_type)
+    }
+
+    pub fn mime_type(&self) -> &'static str {
+        "audio/aac"
+    }
+
+    pub fn clock_rate(&self) -> u32 {
+        self.config.sample_rate()
+    }
+
+    pub fn channels(&self) -> u16 {
+        self.config.channels()
+    }
+
+    pub fn samples(&self) -> u32 {
+        1024
+    }
+
+    pub fn config(&self) -> &Config {
+        &self.config
+    }
+
+    pub fn rtp_codec_capability(&self) -> RTCRtpCodecCapability {
+        RTCRtpCodecCapability {
+            mime_type: "audio/mpeg4-generic".to_string(),
+            clock_rate: self.clock_rate(),
+            channels: self.channels(),
+            sdp_fmtp_line: format!(
+                "streamtype=5;profile-level-id=1;mode=AAC-hbr;sizelength=13;indexlength=3;indexdeltalength=3;config={}",
+                hex::encode(&self.config.payload)
+            ),
+            rtcp_feedback: vec![],
+        }
+    }
+}
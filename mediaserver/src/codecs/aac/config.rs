@@ -0,0 +1,43 @@
+use bytes::Bytes;
+
+const SAMPLE_RATES: [u32; 13] = [
+    96000, 88200, 64000, 48000, 44100, 32000, 24000, 22050, 16000, 12000, 11025, 8000, 7350,
+];
+
+// AudioSpecificConfig (ISO/IEC 14496-3 1.6.2.1)
+#[derive(Debug, Clone, Hash, Eq, PartialEq)]
+pub struct Config {
+    pub payload: Bytes,
+    pub object_type: u8,
+    pub frequency_index: u8,
+    pub channel_config: u8,
+}
+
+impl Config {
+    pub fn from(data: &[u8]) -> anyhow::Result<Config> {
+        if data.len() < 2 {
+            anyhow::bail!("short audio specific config: {}", data.len());
+        }
+        let object_type = data[0] >> 3;
+        let frequency_index = ((data[0] & 0x07) << 1) | (data[1] >> 7);
+        let channel_config = (data[1] >> 3) & 0x0F;
+        if frequency_index as usize >= SAMPLE_RATES.len() {
+            anyhow::bail!("unsupported frequency index: {}", frequency_index);
+        }
+
+        Ok(Config {
+            payload: Bytes::copy_from_slice(data),
+            object_type,
+            frequency_index,
+            channel_config,
+        })
+    }
+
+    pub fn sample_rate(&self) -> u32 {
+        SAMPLE_RATES[self.frequency_index as usize]
+    }
+
+    pub fn channels(&self) -> u16 {
+        self.channel_config as u16
+    }
+}
@@ -0,0 +1,4 @@
+pub mod adts;
+pub mod bfs;
+pub mod codec;
+pub mod config;
@@ -1,4 +1,4 @@
-use crate::codecs::bfs::Bfs::{Opus, H264};
+use crate::codecs::bfs::Bfs::{Aac, Opus, H264};
 use crate::hubs::unit::HubUnit;
 use crate::{codecs, utils};
 use anyhow::anyhow;
@@ -6,6 +6,7 @@ use anyhow::anyhow;
 pub enum Bfs {
     Opus,
     H264,
+    Aac,
 }
 
 impl Bfs {
@@ -13,6 +14,7 @@ impl Bfs {
         match mime_type.to_lowercase().as_str() {
             "audio/opus" => Ok(Opus),
             "video/h264" => Ok(H264),
+            "audio/aac" => Ok(Aac),
             _ => Err(anyhow!("Unsupported codec: {}", mime_type)),
         }
     }
@@ -20,6 +22,7 @@ impl Bfs {
         match self {
             Opus => codecs::opus::bfs::make_packet(unit),
             H264 => codecs::h264::bfs::make_packet_with_avcc(unit),
+            Aac => codecs::aac::bfs::make_packet(unit),
         }
     }
 }
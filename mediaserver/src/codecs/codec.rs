@@ -1,3 +1,4 @@
+use crate::codecs::aac::codec::AacCodec;
 use crate::codecs::h264::codec::H264Codec;
 use crate::codecs::opus::codec::OpusCodec;
 use crate::utils::types::types;
@@ -7,6 +8,7 @@ use webrtc::rtp_transceiver::rtp_codec::RTCRtpCodecCapability;
 pub enum Codec {
     Opus(OpusCodec),
     H264(H264Codec),
+    Aac(AacCodec),
 }
 
 impl Codec {
@@ -22,6 +24,7 @@ impl Codec {
         match self {
             Codec::Opus(codec) => codec.kind(),
             Codec::H264(codec) => codec.kind(),
+            Codec::Aac(codec) => codec.kind(),
         }
     }
 
@@ -29,6 +32,7 @@ impl Codec {
         match self {
             Codec::Opus(codec) => codec.mime_type(),
             Codec::H264(codec) => codec.mime_type(),
+            Codec::Aac(codec) => codec.mime_type(),
         }
     }
 
@@ -36,6 +40,7 @@ impl Codec {
         match self {
             Codec::Opus(codec) => codec.clock_rate(),
             Codec::H264(codec) => codec.clock_rate(),
+            Codec::Aac(codec) => codec.clock_rate(),
         }
     }
 
@@ -43,6 +48,7 @@ impl Codec {
         match self {
             Codec::Opus(codec) => codec.samples(),
             Codec::H264(codec) => codec.samples(),
+            Codec::Aac(codec) => codec.samples(),
         }
     }
 
@@ -50,6 +56,7 @@ impl Codec {
         match self {
             Codec::Opus(codec) => codec.rtp_codec_capability(),
             Codec::H264(codec) => codec.rtp_codec_capability(),
+            Codec::Aac(codec) => codec.rtp_codec_capability(),
         }
     }
 
@@ -57,7 +64,7 @@ impl Codec {
         match self {
             Codec::H264(codec) => codec.codec_string(),
             Codec::Opus(codec) => codec.codec_string(),
-            _ => "".to_string(),
+            Codec::Aac(codec) => codec.codec_string(),
         }
     }
 
@@ -88,4 +95,11 @@ impl Codec {
             _ => 0,
         }
     }
+
+    pub fn audio_specific_config(&self) -> Option<Vec<u8>> {
+        match self {
+            Codec::Aac(codec) => Some(codec.config().payload.to_vec()),
+            _ => None,
+        }
+    }
 }
@@ -0,0 +1,51 @@
+use bytes::Bytes;
+
+// start code(00 00 01, 00 00 00 01) 로 구분된 NAL unit 들을 분리한다.
+pub fn split_nal_units(data: &Bytes) -> Vec<Bytes> {
+    let mut units = Vec::new();
+    let mut start: Option<usize> = None;
+    let mut i = 0;
+    while i + 3 <= data.len() {
+        if data[i] == 0 && data[i + 1] == 0 && data[i + 2] == 1 {
+            if let Some(begin) = start {
+                push_unit(&mut units, data, begin, i);
+            }
+            i += 3;
+            start = Some(i);
+            continue;
+        }
+        i += 1;
+    }
+    if let Some(begin) = start {
+        push_unit(&mut units, data, begin, data.len());
+    }
+    units
+}
+
+fn push_unit(units: &mut Vec<Bytes>, data: &Bytes, begin: usize, mut end: usize) {
+    // 4 byte start code 및 trailing zero 제거
+    while end > begin && data[end - 1] == 0 {
+        end -= 1;
+    }
+    if end > begin {
+        units.push(data.slice(begin..end));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_split_nal_units() {
+        let data = Bytes::from_static(&[
+            0x00, 0x00, 0x00, 0x01, 0x67, 0x42, 0x00, 0x00, 0x01, 0x68, 0xCE, 0x00, 0x00, 0x00,
+            0x01, 0x65, 0x88,
+        ]);
+        let units = split_nal_units(&data);
+        assert_eq!(units.len(), 3);
+        assert_eq!(units[0].as_ref(), &[0x67, 0x42]);
+        assert_eq!(units[1].as_ref(), &[0x68, 0xCE]);
+        assert_eq!(units[2].as_ref(), &[0x65, 0x88]);
+    }
+}
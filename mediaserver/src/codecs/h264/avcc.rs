@@ -0,0 +1,104 @@
+use crate::codecs::h264::config::Config;
+use bitstreams::h264::nal_unit::NalUnit;
+use bitstreams::h264::pps::PPS;
+use bitstreams::h264::sps::SPS;
+use byteorder::{BigEndian, ByteOrder};
+use bytes::Bytes;
+
+// AVCDecoderConfigurationRecord (ISO/IEC 14496-15 5.2.4.1)
+pub struct DecoderConfigurationRecord {
+    pub nal_length_size: usize,
+    pub sps: Vec<Bytes>,
+    pub pps: Vec<Bytes>,
+}
+
+impl DecoderConfigurationRecord {
+    pub fn from(data: &[u8]) -> anyhow::Result<DecoderConfigurationRecord> {
+        if data.len() < 7 {
+            anyhow::bail!("short avc decoder configuration record: {}", data.len());
+        }
+        if data[0] != 1 {
+            anyhow::bail!("unsupported avc configuration version: {}", data[0]);
+        }
+        let nal_length_size = (data[4] & 0x03) as usize + 1;
+
+        let mut offset = 5;
+        let sps_count = (data[offset] & 0x1F) as usize;
+        offset += 1;
+        let (sps, next) = read_parameter_sets(data, offset, sps_count)?;
+        offset = next;
+
+        if data.len() < offset + 1 {
+            anyhow::bail!("avc decoder configuration record has no pps");
+        }
+        let pps_count = data[offset] as usize;
+        offset += 1;
+        let (pps, _) = read_parameter_sets(data, offset, pps_count)?;
+
+        Ok(DecoderConfigurationRecord {
+            nal_length_size,
+            sps,
+            pps,
+        })
+    }
+
+    pub fn config(&self) -> anyhow::Result<Config> {
+        let sps_buffer = self
+            .sps
+            .first()
+            .ok_or(anyhow::anyhow!("no sps in decoder configuration record"))?;
+        let pps_buffer = self
+            .pps
+            .first()
+            .ok_or(anyhow::anyhow!("no pps in decoder configuration record"))?;
+
+        let mut sps_nal_unit = NalUnit::from(sps_buffer.as_ref())?;
+        let sps = SPS::from(&mut sps_nal_unit)?;
+        let mut pps_nal_unit = NalUnit::from(pps_buffer.as_ref())?;
+        let pps = PPS::from(&mut pps_nal_unit)?;
+
+        Ok(Config::from(sps, pps))
+    }
+}
+
+fn read_parameter_sets(
+    data: &[u8],
+    mut offset: usize,
+    count: usize,
+) -> anyhow::Result<(Vec<Bytes>, usize)> {
+    let mut sets = Vec::with_capacity(count);
+    for _ in 0..count {
+        if data.len() < offset + 2 {
+            anyhow::bail!("short parameter set length");
+        }
+        let len = BigEndian::read_u16(&data[offset..]) as usize;
+        offset += 2;
+        if data.len() < offset + len {
+            anyhow::bail!("short parameter set");
+        }
+        sets.push(Bytes::copy_from_slice(&data[offset..offset + len]));
+        offset += len;
+    }
+    Ok((sets, offset))
+}
+
+// length prefix 로 구분된 NAL unit 들을 분리한다.
+pub fn split_nal_units(data: &Bytes, nal_length_size: usize) -> anyhow::Result<Vec<Bytes>> {
+    let mut units = Vec::new();
+    let mut offset = 0;
+    while offset < data.len() {
+        if data.len() < offset + nal_length_size {
+            anyhow::bail!("short nal unit length");
+        }
+        let len = BigEndian::read_uint(&data[offset..], nal_length_size) as usize;
+        offset += nal_length_size;
+        if data.len() < offset + len {
+            anyhow::bail!("short nal unit");
+        }
+        if len > 0 {
+            units.push(data.slice(offset..offset + len));
+        }
+        offset += len;
+    }
+    Ok(units)
+}
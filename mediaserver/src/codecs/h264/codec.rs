@@ -31,6 +31,10 @@ impl H264Codec {
         )
     }
 
+    pub fn config(&self) -> &Config {
+        &self.config
+    }
+
     pub fn sps(&self) -> Option<Vec<u8>> {
         Some(self.config.sps.payload.clone().to_vec())
     }
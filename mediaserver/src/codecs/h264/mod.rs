@@ -1,3 +1,5 @@
+pub mod annexb;
+pub mod avcc;
 pub(crate) mod bfs;
 pub mod codec;
 pub mod config;
@@ -20,6 +20,11 @@ impl H264RtpPayloader {
 }
 
 impl H264RtpPayloader {
+    // IDR 앞에 넣는 SPS/PPS 를 새 codec 의 것으로 바꾼다.
+    pub fn set_codec(&mut self, codec: &Codec) {
+        self.codec = codec.clone();
+    }
+
     pub fn payload(&mut self, mtu: usize, payload: &Bytes) -> anyhow::Result<Vec<Bytes>> {
         let nalu_type = NALUType::from_byte(payload[0]);
         if nalu_type == NALUType::SPS || nalu_type == NALUType::PPS {
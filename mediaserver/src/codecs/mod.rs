@@ -1,6 +1,7 @@
 use crate::hubs::unit::FrameInfo;
 use bytes::Bytes;
 
+pub mod aac;
 pub mod bfs;
 pub mod codec;
 pub mod h264;
@@ -29,6 +29,23 @@ impl RtpPacketizer {
         }
     }
 
+    // sequence, timestamp 는 이어가고 payloader 의 parameter set 만 바꾼다.
+    pub fn set_codec(&mut self, codec: &Codec) {
+        self.payloader.set_codec(codec);
+        self.codec = codec.clone();
+    }
+
+    // 다음 packet 의 (sequence, timestamp)
+    pub fn position(&self) -> (u16, u32) {
+        (self.sequence, self.timestamp)
+    }
+
+    // simulcast layer 를 바꿀 때 이전 layer 의 packetizer 에 이어서 sequence, timestamp 를 쓴다.
+    // viewer 는 ssrc 가 같은 하나의 stream 으로 받는다.
+    pub fn continue_from(&mut self, position: (u16, u32)) {
+        (self.sequence, self.timestamp) = position;
+    }
+
     pub fn packetize(&mut self, payload: &Bytes, duration: u32) -> anyhow::Result<Vec<Packet>> {
         let payloads = self.payloader.payload(self.mtu - 12, payload)?;
         if payloads.len() == 0 {
@@ -19,6 +19,13 @@ impl RtpPayloader {
         }
     }
 
+    pub fn set_codec(&mut self, codec: &Codec) {
+        match self {
+            RtpPayloader::Opus(_) => {}
+            RtpPayloader::H264(payloader) => payloader.set_codec(codec),
+        }
+    }
+
     pub fn payload(&mut self, mtu: usize, b: &Bytes) -> anyhow::Result<Vec<Bytes>> {
         match self {
             RtpPayloader::Opus(payloader) => payloader.payload(mtu, b),
@@ -1,105 +1,411 @@
-use crate::egress::services::hls::config::{ConfigParams, HlsConfig};
+use crate::egress::services::hls::config::{ConfigParams, HlsConfig, HlsFormat};
 use crate::egress::services::hls::service::HlsService;
+use crate::egress::services::hls::store::SegmentStore;
 use crate::egress::sessions::hls::handler::HlsHandler;
-use crate::egress::sessions::session::Session;
+use crate::egress::sessions::session::{Session, CODEC_WAIT_TIMEOUT};
 use crate::hubs::hub::Hub;
 use crate::utils::types::types::MediaKind;
 use std::collections::HashMap;
 use std::sync::atomic::Ordering;
 use std::sync::Arc;
-use tokio::sync::RwLock;
+use std::time::{Duration, Instant};
+use tokio::sync::{OnceCell, RwLock};
 use uuid::Uuid;
+
+// stream 단위 session 의 prefix. /v1/public/hls/stream/{stream_id}/index.m3u8
+const STREAM_SESSION_PREFIX: &str = "stream";
+// source 의 bitrate 를 아직 모를 때 쓰는 BANDWIDTH
+const DEFAULT_BANDWIDTH: u64 = 1000000;
+
 pub struct HlsServer {
     hub: Arc<Hub>,
+    gop_cache: bool,
+    store: Arc<dyn SegmentStore>,
+    // session 을 만들 때 format 을 고르지 않으면 쓰는 format. stream 단위 session 은 항상 이것을 쓴다.
+    format: HlsFormat,
+    // 마지막 요청 후 이 시간이 지나면 stream 단위 session 을 정리한다.
+    idle_timeout: Duration,
+    dvr_window: Option<Duration>,
+    // publisher 가 끝난 뒤 VOD 로 남겨두는 시간
+    vod_retention: Duration,
 
     sessions: RwLock<HashMap<String, Arc<HlsSession>>>,
+    // stream_id 별로 모든 viewer 가 같이 쓰는 session. 만드는 중이면 비어있다.
+    streams: RwLock<HashMap<String, Arc<OnceCell<Arc<HlsSession>>>>>,
 }
 
 pub struct HlsSession {
+    // simulcast 이면 layer 마다 하나씩, 해상도가 높은 순서. 첫 variant 가 master playlist 를 쓴다.
+    pub variants: Vec<HlsVariant>,
+    last_access: RwLock<Instant>,
+}
+
+pub struct HlsVariant {
     pub handler: Arc<Session<HlsHandler>>,
     pub service: Arc<HlsService>,
     pub config: HlsConfig,
 }
 
+impl HlsSession {
+    pub async fn touch(&self) {
+        *self.last_access.write().await = Instant::now();
+    }
+
+    async fn idle_duration(&self) -> Duration {
+        self.last_access.read().await.elapsed()
+    }
+
+    // master playlist 를 가진 가장 높은 layer
+    pub fn primary(&self) -> &HlsVariant {
+        &self.variants[0]
+    }
+
+    // filename 의 video_base 로 variant 를 고른다. ex) video1/video.m3u8
+    pub fn variant(&self, filename: &str) -> &HlsVariant {
+        self.variants
+            .iter()
+            .find(|variant| filename.starts_with(&format!("{}/", variant.config.video_base)))
+            .unwrap_or(self.primary())
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.variants
+            .iter()
+            .all(|variant| variant.service.is_finished())
+    }
+
+    async fn run(&self) -> anyhow::Result<()> {
+        let runs =
+            futures::future::join_all(self.variants.iter().map(|variant| variant.handler.run()));
+        tokio::pin!(runs);
+        tokio::select! {
+            results = &mut runs => return results.into_iter().collect(),
+            _ = self.sync_rendition_reports() => {}
+        }
+        runs.await.into_iter().collect()
+    }
+
+    // 한 variant 의 playlist 가 바뀔 때마다 다른 variant 들의 EXT-X-RENDITION-REPORT 를 갱신한다.
+    async fn sync_rendition_reports(&self) {
+        if self.variants.len() < 2 {
+            return std::future::pending().await;
+        }
+        let mut receivers = self
+            .variants
+            .iter()
+            .map(|variant| variant.service.subscribe_created())
+            .collect::<Vec<_>>();
+        loop {
+            let (changed, _, _) = futures::future::select_all(
+                receivers
+                    .iter_mut()
+                    .map(|receiver| Box::pin(receiver.changed())),
+            )
+            .await;
+            if changed.is_err() {
+                return;
+            }
+
+            let mut reports = vec![];
+            for variant in self.variants.iter() {
+                reports.push(variant.service.rendition_report().await);
+            }
+            for (index, variant) in self.variants.iter().enumerate() {
+                let others = reports
+                    .iter()
+                    .enumerate()
+                    .filter(|(other, _)| *other != index)
+                    .map(|(_, report)| report.clone())
+                    .collect();
+                variant.service.set_rendition_reports(others).await;
+            }
+        }
+    }
+
+    fn stop(&self) {
+        for variant in self.variants.iter() {
+            variant.handler.stop();
+        }
+    }
+
+    // variant 들은 같은 session 경로 아래에 쓰므로 모두 지운다.
+    async fn close(&self) -> anyhow::Result<()> {
+        for variant in self.variants.iter() {
+            variant.service.close().await?;
+        }
+        Ok(())
+    }
+}
+
 impl HlsServer {
-    pub fn new(hub: Arc<Hub>) -> Arc<Self> {
+    pub fn new(
+        hub: Arc<Hub>,
+        gop_cache: bool,
+        store: Arc<dyn SegmentStore>,
+        format: HlsFormat,
+        idle_timeout: Duration,
+        dvr_window: Option<Duration>,
+        vod_retention: Duration,
+    ) -> Arc<Self> {
         m3u8_rs::WRITE_OPT_FLOAT_PRECISION.store(5, Ordering::Relaxed);
         Arc::new(Self {
             hub,
+            gop_cache,
+            store,
+            format,
+            idle_timeout,
+            dvr_window,
+            vod_retention,
             sessions: RwLock::new(HashMap::new()),
+            streams: RwLock::new(HashMap::new()),
         })
     }
 
-    pub async fn start_session(self: &Arc<Self>, stream_id: &str) -> anyhow::Result<String> {
-        let hub_stream = self
-            .hub
-            .get_stream(&stream_id)
-            .await
-            .ok_or(anyhow::anyhow!("stream not found"))?;
-
+    pub async fn start_session(
+        self: &Arc<Self>,
+        stream_id: &str,
+        format: Option<HlsFormat>,
+    ) -> anyhow::Result<String> {
         let session_id = Uuid::new_v4().to_string();
+        let session = self
+            .create_session(stream_id, &session_id, format.unwrap_or(self.format))
+            .await?;
         log::info!("hls session started: {}", &session_id);
 
-        let mut width = 0;
-        let mut height = 0;
-        let mut video_codec_string = "".to_string();
-        let mut audio_codec_string = "".to_string();
-        for source in hub_stream.get_sources().await {
-            let Some(codec) = source.get_codec().await else {
-                continue;
-            };
-            if codec.kind() == MediaKind::Video {
-                width = codec.width() as u64;
-                height = codec.height() as u64;
-                video_codec_string = codec.codec_string();
-            } else if codec.kind() == MediaKind::Audio {
-                audio_codec_string = codec.codec_string();
-            }
+        {
+            self.sessions
+                .write()
+                .await
+                .insert(session_id.to_string(), session.clone());
         }
 
-        let config: HlsConfig = HlsConfig::new(ConfigParams {
-            session_id: session_id.clone(),
-            video_base: "video0".to_string(),
-            codecs: format!("{},{}", video_codec_string, audio_codec_string),
-            // codecs: "avc1.42C020,Opus".to_string(),
-            width: width,
-            height: height,
-            bandwidth: 1000000, // todo
-            framerate: 30.0,
-            part_duration: 1.0,
-            part_max_count: 2,
+        let server = self.clone();
+        let session_id2 = session_id.clone();
+        tokio::spawn(async move {
+            println!("run hls session");
+            if let Err(err) = session.run().await {
+                log::warn!("write file failed: {:?}", err);
+            }
+
+            // publisher 가 끝났으면 retention 동안 VOD 로 남겨둔다.
+            if session.is_finished() {
+                tokio::time::sleep(server.vod_retention).await;
+            }
+            let _ = server.stop_session(session_id2).await;
         });
 
-        let service = Arc::new(HlsService::new(config.clone()));
-        service.init().await?;
+        Ok(session_id)
+    }
 
-        let handler = HlsHandler::new(&hub_stream, service.clone()).await?;
+    // stream 의 공유 session 을 돌려준다. 없으면 첫 viewer 의 요청으로 시작한다.
+    pub async fn get_stream_session(
+        self: &Arc<Self>,
+        stream_id: &str,
+    ) -> anyhow::Result<Arc<HlsSession>> {
+        let republished = self.hub.get_stream(stream_id).await.is_some();
+        let current = self
+            .streams
+            .read()
+            .await
+            .get(stream_id)
+            .and_then(|entry| entry.get().cloned());
+        if let Some(session) = current {
+            if !session.is_finished() || !republished {
+                session.touch().await;
+                return Ok(session);
+            }
+        }
 
-        let sess = Session::new(&session_id, handler);
+        // codec 을 기다리는 동안 다른 stream 을 막지 않도록 빈 entry 만 넣고 lock 밖에서 만든다.
+        let entry = {
+            let mut streams = self.streams.write().await;
+            // write lock 을 기다리는 동안 다른 요청이 만들었거나 만들고 있을 수 있다.
+            let reuse = streams.get(stream_id).filter(|entry| match entry.get() {
+                Some(session) => !session.is_finished() || !republished,
+                None => true,
+            });
+            match reuse {
+                Some(entry) => entry.clone(),
+                None => {
+                    // VOD 로 남아있는 이전 방송 대신 다시 시작한 방송을 보여준다.
+                    let entry = Arc::new(OnceCell::new());
+                    streams.insert(stream_id.to_string(), entry.clone());
+                    entry
+                }
+            }
+        };
 
-        {
-            self.sessions.write().await.insert(
-                session_id.to_string(),
-                Arc::new(HlsSession {
-                    handler: sess.clone(),
-                    service: service.clone(),
-                    config: config.clone(),
-                }),
-            );
+        let mut created = false;
+        let created_flag = &mut created;
+        let result = entry
+            .get_or_try_init(|| async move {
+                *created_flag = true;
+                // 이전 방송의 VOD 와 겹치지 않도록 packager 마다 다른 곳에 저장한다.
+                let session_id =
+                    format!("{}/{}/{}", STREAM_SESSION_PREFIX, stream_id, Uuid::new_v4());
+                self.create_session(stream_id, &session_id, self.format)
+                    .await
+            })
+            .await
+            .cloned();
+        let session = match result {
+            Ok(session) => session,
+            Err(err) => {
+                // 실패한 entry 는 지워서 다음 요청이 다시 만들게 한다.
+                let mut streams = self.streams.write().await;
+                if streams
+                    .get(stream_id)
+                    .is_some_and(|current| Arc::ptr_eq(current, &entry))
+                {
+                    streams.remove(stream_id);
+                }
+                return Err(err);
+            }
+        };
+        if !created {
+            session.touch().await;
+            return Ok(session);
         }
+        log::info!("hls stream session started: {}", stream_id);
 
         let server = self.clone();
-        let session_id2 = session_id.clone();
+        let stream_id = stream_id.to_string();
+        let session2 = session.clone();
         tokio::spawn(async move {
-            println!("run hls session");
-            if let Err(err) = sess.run().await {
-                log::warn!("write file failed: {:?}", err);
+            let run = session2.run();
+            tokio::pin!(run);
+            let (result, idle) = tokio::select! {
+                result = &mut run => (result, false),
+                _ = server.wait_idle(&session2) => {
+                    log::info!("hls stream session idle: {}", stream_id);
+                    // on_finalize 까지 끝나도록 멈추고 기다린다.
+                    session2.stop();
+                    (run.await, true)
+                }
+            };
+            if let Err(err) = result {
+                log::warn!("hls stream session failed: {:?}", err);
             }
 
-            let _ = server.stop_session(session_id2).await;
+            // publisher 가 끝났으면 retention 동안 VOD 로 남겨둔다.
+            if !idle && session2.is_finished() {
+                tokio::time::sleep(server.vod_retention).await;
+            }
+
+            server.stop_stream_session(&stream_id, &session2).await;
         });
 
-        Ok(session_id)
+        Ok(session)
+    }
+
+    async fn wait_idle(&self, session: &HlsSession) {
+        let mut ticker = tokio::time::interval(self.idle_timeout / 2);
+        loop {
+            ticker.tick().await;
+            if session.idle_duration().await >= self.idle_timeout {
+                return;
+            }
+        }
+    }
+
+    async fn create_session(
+        &self,
+        stream_id: &str,
+        session_id: &str,
+        format: HlsFormat,
+    ) -> anyhow::Result<Arc<HlsSession>> {
+        let hub_stream = self
+            .hub
+            .get_stream(&stream_id)
+            .await
+            .ok_or(anyhow::anyhow!("stream not found"))?;
+
+        let mut audio = None;
+        let mut layers = vec![];
+        for (source, codec) in hub_stream.get_ready_sources(CODEC_WAIT_TIMEOUT).await {
+            if codec.kind() == MediaKind::Video {
+                layers.push((source, codec));
+            } else if codec.kind() == MediaKind::Audio && audio.is_none() {
+                audio = Some((source, codec));
+            }
+        }
+        // simulcast 이면 layer 마다 variant 를 만든다. 아니면 첫 video 하나만 쓴다.
+        if layers.iter().any(|(source, _)| source.rid().is_some()) {
+            layers.retain(|(source, _)| source.rid().is_some());
+        } else {
+            layers.truncate(1);
+        }
+        // audio only 이면 video 없는 variant 하나
+        let layers = match layers.is_empty() {
+            true => vec![None],
+            false => layers.into_iter().map(Some).collect(),
+        };
+
+        let (audio_codec_string, audio_bitrate) = audio
+            .as_ref()
+            .map_or(("".to_string(), 0), |(source, codec)| {
+                (codec.codec_string(), source.bitrate())
+            });
+        let mut variants = vec![];
+        for (index, layer) in layers.into_iter().enumerate() {
+            let (width, height, video_codec_string, video_bitrate) =
+                layer
+                    .as_ref()
+                    .map_or((0, 0, "".to_string(), 0), |(source, codec)| {
+                        (
+                            codec.width() as u64,
+                            codec.height() as u64,
+                            codec.codec_string(),
+                            source.bitrate(),
+                        )
+                    });
+            // audio only, video only 이면 있는 codec 만 CODECS 에 넣는다.
+            let codecs = [video_codec_string, audio_codec_string.clone()]
+                .into_iter()
+                .filter(|codec| !codec.is_empty())
+                .collect::<Vec<_>>()
+                .join(",");
+            // 실제로 받고 있는 bitrate 로 BANDWIDTH 를 쓴다.
+            let bandwidth = match video_bitrate + audio_bitrate {
+                0 => DEFAULT_BANDWIDTH,
+                bitrate => bitrate,
+            };
+
+            let config: HlsConfig = HlsConfig::new(ConfigParams {
+                session_id: session_id.to_string(),
+                video_base: format!("video{}", index),
+                format,
+                codecs,
+                width: width,
+                height: height,
+                bandwidth,
+                framerate: 30.0,
+                part_duration: 1.0,
+                part_max_count: 2,
+                dvr_window: self.dvr_window,
+            });
+
+            let service = Arc::new(HlsService::new(config.clone(), self.store.clone()));
+            let video_rid = layer.as_ref().and_then(|(source, _)| source.rid());
+            let handler =
+                HlsHandler::new(&hub_stream, service.clone(), video_rid, self.gop_cache).await?;
+            variants.push(HlsVariant {
+                handler: Session::new(session_id, handler),
+                service,
+                config,
+            });
+        }
+
+        let primary = &variants[0].service;
+        for variant in variants.iter().skip(1) {
+            primary.add_variant(&variant.config).await;
+        }
+        primary.init().await?;
+
+        Ok(Arc::new(HlsSession {
+            variants,
+            last_access: RwLock::new(Instant::now()),
+        }))
     }
 
     pub async fn get_session(
@@ -120,8 +426,30 @@ impl HlsServer {
         let session = sessions
             .remove(&session_id)
             .ok_or(anyhow::anyhow!("session not found"))?;
-        session.handler.stop();
+        session.stop();
+        if let Err(err) = session.close().await {
+            log::warn!("failed to clear hls store: {}", err);
+        }
         log::info!("record session stopped: {}", session_id);
         Ok(())
     }
+
+    async fn stop_stream_session(&self, stream_id: &str, session: &Arc<HlsSession>) {
+        {
+            let mut streams = self.streams.write().await;
+            // 그 사이에 새로 시작된 session 은 지우지 않는다.
+            if streams
+                .get(stream_id)
+                .and_then(|current| current.get())
+                .is_some_and(|current| Arc::ptr_eq(current, session))
+            {
+                streams.remove(stream_id);
+            }
+        }
+        session.stop();
+        if let Err(err) = session.close().await {
+            log::warn!("failed to clear hls store: {}", err);
+        }
+        log::info!("hls stream session stopped: {}", stream_id);
+    }
 }
@@ -1,2 +1,3 @@
 pub mod hls;
+pub mod record;
 pub mod whep;
@@ -0,0 +1,216 @@
+use crate::egress::services::record::config::{sanitize_stream_id, RecordConfig};
+use crate::egress::services::record::mkv_service::MkvRecordService;
+use crate::egress::services::record::service::RecordService;
+use crate::egress::sessions::record::handler::RecordHandler;
+use crate::egress::sessions::record::mkv_handler::MkvRecordHandler;
+use crate::egress::sessions::session::Session;
+use crate::hubs::hub::Hub;
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::broadcast::error::RecvError;
+use tokio::sync::RwLock;
+
+// publish 직후에는 ingress 가 아직 source 를 추가하지 않았을 수 있다.
+const AUTO_START_DELAY: Duration = Duration::from_secs(1);
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RecordFormat {
+    Mp4,
+    // Opus 만 있으면 webm, 아니면 mkv
+    Mkv,
+}
+
+impl RecordFormat {
+    pub fn from(format: &str) -> anyhow::Result<Self> {
+        match format.to_lowercase().as_str() {
+            "mp4" => Ok(RecordFormat::Mp4),
+            "mkv" | "webm" => Ok(RecordFormat::Mkv),
+            _ => Err(anyhow::anyhow!("unsupported record format: {}", format)),
+        }
+    }
+}
+
+pub struct RecordServer {
+    hub: Arc<Hub>,
+    gop_cache: bool,
+    format: RecordFormat,
+    dir: String,
+    max_duration: Option<Duration>,
+    max_size: Option<u64>,
+
+    // stream_id 별 녹화
+    recordings: RwLock<HashMap<String, Arc<RecordSession>>>,
+}
+
+pub enum RecordSession {
+    Mp4 {
+        handler: Arc<Session<RecordHandler>>,
+        service: Arc<RecordService>,
+    },
+    Mkv {
+        handler: Arc<Session<MkvRecordHandler>>,
+        service: Arc<MkvRecordService>,
+    },
+}
+
+impl RecordSession {
+    async fn run(&self) -> anyhow::Result<()> {
+        match self {
+            RecordSession::Mp4 { handler, .. } => handler.run().await,
+            RecordSession::Mkv { handler, .. } => handler.run().await,
+        }
+    }
+
+    fn stop(&self) {
+        match self {
+            RecordSession::Mp4 { handler, .. } => handler.stop(),
+            RecordSession::Mkv { handler, .. } => handler.stop(),
+        }
+    }
+
+    pub async fn files(&self) -> Vec<String> {
+        match self {
+            RecordSession::Mp4 { service, .. } => service.files().await,
+            RecordSession::Mkv { service, .. } => service.files().await,
+        }
+    }
+}
+
+impl RecordServer {
+    pub fn new(
+        hub: Arc<Hub>,
+        gop_cache: bool,
+        format: RecordFormat,
+        dir: String,
+        max_duration: Option<Duration>,
+        max_size: Option<u64>,
+    ) -> Arc<Self> {
+        Arc::new(Self {
+            hub,
+            gop_cache,
+            format,
+            dir,
+            max_duration,
+            max_size,
+            recordings: RwLock::new(HashMap::new()),
+        })
+    }
+
+    // stream 이 publish 될 때마다 녹화를 시작한다.
+    pub fn auto_start(self: &Arc<Self>) {
+        let server = self.clone();
+        let mut published = self.hub.subscribe_published();
+        tokio::spawn(async move {
+            loop {
+                let stream_id = match published.recv().await {
+                    Ok(stream_id) => stream_id,
+                    Err(RecvError::Lagged(_)) => continue,
+                    Err(RecvError::Closed) => break,
+                };
+                let server = server.clone();
+                tokio::spawn(async move {
+                    tokio::time::sleep(AUTO_START_DELAY).await;
+                    if let Err(err) = server.start(&stream_id, None).await {
+                        log::warn!("record auto start failed: {}, {}", stream_id, err);
+                    }
+                });
+            }
+        });
+    }
+
+    // format 이 None 이면 config 의 record.format 을 쓴다.
+    pub async fn start(
+        self: &Arc<Self>,
+        stream_id: &str,
+        format: Option<RecordFormat>,
+    ) -> anyhow::Result<()> {
+        // 파일 경로로 쓸 수 없는 stream 은 녹화하지 않는다.
+        sanitize_stream_id(stream_id)?;
+        if self.recordings.read().await.contains_key(stream_id) {
+            return Err(anyhow::anyhow!("already recording"));
+        }
+
+        let hub_stream = self
+            .hub
+            .get_stream(stream_id)
+            .await
+            .ok_or(anyhow::anyhow!("stream not found"))?;
+
+        let config = RecordConfig {
+            dir: self.dir.clone(),
+            stream_id: stream_id.to_string(),
+            max_duration: self.max_duration,
+            max_size: self.max_size,
+        };
+        let session = match format.unwrap_or(self.format) {
+            RecordFormat::Mp4 => {
+                let service = Arc::new(RecordService::new(config));
+                let handler =
+                    RecordHandler::new(&hub_stream, service.clone(), self.gop_cache).await?;
+                RecordSession::Mp4 {
+                    handler: Session::new(stream_id, handler),
+                    service,
+                }
+            }
+            RecordFormat::Mkv => {
+                let service = Arc::new(MkvRecordService::new(config));
+                let handler =
+                    MkvRecordHandler::new(&hub_stream, service.clone(), self.gop_cache).await?;
+                RecordSession::Mkv {
+                    handler: Session::new(stream_id, handler),
+                    service,
+                }
+            }
+        };
+        let session = Arc::new(session);
+        {
+            let mut recordings = self.recordings.write().await;
+            // codec 을 기다리는 동안 다른 요청이 먼저 시작했을 수 있다.
+            if recordings.contains_key(stream_id) {
+                return Err(anyhow::anyhow!("already recording"));
+            }
+            recordings.insert(stream_id.to_string(), session.clone());
+        }
+        log::info!("record started: {}", stream_id);
+
+        let server = self.clone();
+        let stream_id = stream_id.to_string();
+        tokio::spawn(async move {
+            if let Err(err) = session.run().await {
+                log::warn!("record session failed: {:?}", err);
+            }
+
+            let mut recordings = server.recordings.write().await;
+            if recordings
+                .get(&stream_id)
+                .is_some_and(|current| Arc::ptr_eq(current, &session))
+            {
+                recordings.remove(&stream_id);
+            }
+            log::info!(
+                "record stopped: {}, files: {:?}",
+                stream_id,
+                session.files().await
+            );
+        });
+
+        Ok(())
+    }
+
+    pub async fn get(&self, stream_id: &str) -> anyhow::Result<Arc<RecordSession>> {
+        self.recordings
+            .read()
+            .await
+            .get(stream_id)
+            .cloned()
+            .ok_or(anyhow::anyhow!("recording not found"))
+    }
+
+    // 지금 쓰고 있는 파일은 session 이 끝날 때 마무리된다.
+    pub async fn stop(&self, stream_id: &str) -> anyhow::Result<()> {
+        let session = self.get(stream_id).await?;
+        session.stop();
+        Ok(())
+    }
+}
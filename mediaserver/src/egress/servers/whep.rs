@@ -8,19 +8,30 @@ use uuid::Uuid;
 
 pub struct WhepServer {
     hub: Arc<Hub>,
+    gop_cache: bool,
 
-    sessions: RwLock<HashMap<String, Arc<Session<WhepHandler>>>>,
+    sessions: RwLock<HashMap<String, Arc<WhepSession>>>,
+}
+
+pub struct WhepSession {
+    pub session: Arc<Session<WhepHandler>>,
+    pub handler: Arc<WhepHandler>,
 }
 
 impl WhepServer {
-    pub fn new(hub: Arc<Hub>) -> Arc<Self> {
+    pub fn new(hub: Arc<Hub>, gop_cache: bool) -> Arc<Self> {
         Arc::new(WhepServer {
             hub,
+            gop_cache,
             sessions: RwLock::new(HashMap::new()),
         })
     }
 
-    pub async fn start_session(&self, stream_id: String, offer: &str) -> anyhow::Result<String> {
+    pub async fn start_session(
+        self: &Arc<Self>,
+        stream_id: String,
+        offer: &str,
+    ) -> anyhow::Result<(String, String)> {
         let hub_stream = self
             .hub
             .get_stream(&stream_id)
@@ -31,22 +42,50 @@ impl WhepServer {
 
         log::info!("whep session started: {}", &session_id);
 
-        let whep_handler = WhepHandler::new(&session_id, &hub_stream).await?;
+        let whep_handler = WhepHandler::new(&session_id, &hub_stream, self.gop_cache).await?;
         let answer = whep_handler.init(offer).await?;
         let sess = Session::from_arc(&session_id, whep_handler.clone());
 
-        self.sessions
-            .write()
-            .await
-            .insert(session_id.to_string(), sess.clone());
+        self.sessions.write().await.insert(
+            session_id.to_string(),
+            Arc::new(WhepSession {
+                session: sess.clone(),
+                handler: whep_handler,
+            }),
+        );
 
+        // peer connection 이 끊기거나 DELETE 로 종료되면 session 목록에서 제거한다.
+        let server = self.clone();
+        let session_id_ = session_id.clone();
         tokio::spawn(async move {
             if let Err(err) = sess.run().await {
                 log::warn!("write file failed: {:?}", err);
             }
-            println!("whep session end");
+            server.sessions.write().await.remove(&session_id_);
+            log::info!("whep session ended: {}", session_id_);
         });
 
-        Ok(answer)
+        Ok((session_id, answer))
+    }
+
+    pub async fn get_session(&self, session_id: &str) -> anyhow::Result<Arc<WhepSession>> {
+        self.sessions
+            .read()
+            .await
+            .get(session_id)
+            .cloned()
+            .ok_or(anyhow::anyhow!("session not found"))
+    }
+
+    pub async fn stop_session(&self, session_id: &str) -> anyhow::Result<()> {
+        let session = self
+            .sessions
+            .write()
+            .await
+            .remove(session_id)
+            .ok_or(anyhow::anyhow!("session not found"))?;
+        session.session.stop();
+        log::info!("whep session stopped: {}", session_id);
+        Ok(())
     }
 }
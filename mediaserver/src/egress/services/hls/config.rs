@@ -3,11 +3,38 @@ const VIDEO_M3U8: &str = "video.m3u8";
 const INIT_FILE_NAME: &str = "init.mp4";
 const OUTPUT_PREFIX: &str = "output";
 const PUBLIC: &str = "public";
+// segment_target 에서 keyframe 을 요청하고 받을 때까지 기다리는 시간. PLI 간격과 encoder 지연을 포함한다.
+const KEYFRAME_SLACK: f32 = 1.0;
+
+// segment 형식. Ts 는 LL-HLS part 없이 .ts segment 만 만든다.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum HlsFormat {
+    Fmp4,
+    Ts,
+}
+
+impl HlsFormat {
+    pub fn from(format: &str) -> anyhow::Result<Self> {
+        match format.to_lowercase().as_str() {
+            "fmp4" => Ok(HlsFormat::Fmp4),
+            "ts" => Ok(HlsFormat::Ts),
+            _ => Err(anyhow::anyhow!("unsupported hls format: {}", format)),
+        }
+    }
+
+    fn segment_extension(&self) -> &'static str {
+        match self {
+            HlsFormat::Fmp4 => "m4s",
+            HlsFormat::Ts => "ts",
+        }
+    }
+}
 
 #[derive(Debug, Clone)]
 pub struct HlsConfig {
     pub prefix: String,
     pub video_base: String,
+    pub format: HlsFormat,
 
     pub codecs: String,
     pub bandwidth: u64,
@@ -16,11 +43,14 @@ pub struct HlsConfig {
     pub framerate: f64,
     pub part_duration: f32,
     pub part_max_count: i32,
+    // 이 시간 동안의 segment 를 playlist 에 남겨서 되감아 볼 수 있게 한다. None 이면 live 구간만
+    pub dvr_window: Option<std::time::Duration>,
 }
 
 pub struct ConfigParams {
     pub session_id: String,
     pub video_base: String,
+    pub format: HlsFormat,
     pub codecs: String,
     pub bandwidth: u64,
     pub width: u64,
@@ -28,6 +58,8 @@ pub struct ConfigParams {
     pub framerate: f64,
     pub part_duration: f32,
     pub part_max_count: i32,
+    // 이 시간 동안의 segment 를 playlist 에 남겨서 되감아 볼 수 있게 한다. None 이면 live 구간만
+    pub dvr_window: Option<std::time::Duration>,
 }
 
 impl HlsConfig {
@@ -35,6 +67,7 @@ impl HlsConfig {
         Self {
             prefix: format!("{}/hls/{}", PUBLIC, params.session_id),
             video_base: params.video_base.clone(),
+            format: params.format,
             codecs: params.codecs,
             bandwidth: params.bandwidth,
             width: params.width,
@@ -42,9 +75,21 @@ impl HlsConfig {
             framerate: params.framerate,
             part_duration: params.part_duration,
             part_max_count: params.part_max_count,
+            dvr_window: params.dvr_window,
         }
     }
 
+    // 이 길이를 채운 뒤의 keyframe 에서 segment 를 자른다.
+    pub fn segment_target(&self) -> f32 {
+        self.part_duration * self.part_max_count as f32
+    }
+
+    // EXT-X-TARGETDURATION. live 중에는 바꿀 수 없으므로(RFC 8216 6.2.1) 처음에 정하고,
+    // keyframe 이 늦으면 segment 를 이 길이에서 자른다.
+    pub fn target_duration(&self) -> u64 {
+        (self.segment_target() + KEYFRAME_SLACK).ceil() as u64
+    }
+
     pub fn base_path(&self, filename: &str) -> String {
         format!("{}/{}", self.prefix, filename)
     }
@@ -63,7 +108,7 @@ impl HlsConfig {
         if filename.ends_with(INIT_FILE_NAME) {
             return Ok(self.base_path(filename));
         }
-        if filename.ends_with(".mp4") || filename.ends_with(".m4s") {
+        if filename.ends_with(".mp4") || filename.ends_with(".m4s") || filename.ends_with(".ts") {
             return Ok(self.base_path(filename));
         }
         return Err(anyhow::anyhow!("Bad request"));
@@ -84,10 +129,41 @@ impl HlsConfig {
             self.prefix, &self.video_base, OUTPUT_PREFIX, segment_index, part_index,
         ))
     }
+    // output_{segment}_{part}.m4s 에서 segment, part index 를 읽는다.
+    pub fn parse_part_filename(&self, filename: &str) -> Option<(i32, i32)> {
+        let name = filename.rsplit('/').next()?;
+        let indexes = name
+            .strip_prefix(OUTPUT_PREFIX)?
+            .strip_prefix('_')?
+            .strip_suffix(".m4s")?;
+        let (segment_index, part_index) = indexes.split_once('_')?;
+        Some((segment_index.parse().ok()?, part_index.parse().ok()?))
+    }
+    // DASH SegmentTemplate 의 media. $Number$ 가 segment index 이다.
+    pub fn segment_template(&self) -> String {
+        format!(
+            "{}_$Number$.{}",
+            OUTPUT_PREFIX,
+            self.format.segment_extension()
+        )
+    }
+    // output_{segment}.m4s 에서 segment index 를 읽는다.
+    pub fn parse_segment_filename(&self, filename: &str) -> Option<i32> {
+        let name = filename.rsplit('/').next()?;
+        name.strip_prefix(OUTPUT_PREFIX)?
+            .strip_prefix('_')?
+            .strip_suffix(".m4s")?
+            .parse()
+            .ok()
+    }
     pub fn make_segment_path(&self, segment_index: i32) -> std::path::PathBuf {
         std::path::PathBuf::from(format!(
-            "{}/{}/{}_{}.m4s",
-            self.prefix, &self.video_base, OUTPUT_PREFIX, segment_index,
+            "{}/{}/{}_{}.{}",
+            self.prefix,
+            &self.video_base,
+            OUTPUT_PREFIX,
+            segment_index,
+            self.format.segment_extension(),
         ))
     }
 }
@@ -126,3 +202,45 @@ impl PathBufExt for std::path::PathBuf {
             .ok_or_else(|| anyhow::anyhow!("Failed to retrieve parent directory"))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_filename() {
+        let config = HlsConfig::new(ConfigParams {
+            session_id: "test".to_string(),
+            video_base: "video0".to_string(),
+            format: HlsFormat::Fmp4,
+            codecs: "avc1.42e01f".to_string(),
+            bandwidth: 1000000,
+            width: 640,
+            height: 480,
+            framerate: 30.0,
+            part_duration: 0.2,
+            part_max_count: 2,
+            dvr_window: None,
+        });
+        assert_eq!(config.parse_part_filename("output_3_1.m4s"), Some((3, 1)));
+        assert_eq!(
+            config.parse_part_filename("video0/output_12_0.m4s"),
+            Some((12, 0))
+        );
+        assert_eq!(config.parse_part_filename("output_3.m4s"), None);
+        assert_eq!(config.parse_part_filename("output_a_1.m4s"), None);
+        assert_eq!(config.parse_part_filename("output_3_1.ts"), None);
+        assert_eq!(config.parse_part_filename("init.mp4"), None);
+
+        assert_eq!(
+            config.parse_segment_filename("video0/output_3.m4s"),
+            Some(3)
+        );
+        assert_eq!(config.parse_segment_filename("output_3_1.m4s"), None);
+
+        // make_part_path 로 만든 이름을 다시 읽을 수 있다.
+        let path = config.make_part_path(7, 2);
+        let filename = path.to_string_lossy();
+        assert_eq!(config.parse_part_filename(&filename), Some((7, 2)));
+    }
+}
@@ -1,2 +1,3 @@
 pub mod config;
 pub mod service;
+pub mod store;
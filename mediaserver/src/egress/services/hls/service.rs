@@ -1,55 +1,127 @@
+use bytes::{Bytes, BytesMut};
+use chrono::{DateTime, Utc};
 use m3u8_rs::{MasterPlaylist, MediaPlaylist};
-use tokio::{
-    io::{AsyncReadExt, AsyncWriteExt},
-    sync::RwLock,
-};
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use tokio::{sync::RwLock, time};
 
-use crate::utils;
+use crate::protocols::dash::mpd::{Mpd, MpdSegment, TIMESCALE};
+use crate::protocols::hls::{skip_segments, write_playlist, RenditionReport};
 
-use super::config::{HlsConfig, PathBufExt};
+use super::config::{HlsConfig, HlsFormat, PathBufExt};
+use super::store::SegmentStore;
 
 const INIT_FILE_NAME: &str = "init.mp4";
+// 마지막 part 보다 이만큼 넘게 앞선 blocking 요청은 400 으로 응답한다.
+const ADVANCE_PART_LIMIT: i32 = 3;
+// CAN-SKIP-UNTIL 은 TARGETDURATION 의 6배 이상이어야 한다.
+const SKIP_TARGET_DURATION_MULTIPLE: f32 = 6.0;
 
 pub struct HlsPayload {
     pub duration: f32,
-    pub payload: bytes::Bytes,
+    // part 가 IDR 로 시작하는지
+    pub independent: bool,
+    // part 의 첫 sample 의 media time(초). DASH 의 SegmentTimeline 에 쓴다.
+    pub media_start: f64,
+    pub payload: Bytes,
+}
+
+// 같은 fmp4 segment 를 DASH 로 보내기 위한 segment 의 media time
+#[derive(Default)]
+struct DashTimeline {
+    // 첫 segment 의 PROGRAM-DATE-TIME. MPD 의 availabilityStartTime
+    availability_start: Option<DateTime<Utc>>,
+    // init segment 가 바뀌면 Period 를 새로 시작한다.
+    period: Option<DashPeriod>,
+    // playlist 에 남아 있는 segment 의 (번호, media time)
+    segment_starts: VecDeque<(u64, f64)>,
+    // 만들고 있는 segment 의 첫 part 의 media time
+    pending_start: Option<f64>,
+}
+
+struct DashPeriod {
+    first_number: u64,
+    // availabilityStartTime 부터 Period 가 시작하기까지(초)
+    start: f64,
+    // Period 의 첫 segment 의 media time. presentationTimeOffset 이 된다.
+    media_start: f64,
+    initialization: String,
+}
+
+impl DashTimeline {
+    fn push_segment(
+        &mut self,
+        number: u64,
+        program_date_time: DateTime<Utc>,
+        initialization: Option<String>,
+    ) {
+        let Some(media_start) = self.pending_start.take() else {
+            return;
+        };
+        let availability_start = *self.availability_start.get_or_insert(program_date_time);
+        let initialization = match (initialization, &self.period) {
+            (Some(initialization), _) => Some(initialization),
+            (None, None) => Some(INIT_FILE_NAME.to_string()),
+            (None, Some(_)) => None,
+        };
+        if let Some(initialization) = initialization {
+            self.period = Some(DashPeriod {
+                first_number: number,
+                start: (program_date_time - availability_start).num_milliseconds() as f64 / 1000.0,
+                media_start,
+                initialization,
+            });
+        }
+        self.segment_starts.push_back((number, media_start));
+    }
 }
 
 pub struct HlsService {
     pub config: HlsConfig,
+    store: Arc<dyn SegmentStore>,
 
     // m3u8, playlist, video
     master: RwLock<MasterPlaylist>,
     video0: RwLock<MediaPlaylist>,
-
+    // 다른 rendition 들의 마지막 segment/part 위치
+    rendition_reports: RwLock<Vec<RenditionReport>>,
+    // 지금 만들고 있는 segment 의 (길이, part). segment 가 끝나면 이어붙인다.
+    pending_parts: RwLock<Vec<(f32, Bytes)>>,
+    dash_timeline: RwLock<DashTimeline>,
+    // codec 이 바뀌어서 다음 segment 부터 쓸 init segment. TS 는 init segment 가 없다.
+    pending_map: RwLock<Option<m3u8_rs::Map>>,
+    // 다음 segment 에 EXT-X-DISCONTINUITY 를 붙여야 하는지
+    pending_discontinuity: AtomicBool,
+    init_generation: RwLock<u32>,
+    // EXT-X-ENDLIST 를 쓴 뒤에는 VOD 로 남는다.
+    finished: AtomicBool,
+    // close 한 뒤에는 store 에 더 쓰지 않는다.
+    closed: AtomicBool,
+
+    // 마지막으로 만들어진 (segment, part). segment 가 끝나면 (segment + 1, -1)
     created_signal: tokio::sync::watch::Sender<(i32, i32)>,
 }
 
 impl HlsService {
-    pub fn new(config: HlsConfig) -> Self {
+    pub fn new(config: HlsConfig, store: Arc<dyn SegmentStore>) -> Self {
+        // TS 는 LL-HLS 를 지원하지 않는 player 를 위한 것이므로 예전 version 으로 쓴다.
+        let low_latency = config.format == HlsFormat::Fmp4;
+        let version = if low_latency { 10 } else { 3 };
         let mut master = MasterPlaylist::default();
-        master.version = Some(10);
+        master.version = Some(version);
         master.independent_segments = true;
+        master.variants.push(variant_stream(&config));
 
-        let playlist_path = config.video_m3u8_path();
-        let mut varient = m3u8_rs::VariantStream::default();
-        varient.bandwidth = config.bandwidth;
-        varient.codecs = Some(config.codecs.to_string());
-        varient.resolution = Some(m3u8_rs::Resolution {
-            width: config.width,
-            height: config.height,
-        });
-        varient.frame_rate = Some(config.framerate);
-        varient.uri = playlist_path;
-        master.variants.push(varient);
-
+        let target_duration = config.target_duration();
         let playlist = MediaPlaylist {
-            version: Some(10),
-            target_duration: (1) as u64,
-            server_control: Some(m3u8_rs::ServerControl {
+            version: Some(version),
+            target_duration,
+            server_control: low_latency.then(|| m3u8_rs::ServerControl {
                 can_block_reload: true,
-                part_hold_back: Some(config.part_duration),
-                ..Default::default()
+                // PART-HOLD-BACK 은 PART-TARGET 의 3배 이상을 권장한다.
+                part_hold_back: Some(config.part_duration * 3.0),
+                can_skip_util: Some(target_duration as f32 * SKIP_TARGET_DURATION_MULTIPLE),
             }),
             media_sequence: 0,
             discontinuity_sequence: 0,
@@ -60,22 +132,42 @@ impl HlsService {
         };
 
         let mut video0 = playlist.clone();
-        video0.part_inf = Some(config.part_duration);
-        video0.map = Some(m3u8_rs::Map {
-            uri: INIT_FILE_NAME.to_string(),
-            ..Default::default()
-        });
+        if low_latency {
+            video0.part_inf = Some(config.part_duration);
+            video0.map = Some(m3u8_rs::Map {
+                uri: INIT_FILE_NAME.to_string(),
+                ..Default::default()
+            });
+        }
 
         let (created_signal, _) = tokio::sync::watch::channel((-1, -1));
 
         Self {
             config: config.clone(),
+            store,
             video0: RwLock::new(video0),
             master: RwLock::new(master),
+            rendition_reports: RwLock::new(vec![]),
+            pending_parts: RwLock::new(vec![]),
+            dash_timeline: RwLock::new(DashTimeline::default()),
+            pending_map: RwLock::new(None),
+            pending_discontinuity: AtomicBool::new(false),
+            init_generation: RwLock::new(0),
+            finished: AtomicBool::new(false),
+            closed: AtomicBool::new(false),
             created_signal,
         }
     }
 
+    // simulcast 의 다른 layer 를 master playlist 에 추가한다. init 전에 불러야 한다.
+    pub async fn add_variant(&self, config: &HlsConfig) {
+        self.master
+            .write()
+            .await
+            .variants
+            .push(variant_stream(config));
+    }
+
     pub async fn init(&self) -> anyhow::Result<()> {
         let mut buffer = Vec::new();
         let master = self.master.write().await;
@@ -83,67 +175,115 @@ impl HlsService {
             log::warn!("failed to write playlist: {}", err);
         }
 
-        utils::files::files::write_file_force(&self.config.get_master_path(), &buffer).await?;
+        self.store
+            .put(&self.config.get_master_path(), Bytes::from(buffer))
+            .await?;
 
         Ok(())
     }
 
-    pub async fn init_segment(&self, payload: bytes::Bytes) -> anyhow::Result<()> {
+    pub async fn init_segment(&self, payload: Bytes) -> anyhow::Result<()> {
         let fullpath = self.config.get_init_video_path();
-        utils::files::files::write_file_force(&fullpath, &payload).await?;
+        self.store.put(&fullpath, payload).await?;
 
         Ok(())
     }
 
-    pub async fn write_segment(&self, index: i32, hls_payload: HlsPayload) -> anyhow::Result<()> {
-        let segment_index = index / self.config.part_max_count;
-        let part_index = index % self.config.part_max_count;
-
+    // part 를 추가한다. end_segment 이면 지금까지의 part 들을 하나의 segment 로 묶는다.
+    // TS 는 part 를 playlist 에 올리지 않고 segment 로만 묶는다.
+    pub async fn write_part(
+        &self,
+        hls_payload: HlsPayload,
+        end_segment: bool,
+    ) -> anyhow::Result<()> {
+        if self.closed.load(Ordering::Acquire) {
+            return Ok(());
+        }
         let mut video0 = self.video0.write().await;
-        let part = self.config.make_part_path(segment_index, part_index);
-        // part video 쓰기
-        let fullpath = part.get_fullpath()?;
-        utils::files::files::write_file_force(&fullpath, &hls_payload.payload).await?;
-
-        video0.parts.push(m3u8_rs::Part {
-            duration: hls_payload.duration,
-            uri: part.get_filename()?,
-            independent: true,
-        });
-        if part_index == self.config.part_max_count - 1 {
+        let (segment_index, part_index) = next_part(&video0);
+        let low_latency = self.config.format == HlsFormat::Fmp4;
+
+        if low_latency {
+            let part = self.config.make_part_path(segment_index, part_index);
+            // part video 쓰기
+            let fullpath = part.get_fullpath()?;
+            self.store
+                .put(&fullpath, hls_payload.payload.clone())
+                .await?;
+
+            video0.parts.push(m3u8_rs::Part {
+                duration: hls_payload.duration,
+                uri: part.get_filename()?,
+                independent: hls_payload.independent,
+            });
+        }
+        self.dash_timeline
+            .write()
+            .await
+            .pending_start
+            .get_or_insert(hls_payload.media_start);
+        self.pending_parts
+            .write()
+            .await
+            .push((hls_payload.duration, hls_payload.payload));
+
+        if end_segment {
             // need media segment
             let segment = self.config.make_segment_path(segment_index);
-            let mut paths: Vec<std::path::PathBuf> = Vec::new();
-            for i in 0..self.config.part_max_count {
-                let filepath = self.config.make_part_path(segment_index, i);
-                paths.push(filepath);
-            }
-            let fullpath = segment.get_fullpath()?;
-            let paths: Vec<&std::path::Path> = paths.iter().map(|p| p.as_path()).collect();
-            append_files(std::path::Path::new(&fullpath), &paths).await?;
-
-            if video0.segments.len() > self.config.part_max_count as usize {
-                video0.segments.remove(0);
-                video0.media_sequence += 1;
+            let mut buffer = BytesMut::new();
+            let mut segment_duration: f32 = 0.0;
+            for (duration, part_payload) in self.pending_parts.write().await.drain(..) {
+                segment_duration += duration;
+                buffer.extend_from_slice(&part_payload);
             }
+            self.store
+                .put(&segment.get_fullpath()?, buffer.freeze())
+                .await?;
 
-            let segment_duration: f32 = video0.parts.iter().map(|part| part.duration).sum();
             let parts_clone = video0.parts.clone();
             let title = segment_index.to_string();
+            let map = self.pending_map.write().await.take();
+            let discontinuity = self.pending_discontinuity.swap(false, Ordering::AcqRel);
+            // 이전 segment 에 이어지는 시각. 첫 segment 는 지금 시각에서 길이만큼 뺀다.
+            let program_date_time = match video0.segments.last() {
+                Some(last) if !discontinuity => last.program_date_time.map(|time| {
+                    time + chrono::Duration::milliseconds((last.duration * 1000.0) as i64)
+                }),
+                _ => None,
+            }
+            .unwrap_or_else(|| {
+                (chrono::Utc::now()
+                    - chrono::Duration::milliseconds((segment_duration * 1000.0) as i64))
+                .into()
+            });
+            self.dash_timeline.write().await.push_segment(
+                segment_index as u64,
+                program_date_time.with_timezone(&Utc),
+                map.as_ref().map(|map| map.uri.clone()),
+            );
             video0.segments.push(m3u8_rs::MediaSegment {
                 uri: segment.get_filename()?,
                 duration: segment_duration,
                 title: Some(title),
                 parts: parts_clone,
+                discontinuity,
+                map,
+                program_date_time: Some(program_date_time),
                 ..Default::default()
             });
             video0.parts = vec![];
-
-            let prepload = self.config.make_part_path(segment_index + 1, 0);
-            video0.preload_hint = Some(m3u8_rs::PreloadHint {
-                r#type: "PART".to_string(),
-                uri: prepload.get_filename()?,
-            });
+            self.trim_segments(&mut video0).await;
+
+            if low_latency {
+                let prepload = self.config.make_part_path(segment_index + 1, 0);
+                video0.preload_hint = Some(m3u8_rs::PreloadHint {
+                    r#type: "PART".to_string(),
+                    uri: prepload.get_filename()?,
+                });
+            }
+        } else if !low_latency {
+            // TS 는 segment 가 끝날 때만 playlist 가 바뀐다.
+            return Ok(());
         } else {
             // need media part
             let preload = self.config.make_part_path(segment_index, part_index + 1);
@@ -155,41 +295,541 @@ impl HlsService {
 
         {
             // llhls playlist 쓰기
-            let mut buffer = Vec::new();
-            if let Err(err) = video0.write_to(&mut buffer) {
-                log::warn!("failed to write playlist: {}", err);
-            }
+            let reports = self.rendition_reports.read().await;
+            let buffer = write_playlist(&video0, 0, &reports)?;
             let playlist_path = self.config.get_playlist_path();
-            utils::files::files::write_file_force(&playlist_path, &buffer).await?;
+            self.store.put(&playlist_path, Bytes::from(buffer)).await?;
         }
 
-        let _ = self.created_signal.send((segment_index, part_index));
+        if end_segment {
+            let _ = self.created_signal.send((segment_index + 1, -1));
+        } else {
+            let _ = self.created_signal.send((segment_index, part_index));
+        }
         Ok(())
     }
 
-    pub fn subscribe_signal(&self) -> tokio::sync::watch::Receiver<(i32, i32)> {
+    // live edge 근처의 segment 에만 part 를 남기고, 오래된 segment 는 DVR window(없으면 개수)를 넘으면 지운다.
+    async fn trim_segments(&self, video0: &mut MediaPlaylist) {
+        let live_count = self.config.part_max_count as usize + 1;
+        let live_start = video0.segments.len().saturating_sub(live_count);
+        for segment in video0.segments[..live_start].iter_mut() {
+            for part in std::mem::take(&mut segment.parts) {
+                self.evict(&part.uri).await;
+            }
+        }
+
+        let dvr_start = self.config.dvr_window.and_then(|window| {
+            chrono::Duration::from_std(window)
+                .ok()
+                .map(|window| chrono::Utc::now() - window)
+        });
+        while video0.segments.len() > live_count {
+            let expired = match dvr_start {
+                Some(dvr_start) => video0.segments[0]
+                    .program_date_time
+                    .is_some_and(|time| time < dvr_start),
+                None => true,
+            };
+            if !expired {
+                break;
+            }
+
+            let expired = video0.segments.remove(0);
+            video0.media_sequence += 1;
+            if expired.discontinuity {
+                video0.discontinuity_sequence += 1;
+            }
+            // 새 init segment 를 쓰는 segment 가 맨 앞이 되면 playlist 의 EXT-X-MAP 을 바꾼다.
+            let map = video0
+                .segments
+                .first_mut()
+                .and_then(|segment| segment.map.take());
+            if let Some(map) = map {
+                if let Some(old) = video0.map.replace(map) {
+                    self.evict(&old.uri).await;
+                }
+            }
+            self.evict_segment(&expired).await;
+        }
+        self.dash_timeline
+            .write()
+            .await
+            .segment_starts
+            .retain(|(number, _)| *number >= video0.media_sequence);
+    }
+
+    // codec 이 바뀌면 새 이름으로 init segment 를 쓰고 다음 segment 부터 쓰도록 한다.
+    pub async fn change_init_segment(&self, payload: Bytes) -> anyhow::Result<()> {
+        let generation = {
+            let mut generation = self.init_generation.write().await;
+            *generation += 1;
+            *generation
+        };
+        let uri = format!("init_{}.mp4", generation);
+        self.store.put(&self.video_path(&uri), payload).await?;
+        *self.pending_map.write().await = Some(m3u8_rs::Map {
+            uri,
+            ..Default::default()
+        });
+        self.mark_discontinuity();
+        Ok(())
+    }
+
+    // 다음 segment 에 EXT-X-DISCONTINUITY 를 붙인다. init segment 가 없는 TS 에서 codec 이 바뀌면 쓴다.
+    pub fn mark_discontinuity(&self) {
+        self.pending_discontinuity.store(true, Ordering::Release);
+    }
+
+    // playlist 에서 빠진 segment 와 그 part 들을 store 에서 지운다.
+    async fn evict_segment(&self, segment: &m3u8_rs::MediaSegment) {
+        self.evict(&segment.uri).await;
+        for part in segment.parts.iter() {
+            self.evict(&part.uri).await;
+        }
+    }
+
+    async fn evict(&self, uri: &str) {
+        let path = self.video_path(uri);
+        if let Err(err) = self.store.remove(&path).await {
+            log::warn!("failed to evict {}: {}", path, err);
+        }
+    }
+
+    // playlist 의 uri 에 해당하는 store 의 path
+    fn video_path(&self, uri: &str) -> String {
+        self.config
+            .base_path(&format!("{}/{}", self.config.video_base, uri))
+    }
+
+    // filename 은 session 아래의 상대 경로. ex) video0/output_1_0.m4s
+    pub async fn read(&self, filename: &str) -> anyhow::Result<Option<Bytes>> {
+        let path = self.config.get_path(filename)?;
+        self.store.get(&path).await
+    }
+
+    // playlist 를 EXT-X-PLAYLIST-TYPE:VOD, EXT-X-ENDLIST 로 닫는다. 이후로는 바뀌지 않는다.
+    pub async fn finish(&self) -> anyhow::Result<()> {
+        if self.closed.load(Ordering::Acquire) {
+            return Ok(());
+        }
+        let mut video0 = self.video0.write().await;
+        video0.end_list = true;
+        video0.playlist_type = Some(m3u8_rs::MediaPlaylistType::Vod);
+        video0.preload_hint = None;
+        video0.parts = vec![];
+
+        let reports = self.rendition_reports.read().await;
+        let buffer = write_playlist(&video0, 0, &reports)?;
+        self.store
+            .put(&self.config.get_playlist_path(), Bytes::from(buffer))
+            .await?;
+        self.finished.store(true, Ordering::Release);
+
+        // blocking 요청은 더 기다리지 않고 마지막 playlist 를 받는다.
+        let _ = self.created_signal.send((i32::MAX, i32::MAX));
+        Ok(())
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.finished.load(Ordering::Acquire)
+    }
+
+    // session 이 끝나면 store 에 남은 파일을 모두 지운다.
+    pub async fn close(&self) -> anyhow::Result<()> {
+        self.closed.store(true, Ordering::Release);
+        self.store.remove_all(&self.config.prefix).await
+    }
+
+    // _HLS_skip 요청에 대한 delta playlist. CAN-SKIP-UNTIL 보다 오래된 segment 를 EXT-X-SKIP 으로 대신한다.
+    pub async fn delta_playlist(&self) -> anyhow::Result<Vec<u8>> {
+        let video0 = self.video0.read().await;
+        let reports = self.rendition_reports.read().await;
+        // CAN-SKIP-UNTIL 을 알려주지 않은 playlist(TS) 는 그대로 준다.
+        let Some(can_skip_until) = video0
+            .server_control
+            .as_ref()
+            .and_then(|server_control| server_control.can_skip_util)
+        else {
+            return write_playlist(&video0, 0, &reports);
+        };
+        let (delta, skipped) = skip_segments(&video0, can_skip_until);
+        // delta playlist 를 만들지 못하면 전체 playlist 를 준다.
+        write_playlist(&delta, skipped, &reports).or_else(|err| {
+            log::warn!("failed to write delta playlist: {}", err);
+            write_playlist(&video0, 0, &reports)
+        })
+    }
+
+    // playlist 와 같은 segment 를 SegmentTemplate($Number$ 가 segment index)으로 알려주는 MPD.
+    // segment 가 다 만들어지기 전부터 part 단위로 받을 수 있도록 availabilityTimeOffset 을 준다.
+    pub async fn dash_manifest(&self) -> anyhow::Result<String> {
+        let video0 = self.video0.read().await;
+        let timeline = self.dash_timeline.read().await;
+        let (Some(availability_start), Some(period)) =
+            (timeline.availability_start, timeline.period.as_ref())
+        else {
+            return Err(anyhow::anyhow!("no segment yet"));
+        };
+
+        let to_ticks = |seconds: f64| (seconds * TIMESCALE as f64).round() as u64;
+        let mut segments = vec![];
+        for (i, segment) in video0.segments.iter().enumerate() {
+            let number = video0.media_sequence + i as u64;
+            if number < period.first_number {
+                continue;
+            }
+            let Some((_, start)) = timeline.segment_starts.iter().find(|(n, _)| *n == number)
+            else {
+                continue;
+            };
+            let t = to_ticks(*start);
+            segments.push(MpdSegment {
+                number,
+                t,
+                d: to_ticks(start + segment.duration as f64).saturating_sub(t),
+            });
+        }
+
+        let segment_target = self.config.segment_target() as f64;
+        let part_duration = self.config.part_duration as f64;
+        let availability_time_offset = availability_time_offset(&video0);
+        let time_shift_buffer_depth = video0
+            .segments
+            .iter()
+            .map(|segment| segment.duration as f64)
+            .sum::<f64>();
+        let video = self.config.width > 0 && self.config.height > 0;
+        // DASH 에서는 Opus 의 codecs 가 소문자다.
+        let codecs = self
+            .config
+            .codecs
+            .split(',')
+            .map(|codec| {
+                if codec.eq_ignore_ascii_case("opus") {
+                    "opus"
+                } else {
+                    codec
+                }
+            })
+            .collect::<Vec<_>>()
+            .join(",");
+        let content_types = self
+            .config
+            .codecs
+            .split(',')
+            .map(|codec| {
+                let codec = codec.to_ascii_lowercase();
+                if codec == "opus" || codec.starts_with("mp4a") {
+                    "audio"
+                } else {
+                    "video"
+                }
+                .to_string()
+            })
+            .collect::<Vec<_>>();
+
+        let mpd = Mpd {
+            dynamic: !self.is_finished(),
+            availability_start_time: availability_start,
+            publish_time: Utc::now(),
+            minimum_update_period: segment_target,
+            min_buffer_time: part_duration,
+            // LL-HLS 의 PART-HOLD-BACK 과 같은 latency 를 목표로 한다.
+            suggested_presentation_delay: part_duration * 3.0,
+            time_shift_buffer_depth,
+            max_segment_duration: video0.target_duration as f64,
+            availability_time_offset,
+            target_latency: Some(part_duration * 3.0),
+            mime_type: if video { "video/mp4" } else { "audio/mp4" }.to_string(),
+            codecs,
+            bandwidth: self.config.bandwidth,
+            width: self.config.width,
+            height: self.config.height,
+            frame_rate: self.config.framerate,
+            content_types,
+            period_id: period.first_number,
+            period_start: period.start,
+            media: format!(
+                "{}/{}",
+                self.config.video_base,
+                self.config.segment_template()
+            ),
+            initialization: format!("{}/{}", self.config.video_base, period.initialization),
+            presentation_time_offset: to_ticks(period.media_start),
+            segments,
+        };
+        Ok(mpd.write())
+    }
+
+    // 만들고 있는 segment 를 part 가 만들어지는 대로 읽는다. chunked transfer 로 보내는 LL-DASH 용
+    // 이미 끝난 segment 이거나 더 만들 part 가 없으면 None
+    pub async fn read_part(&self, segment_index: i32, part_index: i32) -> Option<Bytes> {
+        if !self.wait_for(segment_index, Some(part_index)).await {
+            return None;
+        }
+        let (next_segment, _) = next_part(&*self.video0.read().await);
+        let part = self.config.make_part_path(segment_index, part_index);
+        // segment 가 part_index 전에 끝났으면 더 읽을 part 가 없다.
+        if segment_index < next_segment {
+            let video0 = self.video0.read().await;
+            let index = segment_index as u64;
+            let parts = index
+                .checked_sub(video0.media_sequence)
+                .and_then(|i| video0.segments.get(i as usize))
+                .map_or(0, |segment| segment.parts.len());
+            if part_index as usize >= parts {
+                return None;
+            }
+        }
+        self.store.get(&part.get_fullpath().ok()?).await.ok()?
+    }
+
+    // 아직 만들고 있는 segment 인지
+    pub async fn is_producing(&self, segment_index: i32) -> bool {
+        let (next_segment, _) = next_part(&*self.video0.read().await);
+        !self.is_finished() && segment_index == next_segment
+    }
+
+    // 다른 rendition 의 playlist 에 넣을 이 rendition 의 마지막 위치
+    pub async fn rendition_report(&self) -> RenditionReport {
+        let video0 = self.video0.read().await;
+        let (next_segment, next_part) = next_part(&video0);
+        let (last_msn, last_part) = if next_part > 0 {
+            (next_segment, Some(next_part - 1))
+        } else {
+            // segment 가 막 끝났으면 그 segment 의 마지막 part
+            let last_part = video0
+                .segments
+                .last()
+                .and_then(|segment| segment.parts.len().checked_sub(1))
+                .map(|last_part| last_part as i32);
+            (next_segment - 1, last_part)
+        };
+        RenditionReport {
+            uri: format!("../{}", self.config.video_m3u8_path()),
+            last_msn: last_msn.max(0) as u64,
+            last_part,
+        }
+    }
+
+    pub async fn set_rendition_reports(&self, reports: Vec<RenditionReport>) {
+        *self.rendition_reports.write().await = reports;
+    }
+
+    // playlist 가 바뀔 때마다 마지막으로 만들어진 (segment, part) 를 받는다.
+    pub fn subscribe_created(&self) -> tokio::sync::watch::Receiver<(i32, i32)> {
         self.created_signal.subscribe()
     }
+
+    // 마지막 segment 보다 2개 넘게, 또는 마지막 part 보다 ADVANCE_PART_LIMIT 넘게 앞선 요청인지
+    pub async fn is_too_far(&self, segment_index: i32, part_index: Option<i32>) -> bool {
+        let (next_segment, next_part) = next_part(&*self.video0.read().await);
+        if segment_index > next_segment + 1 {
+            return true;
+        }
+        let Some(part_index) = part_index else {
+            return false;
+        };
+        if segment_index == next_segment {
+            part_index - (next_part - 1) > ADVANCE_PART_LIMIT
+        } else if segment_index > next_segment {
+            part_index + 1 + next_part > ADVANCE_PART_LIMIT
+        } else {
+            false
+        }
+    }
+
+    // 요청한 segment(part 가 있으면 part)가 만들어질 때까지 기다린다. TARGETDURATION 의 3배가 지나면 false
+    pub async fn wait_for(&self, segment_index: i32, part_index: Option<i32>) -> bool {
+        let target = match part_index {
+            Some(part_index) => (segment_index, part_index),
+            None => (segment_index + 1, -1),
+        };
+        let timeout = time::Duration::from_secs(self.video0.read().await.target_duration * 3);
+        let mut rx = self.created_signal.subscribe();
+        match time::timeout(timeout, rx.wait_for(|created| *created >= target)).await {
+            Ok(result) => result.is_ok(),
+            Err(_) => false,
+        }
+    }
+
+    // 이미 만들어진 part 이거나 preload hint 로 알려준 part 이면 만들어질 때까지 기다린다.
+    pub async fn wait_for_part(&self, segment_index: i32, part_index: i32) -> bool {
+        if *self.created_signal.borrow() >= (segment_index, part_index) {
+            return true;
+        }
+        let hint = next_part(&*self.video0.read().await);
+        if (segment_index, part_index) != hint {
+            return false;
+        }
+        self.wait_for(segment_index, Some(part_index)).await
+    }
 }
 
-async fn append_files(
-    output: &std::path::Path,
-    inputs: &[&std::path::Path],
-) -> tokio::io::Result<()> {
-    // 출력 파일을 비동기로 열고, 없으면 생성하고 기존 내용을 지웁니다.
-    let mut output_file = tokio::fs::File::create(output).await?;
+// master playlist 의 EXT-X-STREAM-INF
+fn variant_stream(config: &HlsConfig) -> m3u8_rs::VariantStream {
+    let mut varient = m3u8_rs::VariantStream::default();
+    varient.bandwidth = config.bandwidth;
+    varient.codecs = Some(config.codecs.to_string());
+    // audio only 이면 RESOLUTION, FRAME-RATE 를 쓰지 않는다.
+    if config.width > 0 && config.height > 0 {
+        varient.resolution = Some(m3u8_rs::Resolution {
+            width: config.width,
+            height: config.height,
+        });
+        varient.frame_rate = Some(config.framerate);
+    }
+    varient.uri = config.video_m3u8_path();
+    varient
+}
 
-    for input_path in inputs {
-        // 입력 파일을 비동기로 열고 모든 내용을 읽어들입니다.
-        let mut input_file = tokio::fs::File::open(input_path).await?;
-        let mut buffer = Vec::new();
+// 다음에 만들어질 (segment, part). preload hint 의 위치와 같다.
+fn next_part(playlist: &MediaPlaylist) -> (i32, i32) {
+    let segment_index = (playlist.media_sequence + playlist.segments.len() as u64) as i32;
+    (segment_index, playlist.parts.len() as i32)
+}
 
-        // 파일 내용을 모두 비동기로 읽어서 버퍼에 저장합니다.
-        input_file.read_to_end(&mut buffer).await?;
+// segment 는 첫 part 가 만들어진 뒤부터 받을 수 있다. segment 가 끝나기 (segment 길이 - 첫 part 길이) 전.
+// keyframe 에서 잘리므로 설정값이 아닌 실제 길이로, 남아있는 segment 중 가장 작은 값을 쓴다.
+// part 가 없으면(mpeg-ts) chunked transfer 로 받을 수 없으니 0
+fn availability_time_offset(playlist: &MediaPlaylist) -> f64 {
+    playlist
+        .segments
+        .iter()
+        .filter_map(|segment| {
+            let first = segment.parts.first()?;
+            Some((segment.duration - first.duration).max(0.0) as f64)
+        })
+        .min_by(|a, b| a.total_cmp(b))
+        .map_or(0.0, |offset| (offset * 1000.0).round() / 1000.0)
+}
 
-        // 출력 파일에 버퍼 내용을 비동기로 씁니다.
-        output_file.write_all(&buffer).await?;
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::egress::services::hls::config::ConfigParams;
+    use crate::egress::services::hls::store::MemoryStore;
+
+    fn new_service() -> Arc<HlsService> {
+        let config = HlsConfig::new(ConfigParams {
+            session_id: "test".to_string(),
+            video_base: "video0".to_string(),
+            format: HlsFormat::Fmp4,
+            codecs: "avc1.42e01f".to_string(),
+            bandwidth: 1000000,
+            width: 640,
+            height: 480,
+            framerate: 30.0,
+            part_duration: 0.2,
+            part_max_count: 2,
+            dvr_window: None,
+        });
+        Arc::new(HlsService::new(config, MemoryStore::new()))
     }
 
-    Ok(())
+    async fn write_part(service: &HlsService, end_segment: bool) {
+        let payload = HlsPayload {
+            duration: 0.2,
+            independent: true,
+            media_start: 0.0,
+            payload: Bytes::from_static(b"part"),
+        };
+        service.write_part(payload, end_segment).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_is_too_far() {
+        let service = new_service();
+        // 다음 part 는 (0, 0)
+        assert!(!service.is_too_far(0, None).await);
+        assert!(!service.is_too_far(1, None).await);
+        assert!(service.is_too_far(2, None).await);
+        assert!(!service.is_too_far(0, Some(2)).await);
+        assert!(service.is_too_far(0, Some(3)).await);
+
+        write_part(&service, false).await;
+        write_part(&service, false).await;
+        // 다음 part 는 (0, 2)
+        assert!(!service.is_too_far(0, Some(4)).await);
+        assert!(service.is_too_far(0, Some(5)).await);
+        assert!(!service.is_too_far(1, Some(0)).await);
+        assert!(service.is_too_far(1, Some(1)).await);
+        // 이미 만들어진 segment 는 기다리지 않고 응답한다.
+        assert!(!service.is_too_far(0, None).await);
+    }
+
+    #[tokio::test]
+    async fn test_wait_for_first_part() {
+        let service = new_service();
+        // msn 없이 요청하면 첫 part 가 만들어질 때까지 기다린다.
+        let waiting = tokio::spawn({
+            let service = service.clone();
+            async move { service.wait_for(0, Some(0)).await }
+        });
+        let segment = tokio::spawn({
+            let service = service.clone();
+            async move { service.wait_for(0, None).await }
+        });
+        time::sleep(time::Duration::from_millis(50)).await;
+        assert!(!waiting.is_finished());
+
+        write_part(&service, false).await;
+        assert!(waiting.await.unwrap());
+        assert!(!segment.is_finished());
+
+        // segment 는 마지막 part 가 끝나야 만들어진다.
+        write_part(&service, true).await;
+        assert!(segment.await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_wait_for_timeout() {
+        let service = new_service();
+        write_part(&service, false).await;
+        // TARGETDURATION(0.4초 + keyframe 여유 1초를 올림한 2초)의 3배 동안 만들어지지 않으면 false
+        let started = time::Instant::now();
+        assert!(!service.wait_for(0, Some(1)).await);
+        assert!(started.elapsed() >= time::Duration::from_secs(6));
+    }
+
+    #[tokio::test]
+    async fn test_wait_for_part() {
+        let service = new_service();
+        write_part(&service, false).await;
+        // 이미 만들어진 part
+        assert!(service.wait_for_part(0, 0).await);
+        // preload hint 가 아닌 part 는 기다리지 않는다.
+        assert!(!service.wait_for_part(0, 2).await);
+
+        // preload hint 로 알려준 part 는 만들어질 때까지 기다린다.
+        let hint = tokio::spawn({
+            let service = service.clone();
+            async move { service.wait_for_part(0, 1).await }
+        });
+        time::sleep(time::Duration::from_millis(50)).await;
+        assert!(!hint.is_finished());
+        write_part(&service, false).await;
+        assert!(hint.await.unwrap());
+    }
+
+    #[test]
+    fn test_availability_time_offset() {
+        let segment = |duration: f32, first_part: f32| m3u8_rs::MediaSegment {
+            duration,
+            parts: vec![m3u8_rs::Part {
+                duration: first_part,
+                uri: "part".to_string(),
+                independent: true,
+            }],
+            ..Default::default()
+        };
+        let mut playlist = MediaPlaylist::default();
+        // part 가 없으면 0
+        assert_eq!(availability_time_offset(&playlist), 0.0);
+
+        // keyframe 이 늦어 길어진 segment 보다 짧은 segment 에 맞춘다.
+        playlist.segments = vec![segment(2.5, 0.2), segment(1.0, 0.3)];
+        assert_eq!(availability_time_offset(&playlist), 0.7);
+    }
 }
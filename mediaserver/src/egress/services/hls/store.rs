@@ -0,0 +1,136 @@
+use async_trait::async_trait;
+use bytes::Bytes;
+use std::collections::HashMap;
+use std::sync::Arc;
+use tokio::sync::RwLock;
+
+use crate::utils;
+
+// playlist, init segment, part, segment 를 path 로 저장하고 꺼내는 곳
+#[async_trait]
+pub trait SegmentStore: Send + Sync {
+    async fn put(&self, path: &str, data: Bytes) -> anyhow::Result<()>;
+    async fn get(&self, path: &str) -> anyhow::Result<Option<Bytes>>;
+    async fn remove(&self, path: &str) -> anyhow::Result<()>;
+    // session 이 끝나면 prefix 아래를 모두 지운다.
+    async fn remove_all(&self, prefix: &str) -> anyhow::Result<()>;
+}
+
+// 메모리에만 보관한다. 오래된 part/segment 는 playlist 에서 빠질 때 service 가 지운다.
+pub struct MemoryStore {
+    files: RwLock<HashMap<String, Bytes>>,
+}
+
+impl MemoryStore {
+    pub fn new() -> Arc<Self> {
+        Arc::new(MemoryStore {
+            files: RwLock::new(HashMap::new()),
+        })
+    }
+}
+
+#[async_trait]
+impl SegmentStore for MemoryStore {
+    async fn put(&self, path: &str, data: Bytes) -> anyhow::Result<()> {
+        self.files.write().await.insert(path.to_string(), data);
+        Ok(())
+    }
+
+    async fn get(&self, path: &str) -> anyhow::Result<Option<Bytes>> {
+        Ok(self.files.read().await.get(path).cloned())
+    }
+
+    async fn remove(&self, path: &str) -> anyhow::Result<()> {
+        self.files.write().await.remove(path);
+        Ok(())
+    }
+
+    async fn remove_all(&self, prefix: &str) -> anyhow::Result<()> {
+        let prefix = format!("{}/", prefix.trim_end_matches('/'));
+        self.files
+            .write()
+            .await
+            .retain(|path, _| !path.starts_with(&prefix));
+        Ok(())
+    }
+}
+
+// path 를 그대로 파일 경로로 써서 디스크에 저장한다.
+pub struct FileStore {}
+
+impl FileStore {
+    pub fn new() -> Arc<Self> {
+        Arc::new(FileStore {})
+    }
+}
+
+#[async_trait]
+impl SegmentStore for FileStore {
+    async fn put(&self, path: &str, data: Bytes) -> anyhow::Result<()> {
+        utils::files::files::write_file_force(path, &data).await
+    }
+
+    async fn get(&self, path: &str) -> anyhow::Result<Option<Bytes>> {
+        match tokio::fs::read(path).await {
+            Ok(data) => Ok(Some(Bytes::from(data))),
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    async fn remove(&self, path: &str) -> anyhow::Result<()> {
+        match tokio::fs::remove_file(path).await {
+            Err(err) if err.kind() != std::io::ErrorKind::NotFound => Err(err.into()),
+            _ => Ok(()),
+        }
+    }
+
+    async fn remove_all(&self, prefix: &str) -> anyhow::Result<()> {
+        match tokio::fs::remove_dir_all(prefix).await {
+            Err(err) if err.kind() != std::io::ErrorKind::NotFound => Err(err.into()),
+            _ => Ok(()),
+        }
+    }
+}
+
+// config 의 hls.store 로 고른다. 기본은 memory
+pub fn new_store(kind: &str) -> Arc<dyn SegmentStore> {
+    match kind {
+        "file" => FileStore::new(),
+        _ => MemoryStore::new(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_memory_store() {
+        let store = MemoryStore::new();
+        store
+            .put("public/hls/a/video0/init.mp4", Bytes::from_static(b"init"))
+            .await
+            .unwrap();
+        store
+            .put("public/hls/ab/index.m3u8", Bytes::from_static(b"#EXTM3U"))
+            .await
+            .unwrap();
+        assert_eq!(
+            store.get("public/hls/a/video0/init.mp4").await.unwrap(),
+            Some(Bytes::from_static(b"init"))
+        );
+
+        store.remove_all("public/hls/a").await.unwrap();
+        assert!(store
+            .get("public/hls/a/video0/init.mp4")
+            .await
+            .unwrap()
+            .is_none());
+        assert!(store
+            .get("public/hls/ab/index.m3u8")
+            .await
+            .unwrap()
+            .is_some());
+    }
+}
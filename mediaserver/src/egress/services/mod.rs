@@ -1 +1,2 @@
 pub mod hls;
+pub mod record;
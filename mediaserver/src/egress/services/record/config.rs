@@ -0,0 +1,72 @@
+use std::path::PathBuf;
+use std::time::Duration;
+
+const TEMP_EXTENSION: &str = "part";
+
+#[derive(Debug, Clone)]
+pub struct RecordConfig {
+    pub dir: String,
+    pub stream_id: String,
+    // 둘 중 먼저 넘는 쪽에서 다음 keyframe 에 새 파일로 넘어간다. None 이면 자르지 않는다.
+    pub max_duration: Option<Duration>,
+    pub max_size: Option<u64>,
+}
+
+impl RecordConfig {
+    // {dir}/{stream_id}/{시작 시각}.{extension}
+    pub fn make_file_path(&self, extension: &str) -> anyhow::Result<PathBuf> {
+        let started = chrono::Local::now().format("%Y%m%d_%H%M%S_%3f");
+        Ok(PathBuf::from(format!(
+            "{}/{}/{}.{}",
+            self.dir,
+            sanitize_stream_id(&self.stream_id)?,
+            started,
+            extension
+        )))
+    }
+
+    // 녹화 중에는 moov 가 뒤에 있는 임시 파일에 쓴다.
+    pub fn make_temp_path(&self, path: &std::path::Path) -> PathBuf {
+        let extension = path.extension().unwrap_or_default().to_string_lossy();
+        path.with_extension(format!("{}.{}", extension, TEMP_EXTENSION))
+    }
+}
+
+// stream_id 는 publisher 가 정하므로 dir 밖으로 나가지 않도록 하나의 디렉토리 이름으로 만든다.
+// 경로 구분자는 '_' 로 바꾸고, '.' 이나 '..' 처럼 디렉토리를 가리키는 이름은 거절한다.
+pub fn sanitize_stream_id(stream_id: &str) -> anyhow::Result<String> {
+    let name: String = stream_id
+        .chars()
+        .map(|c| match c {
+            '/' | '\\' | ':' => '_',
+            c if c.is_control() => '_',
+            c => c,
+        })
+        .collect();
+    if name.chars().all(|c| c == '.') {
+        return Err(anyhow::anyhow!(
+            "invalid stream id for record: {:?}",
+            stream_id
+        ));
+    }
+    Ok(name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sanitize_stream_id() -> anyhow::Result<()> {
+        assert_eq!(sanitize_stream_id("camera1")?, "camera1");
+        assert_eq!(sanitize_stream_id("live/camera1")?, "live_camera1");
+        assert_eq!(sanitize_stream_id("../../etc")?, ".._.._etc");
+        assert_eq!(sanitize_stream_id("/etc/passwd")?, "_etc_passwd");
+        assert_eq!(sanitize_stream_id("..\\windows")?, ".._windows");
+        assert_eq!(sanitize_stream_id("C:\\temp")?, "C__temp");
+        assert!(sanitize_stream_id("..").is_err());
+        assert!(sanitize_stream_id(".").is_err());
+        assert!(sanitize_stream_id("").is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,188 @@
+use anyhow::anyhow;
+use byteorder::{BigEndian, ByteOrder, ReadBytesExt};
+use std::fs::File;
+use std::io::{BufWriter, Read, Seek, SeekFrom, Write};
+use std::path::Path;
+
+// moov 안에서 stco/co64 를 찾기 위해 들어가 봐야 하는 box
+const CONTAINER_BOXES: [&[u8; 4]; 5] = [b"moov", b"trak", b"mdia", b"minf", b"stbl"];
+
+struct BoxInfo {
+    box_type: [u8; 4],
+    offset: u64,
+    size: u64,
+}
+
+// mp4 writer 는 mdat 뒤에 moov 를 쓴다. 끝까지 받지 않아도 재생할 수 있도록
+// moov 를 ftyp 바로 뒤로 옮기고 chunk offset 을 옮긴 만큼 더해서 output 에 쓴다.
+pub fn faststart(input: &Path, output: &Path) -> anyhow::Result<()> {
+    let mut file = File::open(input)?;
+    let file_len = file.metadata()?.len();
+    let boxes = read_boxes(&mut file, file_len)?;
+
+    let moov_index = boxes
+        .iter()
+        .position(|b| &b.box_type == b"moov")
+        .ok_or(anyhow!("no moov box"))?;
+    let mdat_index = boxes
+        .iter()
+        .position(|b| &b.box_type == b"mdat")
+        .ok_or(anyhow!("no mdat box"))?;
+    if moov_index < mdat_index {
+        std::fs::copy(input, output)?;
+        return Ok(());
+    }
+
+    let moov = &boxes[moov_index];
+    let mut moov_data = vec![0u8; moov.size as usize];
+    file.seek(SeekFrom::Start(moov.offset))?;
+    file.read_exact(&mut moov_data)?;
+    shift_chunk_offsets(&mut moov_data, moov.size)?;
+
+    // ftyp 가 있으면 그 뒤, 없으면 맨 앞에 moov 를 넣는다.
+    let insert_index = boxes
+        .iter()
+        .position(|b| &b.box_type == b"ftyp")
+        .map(|index| index + 1)
+        .unwrap_or(0);
+
+    let mut writer = BufWriter::new(File::create(output)?);
+    for (index, b) in boxes.iter().enumerate() {
+        if index == insert_index {
+            writer.write_all(&moov_data)?;
+        }
+        if index == moov_index {
+            continue;
+        }
+        file.seek(SeekFrom::Start(b.offset))?;
+        std::io::copy(&mut (&mut file).take(b.size), &mut writer)?;
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+fn read_boxes(file: &mut File, file_len: u64) -> anyhow::Result<Vec<BoxInfo>> {
+    let mut boxes = vec![];
+    let mut offset = 0;
+    while offset + 8 <= file_len {
+        file.seek(SeekFrom::Start(offset))?;
+        let mut size = file.read_u32::<BigEndian>()? as u64;
+        let mut box_type = [0u8; 4];
+        file.read_exact(&mut box_type)?;
+        if size == 1 {
+            size = file.read_u64::<BigEndian>()?;
+        } else if size == 0 {
+            size = file_len - offset;
+        }
+        if size < 8 || offset + size > file_len {
+            return Err(anyhow!("invalid box size"));
+        }
+        boxes.push(BoxInfo {
+            box_type,
+            offset,
+            size,
+        });
+        offset += size;
+    }
+    Ok(boxes)
+}
+
+// data 는 box 하나 전체. container 는 안으로 들어가고 stco/co64 의 offset 에 shift 를 더한다.
+fn shift_chunk_offsets(data: &mut [u8], shift: u64) -> anyhow::Result<()> {
+    let (header_size, box_type) = box_header(data)?;
+    let payload = &mut data[header_size..];
+    if CONTAINER_BOXES.contains(&&box_type) {
+        let mut offset = 0;
+        while offset + 8 <= payload.len() {
+            let size = box_size(&payload[offset..])?;
+            shift_chunk_offsets(&mut payload[offset..offset + size], shift)?;
+            offset += size;
+        }
+    } else if &box_type == b"stco" || &box_type == b"co64" {
+        if payload.len() < 8 {
+            return Err(anyhow!("short {:?}", box_type));
+        }
+        let count = BigEndian::read_u32(&payload[4..8]) as usize;
+        let entry_size = if &box_type == b"stco" { 4 } else { 8 };
+        let entries = payload
+            .get_mut(8..8 + count * entry_size)
+            .ok_or(anyhow!("short chunk offset table"))?;
+        for entry in entries.chunks_exact_mut(entry_size) {
+            if entry_size == 4 {
+                let value = BigEndian::read_u32(entry) as u64 + shift;
+                let value = u32::try_from(value).map_err(|_| anyhow!("stco overflow"))?;
+                BigEndian::write_u32(entry, value);
+            } else {
+                BigEndian::write_u64(entry, BigEndian::read_u64(entry) + shift);
+            }
+        }
+    }
+    Ok(())
+}
+
+fn box_header(data: &[u8]) -> anyhow::Result<(usize, [u8; 4])> {
+    if data.len() < 8 {
+        return Err(anyhow!("short box"));
+    }
+    let box_type = [data[4], data[5], data[6], data[7]];
+    let header_size = if BigEndian::read_u32(&data[0..4]) == 1 {
+        16
+    } else {
+        8
+    };
+    Ok((header_size, box_type))
+}
+
+fn box_size(data: &[u8]) -> anyhow::Result<usize> {
+    let size = match BigEndian::read_u32(&data[0..4]) {
+        0 => data.len() as u64,
+        1 if data.len() >= 16 => BigEndian::read_u64(&data[8..16]),
+        size => size as u64,
+    };
+    if size < 8 || size as usize > data.len() {
+        return Err(anyhow!("invalid box size"));
+    }
+    Ok(size as usize)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn make_box(box_type: &[u8; 4], payload: &[u8]) -> Vec<u8> {
+        let mut data = vec![];
+        data.extend_from_slice(&(payload.len() as u32 + 8).to_be_bytes());
+        data.extend_from_slice(box_type);
+        data.extend_from_slice(payload);
+        data
+    }
+
+    #[test]
+    fn test_faststart() {
+        let ftyp = make_box(b"ftyp", b"isom\0\0\0\0");
+        let mdat = make_box(b"mdat", b"sample");
+        let chunk_offset = (ftyp.len() + 8) as u32;
+        let mut stco_payload = vec![0, 0, 0, 0, 0, 0, 0, 1];
+        stco_payload.extend_from_slice(&chunk_offset.to_be_bytes());
+        let stbl = make_box(b"stbl", &make_box(b"stco", &stco_payload));
+        let trak = make_box(b"trak", &make_box(b"mdia", &make_box(b"minf", &stbl)));
+        let moov = make_box(b"moov", &[make_box(b"mvhd", &[0; 4]), trak].concat());
+
+        let dir = std::env::temp_dir().join(format!("faststart-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let input = dir.join("input.mp4");
+        let output = dir.join("output.mp4");
+        std::fs::write(&input, [ftyp.clone(), mdat.clone(), moov.clone()].concat()).unwrap();
+
+        faststart(&input, &output).unwrap();
+        let data = std::fs::read(&output).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(data.len(), ftyp.len() + moov.len() + mdat.len());
+        assert_eq!(&data[ftyp.len() + 4..ftyp.len() + 8], b"moov");
+        let stco_entry = data.len() - mdat.len() - 4;
+        let shifted = BigEndian::read_u32(&data[stco_entry..stco_entry + 4]) as usize;
+        assert_eq!(shifted, chunk_offset as usize + moov.len());
+        assert_eq!(&data[shifted..shifted + 6], b"sample");
+    }
+}
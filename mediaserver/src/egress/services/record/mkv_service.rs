@@ -0,0 +1,121 @@
+use bytes::Bytes;
+use std::fs::File;
+use std::io::BufWriter;
+use std::path::PathBuf;
+use std::sync::Arc;
+use tokio::sync::{Mutex, RwLock};
+
+use super::config::RecordConfig;
+use crate::protocols::matroska::muxer::{MkvMuxer, MkvTrack};
+
+struct MkvFile {
+    muxer: MkvMuxer<BufWriter<File>>,
+    path: PathBuf,
+    size: u64,
+}
+
+// WebM/Matroska 파일을 cluster 단위로 이어서 쓴다.
+// mp4 와 달리 임시 파일 없이 바로 쓰고, 중간에 끊겨도 마지막 cluster 까지 재생할 수 있다.
+pub struct MkvRecordService {
+    pub config: RecordConfig,
+    // MkvMuxer 는 동기 I/O 를 하므로 spawn_blocking 안에서만 쓴다.
+    file: Arc<Mutex<Option<MkvFile>>>,
+    // 다 쓴 파일 목록
+    files: RwLock<Vec<String>>,
+}
+
+impl MkvRecordService {
+    pub fn new(config: RecordConfig) -> Self {
+        Self {
+            config,
+            file: Arc::new(Mutex::new(None)),
+            files: RwLock::new(vec![]),
+        }
+    }
+
+    // 쓰고 있던 파일을 닫고 새 파일을 시작한다. doc_type 이 webm 이면 .webm, 아니면 .mkv
+    pub async fn start_file(&self, doc_type: &str, tracks: &[MkvTrack]) -> anyhow::Result<()> {
+        let current = self.file.lock().await.take();
+        if let Some(current) = current {
+            self.finish(current).await?;
+        }
+
+        let extension = if doc_type == "webm" { "webm" } else { "mkv" };
+        let path = self.config.make_file_path(extension)?;
+        if let Some(parent) = path.parent() {
+            tokio::fs::create_dir_all(parent).await?;
+        }
+
+        let file = self.file.clone();
+        let doc_type = doc_type.to_string();
+        let tracks = tracks.to_vec();
+        tokio::task::spawn_blocking(move || -> anyhow::Result<()> {
+            let muxer = MkvMuxer::new(BufWriter::new(File::create(&path)?), &doc_type, &tracks)?;
+
+            log::info!("record file started: {:?}", path);
+            file.blocking_lock().replace(MkvFile {
+                muxer,
+                path,
+                size: 0,
+            });
+            Ok(())
+        })
+        .await?
+    }
+
+    // 아직 파일을 시작하지 않았으면 버린다. timestamp 는 ms
+    pub async fn write_frame(
+        &self,
+        track: u64,
+        timestamp: u64,
+        keyframe: bool,
+        data: Bytes,
+    ) -> anyhow::Result<()> {
+        let file = self.file.clone();
+        tokio::task::spawn_blocking(move || -> anyhow::Result<()> {
+            let mut file = file.blocking_lock();
+            let Some(current) = file.as_mut() else {
+                return Ok(());
+            };
+            current
+                .muxer
+                .write_frame(track, timestamp, keyframe, &data)?;
+            current.size += data.len() as u64;
+            Ok(())
+        })
+        .await?
+    }
+
+    // 지금 파일에 쓴 frame 크기. 파일이 없으면 None
+    pub async fn file_size(&self) -> Option<u64> {
+        self.file.lock().await.as_ref().map(|current| current.size)
+    }
+
+    pub async fn finish_file(&self) -> anyhow::Result<()> {
+        let current = self.file.lock().await.take();
+        if let Some(current) = current {
+            self.finish(current).await?;
+        }
+        Ok(())
+    }
+
+    pub async fn files(&self) -> Vec<String> {
+        self.files.read().await.clone()
+    }
+
+    // Cues 와 크기만 채우면 되므로 mp4 의 faststart 처럼 따로 돌리지 않는다.
+    async fn finish(&self, current: MkvFile) -> anyhow::Result<()> {
+        let MkvFile { muxer, path, .. } = current;
+        let path = tokio::task::spawn_blocking(move || -> anyhow::Result<_> {
+            muxer.finalize()?;
+            Ok(path)
+        })
+        .await??;
+        log::info!("record file finished: {:?}", path);
+        self.files
+            .write()
+            .await
+            .push(path.to_string_lossy().to_string());
+        Ok(())
+    }
+}
@@ -0,0 +1,4 @@
+pub mod config;
+pub mod faststart;
+pub mod mkv_service;
+pub mod service;
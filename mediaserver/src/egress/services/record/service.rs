@@ -0,0 +1,148 @@
+use std::fs::File;
+use std::io::BufWriter;
+use std::path::PathBuf;
+use std::sync::Arc;
+use tokio::sync::{Mutex, RwLock};
+
+use super::config::RecordConfig;
+use super::faststart::faststart;
+
+struct RecordFile {
+    writer: mp4::Mp4Writer<BufWriter<File>>,
+    path: PathBuf,
+    temp_path: PathBuf,
+    size: u64,
+}
+
+// progressive mp4 파일을 쓴다. 파일을 닫을 때 moov 를 앞으로 옮긴다.
+pub struct RecordService {
+    pub config: RecordConfig,
+    // Mp4Writer 는 동기 I/O 를 하므로 spawn_blocking 안에서만 쓴다.
+    file: Arc<Mutex<Option<RecordFile>>>,
+    // 다 쓴 파일 목록
+    files: Arc<RwLock<Vec<String>>>,
+}
+
+impl RecordService {
+    pub fn new(config: RecordConfig) -> Self {
+        Self {
+            config,
+            file: Arc::new(Mutex::new(None)),
+            files: Arc::new(RwLock::new(vec![])),
+        }
+    }
+
+    // 쓰고 있던 파일을 닫고 새 파일을 시작한다. track id 는 track_configs 순서대로 1 부터
+    pub async fn start_file(&self, track_configs: &[mp4::TrackConfig]) -> anyhow::Result<()> {
+        let path = self.config.make_file_path("mp4")?;
+        let temp_path = self.config.make_temp_path(&path);
+        if let Some(parent) = path.parent() {
+            tokio::fs::create_dir_all(parent).await?;
+        }
+
+        let file = self.file.clone();
+        let track_configs = track_configs.to_vec();
+        let previous = tokio::task::spawn_blocking(move || -> anyhow::Result<_> {
+            let mp4_config = mp4::Mp4Config {
+                major_brand: str::parse("isom").unwrap(),
+                minor_version: 512,
+                compatible_brands: vec![
+                    str::parse("isom").unwrap(),
+                    str::parse("iso2").unwrap(),
+                    str::parse("avc1").unwrap(),
+                    str::parse("mp41").unwrap(),
+                ],
+                timescale: 1000,
+            };
+            let mut writer = mp4::Mp4Writer::write_start(
+                BufWriter::new(File::create(&temp_path)?),
+                &mp4_config,
+            )?;
+            for track_config in &track_configs {
+                writer.add_track(track_config)?;
+            }
+
+            log::info!("record file started: {:?}", path);
+            Ok(file.blocking_lock().replace(RecordFile {
+                writer,
+                path,
+                temp_path,
+                size: 0,
+            }))
+        })
+        .await??;
+
+        if let Some(current) = previous {
+            // faststart 는 파일 전체를 복사하므로 녹화를 멈추지 않도록 따로 돌린다.
+            let files = self.files.clone();
+            tokio::spawn(async move {
+                if let Err(err) = finish(current, files).await {
+                    log::warn!("failed to finish record file: {}", err);
+                }
+            });
+        }
+        Ok(())
+    }
+
+    // 아직 파일을 시작하지 않았으면 버린다.
+    pub async fn write_sample(&self, track_id: u32, sample: mp4::Mp4Sample) -> anyhow::Result<()> {
+        let file = self.file.clone();
+        tokio::task::spawn_blocking(move || -> anyhow::Result<()> {
+            let mut file = file.blocking_lock();
+            let Some(current) = file.as_mut() else {
+                return Ok(());
+            };
+            current.writer.write_sample(track_id, &sample)?;
+            current.size += sample.bytes.len() as u64;
+            Ok(())
+        })
+        .await?
+    }
+
+    // 지금 파일에 쓴 sample 크기. 파일이 없으면 None
+    pub async fn file_size(&self) -> Option<u64> {
+        self.file.lock().await.as_ref().map(|current| current.size)
+    }
+
+    pub async fn finish_file(&self) -> anyhow::Result<()> {
+        let current = self.file.lock().await.take();
+        if let Some(current) = current {
+            finish(current, self.files.clone()).await?;
+        }
+        Ok(())
+    }
+
+    pub async fn files(&self) -> Vec<String> {
+        self.files.read().await.clone()
+    }
+}
+
+async fn finish(current: RecordFile, files: Arc<RwLock<Vec<String>>>) -> anyhow::Result<()> {
+    let RecordFile {
+        mut writer,
+        path,
+        temp_path,
+        ..
+    } = current;
+    let path2 = path.clone();
+    tokio::task::spawn_blocking(move || -> anyhow::Result<()> {
+        writer.write_end()?;
+        let mut writer = writer.into_writer();
+        std::io::Write::flush(&mut writer)?;
+        drop(writer);
+
+        if let Err(err) = faststart(&temp_path, &path2) {
+            // moov 를 옮기지 못해도 뒤에 moov 가 있는 파일은 재생할 수 있다.
+            log::warn!("faststart failed, keep moov at the end: {}", err);
+            std::fs::rename(&temp_path, &path2)?;
+            return Ok(());
+        }
+        std::fs::remove_file(&temp_path)?;
+        Ok(())
+    })
+    .await??;
+
+    log::info!("record file finished: {:?}", path);
+    files.write().await.push(path.to_string_lossy().to_string());
+    Ok(())
+}
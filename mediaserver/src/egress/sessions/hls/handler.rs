@@ -1,11 +1,13 @@
 use crate::codecs::codec::Codec;
 use crate::codecs::h264::format::NALUType;
+use crate::egress::services::hls::config::HlsFormat;
 use crate::egress::services::hls::service::{HlsPayload, HlsService};
 use crate::egress::sessions::hls::track_context;
-use crate::egress::sessions::session::SessionHandler;
+use crate::egress::sessions::session::{SessionHandler, CODEC_WAIT_TIMEOUT};
 use crate::hubs::source::HubSource;
 use crate::hubs::stream::HubStream;
 use crate::hubs::unit::HubUnit;
+use crate::protocols::mpegts::muxer::{TsMuxer, TsTrack};
 use crate::utils;
 use crate::utils::types::types;
 use std::io::{Cursor, Write};
@@ -14,21 +16,107 @@ use std::sync::Arc;
 use tokio::sync::{Mutex, RwLock};
 use tokio_util::sync::CancellationToken;
 
+// part, segment 경계는 wall clock 이 아니라 video dts 로 정한다.
 struct HlsState {
-    index: i32,
-    started: bool,
-    duration_sum: i64,
-    prev_time: tokio::time::Instant,
+    // 현재 part 와 segment 가 시작된 video dts. None 이면 아직 시작하지 않음
+    part_start: Option<i64>,
+    segment_start: i64,
+    // 현재 part 가 IDR 로 시작했는지
+    part_independent: bool,
+    // 현재 part 의 첫 sample 의 media time(초). fmp4 의 tfdt 와 같다.
+    part_media_start: f64,
+    // codec 이 바뀌어서 다음 keyframe 에서 segment 를 끊고 init segment 를 다시 써야 하는지
+    codec_changed: bool,
+    // segment 를 끊으려고 keyframe 을 요청했는지. segment 가 끝나면 다시 요청할 수 있다.
+    keyframe_requested: bool,
+    // 마지막으로 받은 packet 이 끝나는 dts 와 그 timescale. 종료할 때 남은 part 의 길이를 구한다.
+    last_end: i64,
+    timescale: i64,
+}
+
+// part, segment 를 자르는 길이. timescale 단위
+#[derive(Debug, Clone, Copy)]
+struct CutTarget {
+    part: i64,
+    // 이 길이를 채운 뒤의 keyframe 에서 segment 를 자른다.
+    segment: i64,
+    // TARGETDURATION. keyframe 이 오지 않아도 segment 가 이 길이를 넘지 않도록 자른다.
+    max_segment: i64,
+}
+
+// 닫은 part. duration 은 초
+#[derive(Debug, PartialEq)]
+struct PartCut {
+    duration: f32,
+    independent: bool,
+    media_start: f64,
+    end_segment: bool,
+    // codec 이 바뀌어서 이 segment 다음부터 새 init segment 를 써야 하는지
+    reinit: bool,
 }
 
 impl HlsState {
     fn new() -> Self {
         Self {
-            started: false,
-            index: 0,
-            prev_time: tokio::time::Instant::now(),
-            duration_sum: 0,
+            part_start: None,
+            segment_start: 0,
+            part_independent: false,
+            part_media_start: 0.0,
+            codec_changed: false,
+            keyframe_requested: false,
+            last_end: 0,
+            timescale: 1,
+        }
+    }
+
+    // video sample 을 쓰기 전에 호출한다. part 를 닫아야 하면 닫은 part 를 돌려주고 이 sample 부터 다음 part 를 시작한다.
+    // part 는 target.part 를 넘지 않도록 자르고, segment 는 target.segment 를 채운 뒤 keyframe 에서 자른다.
+    // keyframe 이 늦어서 target.max_segment 를 넘게 되면 keyframe 이 아니어도 자른다.
+    fn cut(
+        &mut self,
+        dts: i64,
+        duration: i64,
+        keyframe: bool,
+        media_time: f64,
+        target: CutTarget,
+    ) -> Option<PartCut> {
+        self.last_end = dts + duration;
+        let Some(part_start) = self.part_start else {
+            self.part_start = Some(dts);
+            self.segment_start = dts;
+            self.part_independent = keyframe;
+            self.part_media_start = media_time;
+            return None;
+        };
+        if dts <= part_start {
+            return None;
+        }
+
+        let segment_duration = dts - self.segment_start;
+        let end_segment = segment_duration + duration > target.max_segment
+            || (keyframe && (segment_duration >= target.segment || self.codec_changed));
+        let end_part = end_segment || dts - part_start + duration > target.part;
+        if !end_part {
+            return None;
         }
+
+        let cut = PartCut {
+            duration: (dts - part_start) as f32 / self.timescale as f32,
+            independent: self.part_independent,
+            media_start: self.part_media_start,
+            end_segment,
+            // 새 init segment 는 keyframe 부터 시작해야 한다.
+            reinit: end_segment && keyframe && self.codec_changed,
+        };
+        self.part_start = Some(dts);
+        self.part_independent = keyframe;
+        self.part_media_start = media_time;
+        if end_segment {
+            self.segment_start = dts;
+            self.keyframe_requested = false;
+            self.codec_changed &= !keyframe;
+        }
+        Some(cut)
     }
 }
 
@@ -39,78 +127,76 @@ pub struct HlsHandler {
     state: RwLock<HlsState>,
 
     sources: Vec<Arc<HubSource>>,
+    // keyframe 을 요청할 video source
+    video_source: Option<Arc<HubSource>>,
     target: Arc<HlsService>,
-
-    fmp4: Mutex<mp4::Fmp4Writer>,
+    // source 가 없는 track 은 None
+    audio_track_id: Option<u32>,
+    video_track_id: Option<u32>,
+    // init segment 를 다시 만들 때 쓰는 지금의 codec
+    audio_codec: RwLock<Option<Codec>>,
+    video_codec: RwLock<Option<Codec>>,
+
+    writer: Mutex<HlsWriter>,
     duration_ms: u64,
+    gop_cache: bool,
 }
 
 impl HlsHandler {
-    pub async fn new(hub_stream: &Arc<HubStream>, target: Arc<HlsService>) -> anyhow::Result<Self> {
+    // video_rid 가 있으면 그 simulcast layer 를 video 로 쓴다.
+    pub async fn new(
+        hub_stream: &Arc<HubStream>,
+        target: Arc<HlsService>,
+        video_rid: Option<&str>,
+        gop_cache: bool,
+    ) -> anyhow::Result<Self> {
         let duration_ms = (target.config.part_duration * 1000.0) as u64;
 
-        let mut width = 0;
-        let mut height = 0;
-        let mut sps = None;
-        let mut pps = None;
-        let mut audio_timescale = 0;
-        let mut video_timescale = 0;
+        let ready_sources = hub_stream.get_ready_sources(CODEC_WAIT_TIMEOUT).await;
+        if ready_sources.is_empty() {
+            return Err(anyhow::anyhow!("no source with known codec"));
+        }
 
+        // 실제로 있는 source 만 track 으로 쓴다. kind 별로 첫 source 하나씩
         let mut sources = vec![];
-        for source in hub_stream.get_sources().await {
-            let codec_info = source.get_codec().await.unwrap();
+        let mut video_source = None;
+        let mut audio_codec = None;
+        let mut video_codec = None;
+        for (source, codec_info) in ready_sources {
             if codec_info.kind() == types::MediaKind::Audio {
-                audio_timescale = codec_info.clock_rate();
+                if audio_codec.is_some() {
+                    continue;
+                }
+                audio_codec = Some(codec_info);
             } else if codec_info.kind() == types::MediaKind::Video {
-                sps = codec_info.sps();
-                pps = codec_info.pps();
-
-                width = codec_info.width();
-                height = codec_info.height();
-
-                video_timescale = codec_info.clock_rate();
+                if video_codec.is_some() || (video_rid.is_some() && source.rid() != video_rid) {
+                    continue;
+                }
+                if codec_info.sps().is_none() || codec_info.pps().is_none() {
+                    log::warn!("video source without sps/pps, skip");
+                    continue;
+                }
+                video_codec = Some(codec_info);
+                video_source = Some(source.clone());
+            } else {
+                continue;
             }
             sources.push(source);
         }
-        println!(
-            "width:{}, height:{}, sps:{:?}, pps:{:?}",
-            width, height, sps, pps
-        );
-        println!(
-            "audio_timescale:{}, video_timescale:{}",
-            audio_timescale, video_timescale
-        );
+        if sources.is_empty() {
+            return Err(anyhow::anyhow!("no supported source for hls"));
+        }
 
-        let fmp4_config = mp4::Mp4Config {
-            major_brand: str::parse("iso5").unwrap(),
-            minor_version: 512,
-            compatible_brands: vec![
-                str::parse("iso5").unwrap(),
-                str::parse("iso6").unwrap(),
-                str::parse("mp41").unwrap(),
-            ],
-            timescale: 1000,
-        };
-        let mut fmp4 = mp4::Fmp4Writer::new(&fmp4_config).unwrap();
-        fmp4.add_track(&mp4::TrackConfig {
-            track_type: mp4::TrackType::Audio,
-            timescale: audio_timescale,
-            language: String::from("und"),
-            media_conf: mp4::MediaConfig::OpusConfig(mp4::OpusConfig {}),
-        })
-        .unwrap();
-        fmp4.add_track(&mp4::TrackConfig {
-            track_type: mp4::TrackType::Video,
-            timescale: video_timescale,
-            language: String::from("und"),
-            media_conf: mp4::MediaConfig::AvcConfig(mp4::AvcConfig {
-                width: width as u16,
-                height: height as u16,
-                seq_param_set: sps.unwrap(),
-                pic_param_set: pps.unwrap(),
-            }),
-        })
-        .unwrap();
+        let writer = HlsWriter::new(
+            target.config.format,
+            audio_codec.as_ref(),
+            video_codec.as_ref(),
+        )?;
+        // track id 는 audio, video 순서로 추가한 순서대로 1 부터
+        let audio_track_id = audio_codec.as_ref().map(|_| 1);
+        let video_track_id = video_codec
+            .as_ref()
+            .map(|_| 1 + audio_track_id.is_some() as u32);
 
         println!("duration_ms:{}", duration_ms);
 
@@ -118,75 +204,358 @@ impl HlsHandler {
             duration_ms,
             token: CancellationToken::new(),
             state: RwLock::new(HlsState::new()),
-            started: AtomicBool::new(false),
+            // video 가 없으면 keyframe 을 기다리지 않는다.
+            started: AtomicBool::new(video_track_id.is_none()),
             sources,
+            video_source,
+            audio_track_id,
+            video_track_id,
+            audio_codec: RwLock::new(audio_codec),
+            video_codec: RwLock::new(video_codec),
             target,
-            fmp4: Mutex::new(fmp4),
+            writer: Mutex::new(writer),
+            gop_cache,
         })
     }
 
-    pub async fn write_hls_segment(&self, pkt: &utils::packet::packet::Packet) {
-        let (index, duration) = {
-            let mut state = self.state.write().await;
-            state.duration_sum += pkt.duration();
+    // video packet 을 쓰기 전에 호출해서 part 를 닫아야 하면 지금까지의 fragment 를 part 로 내보낸다.
+    // part 는 part_duration 을 넘지 않도록 자르고, segment 는 segment 길이를 채운 뒤 keyframe 에서 자른다.
+    pub async fn write_hls_segment(
+        &self,
+        pkt: &utils::packet::packet::Packet,
+        unit: &HubUnit,
+        keyframe: bool,
+    ) {
+        let Some(dts) = pkt.dts else {
+            return;
+        };
+        let timescale = pkt.time_base().den as i64;
+        let media_time = unit.pts as f64 / unit.timebase.max(1) as f64;
+        let part_target = self.duration_ms as i64 * timescale / 1000;
+        let target = CutTarget {
+            part: part_target,
+            segment: part_target * self.target.config.part_max_count as i64,
+            max_segment: self.target.config.target_duration() as i64 * timescale,
+        };
 
-            if !state.started {
-                state.started = true;
-                state.prev_time = tokio::time::Instant::now();
-                return;
-            } else if state.prev_time.elapsed()
-                < tokio::time::Duration::from_millis(self.duration_ms)
+        let PartCut {
+            duration,
+            independent,
+            media_start,
+            end_segment,
+            reinit,
+        } = {
+            let mut state = self.state.write().await;
+            state.timescale = timescale;
+            // publisher 의 keyframe 간격이 길어도 segment 가 target 길이에서 끊기도록 요청한다.
+            // TARGETDURATION 은 segment_target 보다 keyframe 을 기다릴 만큼 길다.
+            if !keyframe
+                && state.part_start.is_some_and(|part_start| dts > part_start)
+                && dts - state.segment_start >= target.segment
             {
-                return;
+                self.request_keyframe(&mut state);
             }
-            let duration = state.duration_sum as f32 / pkt.time_base().den as f32;
-            // if duration < 1.0 {
-            //     return;
-            // }
-            state.prev_time = tokio::time::Instant::now();
-
-            state.duration_sum = 0;
-            let index = state.index;
-            state.index += 1;
-
-            (index, duration)
+            let Some(cut) = state.cut(dts, pkt.duration(), keyframe, media_time, target) else {
+                return;
+            };
+            cut
         };
+        if end_segment && !keyframe {
+            log::warn!("no keyframe until target duration, cut segment without keyframe");
+        }
 
         {
-            let mut cursor: Cursor<Vec<u8>> = Cursor::new(Vec::<u8>::new());
-            let mut fmp4 = self.fmp4.lock().await;
-            if let Err(err) = fmp4.write_end(&mut cursor) {
+            let mut writer = self.writer.lock().await;
+            let data = writer.write_end(end_segment).unwrap_or_else(|err| {
                 println!("failed to write end: {}", err);
-            }
+                bytes::Bytes::new()
+            });
 
-            let data = cursor.into_inner();
             if let Err(err) = self
                 .target
-                .write_segment(
-                    index,
+                .write_part(
                     HlsPayload {
                         duration,
-                        payload: bytes::Bytes::from(data),
+                        independent,
+                        media_start,
+                        payload: data,
                     },
+                    end_segment,
                 )
                 .await
             {
                 println!("failed to write segment: {}", err);
             }
+
+            if reinit {
+                // 새 codec 의 sample 은 새 init segment 를 쓰는 writer 로 쓴다.
+                match self.make_writer().await {
+                    Ok((new_writer, Some(header))) => {
+                        *writer = new_writer;
+                        if let Err(err) = self.target.change_init_segment(header).await {
+                            log::warn!("failed to write init segment: {}", err);
+                        }
+                    }
+                    Ok((new_writer, None)) => {
+                        *writer = new_writer;
+                        self.target.mark_discontinuity();
+                    }
+                    Err(err) => log::warn!("failed to make hls writer: {}", err),
+                }
+            }
+        }
+    }
+
+    fn request_keyframe(&self, state: &mut HlsState) {
+        if state.keyframe_requested {
+            return;
+        }
+        state.keyframe_requested = true;
+        if let Some(source) = self.video_source.as_ref() {
+            source.request_keyframe();
         }
     }
+
+    // 아직 part 로 내보내지 않은 fragment 를 마지막 segment 로 쓴다.
+    async fn flush(&self) {
+        let (duration, independent, media_start) = {
+            let mut state = self.state.write().await;
+            let Some(part_start) = state.part_start.take() else {
+                return;
+            };
+            if state.last_end <= part_start {
+                return;
+            }
+            let duration = (state.last_end - part_start) as f32 / state.timescale as f32;
+            (duration, state.part_independent, state.part_media_start)
+        };
+
+        let data = match self.writer.lock().await.write_end(true) {
+            Ok(data) => data,
+            Err(err) => {
+                log::warn!("failed to write end: {}", err);
+                return;
+            }
+        };
+        if let Err(err) = self
+            .target
+            .write_part(
+                HlsPayload {
+                    duration,
+                    independent,
+                    media_start,
+                    payload: data,
+                },
+                true,
+            )
+            .await
+        {
+            log::warn!("failed to write last segment: {}", err);
+        }
+    }
+
+    // 지금의 codec 으로 writer 와 init segment(TS 는 없음)를 만든다.
+    async fn make_writer(&self) -> anyhow::Result<(HlsWriter, Option<bytes::Bytes>)> {
+        let audio_codec = self.audio_codec.read().await.clone();
+        let video_codec = self.video_codec.read().await.clone();
+        let mut writer = HlsWriter::new(
+            self.target.config.format,
+            audio_codec.as_ref(),
+            video_codec.as_ref(),
+        )?;
+        let header = writer.header()?;
+        Ok((writer, header))
+    }
+}
+
+// segment 를 만드는 muxer. HlsConfig 의 format 으로 고른다.
+enum HlsWriter {
+    Fmp4(mp4::Fmp4Writer),
+    Ts(TsMuxer),
+}
+
+impl HlsWriter {
+    fn new(
+        format: HlsFormat,
+        audio: Option<&Codec>,
+        video: Option<&Codec>,
+    ) -> anyhow::Result<Self> {
+        match format {
+            HlsFormat::Fmp4 => Ok(HlsWriter::Fmp4(new_fmp4(audio, video)?)),
+            HlsFormat::Ts => {
+                let mut muxer = TsMuxer::new(ts_tracks(audio, video)?)?;
+                muxer.write_tables();
+                Ok(HlsWriter::Ts(muxer))
+            }
+        }
+    }
+
+    // fmp4 의 init segment. TS 는 segment 마다 PAT/PMT 를 넣으므로 없다.
+    fn header(&mut self) -> anyhow::Result<Option<bytes::Bytes>> {
+        match self {
+            HlsWriter::Fmp4(fmp4) => {
+                let mut cursor = Cursor::new(Vec::<u8>::new());
+                fmp4.write_header(&mut cursor)?;
+                Ok(Some(bytes::Bytes::from(cursor.into_inner())))
+            }
+            HlsWriter::Ts(_) => Ok(None),
+        }
+    }
+
+    fn write_sample(
+        &mut self,
+        track_id: u32,
+        unit: &HubUnit,
+        data: &[u8],
+        keyframe: bool,
+    ) -> anyhow::Result<()> {
+        match self {
+            HlsWriter::Fmp4(fmp4) => {
+                // B-frame 이 있으면 decode 순서와 표시 순서가 다르므로 dts 에 cts 를 더해 표시한다.
+                let sample = mp4::Mp4Sample {
+                    start_time: unit.dts as u64,
+                    duration: unit.duration,
+                    rendering_offset: unit.pts.wrapping_sub(unit.dts) as i32,
+                    is_sync: keyframe,
+                    bytes: bytes::Bytes::copy_from_slice(data),
+                };
+                fmp4.write_sample(track_id, &sample)?;
+                Ok(())
+            }
+            HlsWriter::Ts(muxer) => {
+                // PES 의 PTS, DTS 는 90kHz
+                let timebase = unit.timebase.max(1) as u64;
+                let pts = unit.pts as u64 * 90000 / timebase;
+                let dts = unit.dts as u64 * 90000 / timebase;
+                muxer.write_frame((track_id - 1) as usize, pts, dts, keyframe, data)
+            }
+        }
+    }
+
+    // part 하나 분량의 데이터. TS 는 segment 가 끝나면 다음 segment 앞에 PAT/PMT 를 넣는다.
+    fn write_end(&mut self, end_segment: bool) -> anyhow::Result<bytes::Bytes> {
+        match self {
+            HlsWriter::Fmp4(fmp4) => {
+                let mut cursor = Cursor::new(Vec::<u8>::new());
+                fmp4.write_end(&mut cursor)?;
+                Ok(bytes::Bytes::from(cursor.into_inner()))
+            }
+            HlsWriter::Ts(muxer) => {
+                let data = muxer.take();
+                if end_segment {
+                    muxer.write_tables();
+                }
+                Ok(data)
+            }
+        }
+    }
+}
+
+// audio, video 순서로 track 을 추가한 fmp4 writer
+fn new_fmp4(audio: Option<&Codec>, video: Option<&Codec>) -> anyhow::Result<mp4::Fmp4Writer> {
+    let fmp4_config = mp4::Mp4Config {
+        major_brand: str::parse("iso5").unwrap(),
+        minor_version: 512,
+        compatible_brands: vec![
+            str::parse("iso5").unwrap(),
+            str::parse("iso6").unwrap(),
+            str::parse("mp41").unwrap(),
+        ],
+        timescale: 1000,
+    };
+    let mut fmp4 = mp4::Fmp4Writer::new(&fmp4_config)?;
+    for track_config in track_configs(audio, video)? {
+        fmp4.add_track(&track_config)?;
+    }
+    Ok(fmp4)
+}
+
+// audio, video 순서의 mp4 track 설정. 녹화에서도 같은 순서로 쓴다.
+pub fn track_configs(
+    audio: Option<&Codec>,
+    video: Option<&Codec>,
+) -> anyhow::Result<Vec<mp4::TrackConfig>> {
+    let mut configs = vec![];
+    if let Some(codec) = audio {
+        configs.push(mp4::TrackConfig {
+            track_type: mp4::TrackType::Audio,
+            timescale: codec.clock_rate(),
+            language: String::from("und"),
+            media_conf: audio_media_config(codec)?,
+        });
+    }
+    if let Some(codec) = video {
+        let (Some(sps), Some(pps)) = (codec.sps(), codec.pps()) else {
+            return Err(anyhow::anyhow!("video codec without sps/pps"));
+        };
+        log::debug!(
+            "width:{}, height:{}, sps:{:?}, pps:{:?}",
+            codec.width(),
+            codec.height(),
+            sps,
+            pps
+        );
+        configs.push(mp4::TrackConfig {
+            track_type: mp4::TrackType::Video,
+            timescale: codec.clock_rate(),
+            language: String::from("und"),
+            media_conf: mp4::MediaConfig::AvcConfig(mp4::AvcConfig {
+                width: codec.width() as u16,
+                height: codec.height() as u16,
+                seq_param_set: sps,
+                pic_param_set: pps,
+            }),
+        });
+    }
+    Ok(configs)
+}
+
+// track_configs 와 같은 순서의 TS track. legacy player 는 Opus 를 재생하지 못하므로 AAC 를 써야 한다.
+fn ts_tracks(audio: Option<&Codec>, video: Option<&Codec>) -> anyhow::Result<Vec<TsTrack>> {
+    let mut tracks = vec![];
+    if let Some(codec) = audio {
+        tracks.push(match codec {
+            Codec::Aac(aac) => TsTrack::Aac(aac.config().clone()),
+            Codec::Opus(opus) => TsTrack::Opus {
+                channels: opus.channels(),
+            },
+            _ => return Err(anyhow::anyhow!("unsupported audio codec for ts")),
+        });
+    }
+    if let Some(codec) = video {
+        let (Some(sps), Some(pps)) = (codec.sps(), codec.pps()) else {
+            return Err(anyhow::anyhow!("video codec without sps/pps"));
+        };
+        tracks.push(TsTrack::H264 {
+            sps: bytes::Bytes::from(sps),
+            pps: bytes::Bytes::from(pps),
+        });
+    }
+    Ok(tracks)
+}
+
+fn audio_media_config(codec: &Codec) -> anyhow::Result<mp4::MediaConfig> {
+    match codec {
+        Codec::Aac(aac) => {
+            let config = aac.config();
+            Ok(mp4::MediaConfig::AacConfig(mp4::AacConfig {
+                bitrate: 0,
+                profile: mp4::AudioObjectType::try_from(config.object_type)?,
+                freq_index: mp4::SampleFreqIndex::try_from(config.frequency_index)?,
+                chan_conf: mp4::ChannelConfig::try_from(config.channel_config)?,
+            }))
+        }
+        _ => Ok(mp4::MediaConfig::OpusConfig(mp4::OpusConfig {})),
+    }
 }
 
 impl SessionHandler for HlsHandler {
     type TrackContext = track_context::TrackContext;
 
     async fn on_initialize(&self) -> anyhow::Result<()> {
-        {
-            let mut fmp4 = self.fmp4.lock().await;
-            let mut cursor = Cursor::new(Vec::<u8>::new());
-            fmp4.write_header(&mut cursor)?;
-            let data = cursor.into_inner();
-            self.target.init_segment(bytes::Bytes::from(data)).await?;
+        let header = self.writer.lock().await.header()?;
+        if let Some(header) = header {
+            self.target.init_segment(header).await?;
         }
         Ok(())
     }
@@ -195,20 +564,61 @@ impl SessionHandler for HlsHandler {
         self.token.clone()
     }
 
+    // publisher 가 끝나면 남은 fragment 를 쓰고 playlist 를 VOD 로 닫는다.
     async fn on_finalize(&self) -> anyhow::Result<()> {
-        Ok(())
+        self.flush().await;
+        self.target.finish().await
     }
     fn get_sources(&self) -> Vec<Arc<HubSource>> {
         self.sources.clone()
     }
 
+    fn use_gop_cache(&self) -> bool {
+        self.gop_cache
+    }
+
     fn on_track_context(&self, idx: usize, codec: &Codec) -> Self::TrackContext {
         track_context::TrackContext::new(idx, codec)
     }
 
+    async fn on_codec_change(&self, ctx: &mut Self::TrackContext, codec: &Codec) {
+        ctx.set_codec(codec);
+        if codec.kind() == types::MediaKind::Audio {
+            *self.audio_codec.write().await = Some(codec.clone());
+        } else if codec.kind() == types::MediaKind::Video {
+            if codec.sps().is_none() || codec.pps().is_none() {
+                return;
+            }
+            *self.video_codec.write().await = Some(codec.clone());
+        }
+
+        let mut state = self.state.write().await;
+        if state.part_start.is_some() {
+            state.codec_changed = true;
+            return;
+        }
+        // 아직 part 를 만들기 전이면 init segment 만 바꾼다.
+        match self.make_writer().await {
+            Ok((writer, header)) => {
+                *self.writer.lock().await = writer;
+                if let Some(header) = header {
+                    if let Err(err) = self.target.init_segment(header).await {
+                        log::warn!("failed to write init segment: {}", err);
+                    }
+                }
+            }
+            Err(err) => log::warn!("failed to make hls writer: {}", err),
+        }
+    }
+
     async fn on_video(&self, ctx: &mut Self::TrackContext, unit: &HubUnit) {
+        let Some(video_track_id) = self.video_track_id else {
+            return;
+        };
         if !self.started.load(Ordering::Acquire) {
             if unit.frame_info.flag != 1 {
+                // 첫 segment 를 바로 시작할 수 있도록 keyframe 을 요청한다.
+                self.request_keyframe(&mut *self.state.write().await);
                 return;
             }
             self.started.store(true, Ordering::Release);
@@ -217,29 +627,23 @@ impl SessionHandler for HlsHandler {
         let Some(pkt) = ctx.make_packet(unit) else {
             return;
         };
+        let keyframe = NALUType::from_byte(unit.payload[0]) == NALUType::IDR;
 
-        {
-            if let Some(data) = pkt.data() {
-                let bytes = bytes::Bytes::copy_from_slice(data);
+        // IDR 이 새 part 의 첫 sample 이 되도록 sample 을 쓰기 전에 part 를 자른다.
+        self.write_hls_segment(&pkt, unit, keyframe).await;
 
-                let mut fmp4 = self.fmp4.lock().await;
-                let sample = mp4::Mp4Sample {
-                    start_time: unit.pts as u64,
-                    duration: unit.duration as u32,
-                    rendering_offset: 0,
-                    is_sync: false,
-                    bytes,
-                };
-                if let Err(err) = fmp4.write_sample(2, &sample) {
-                    log::warn!("failed to write sample: {}", err);
-                }
+        if let Some(data) = pkt.data() {
+            let mut writer = self.writer.lock().await;
+            if let Err(err) = writer.write_sample(video_track_id, unit, data, keyframe) {
+                log::warn!("failed to write sample: {}", err);
             }
         }
-
-        self.write_hls_segment(&pkt).await;
     }
 
     async fn on_audio(&self, ctx: &mut Self::TrackContext, unit: &HubUnit) {
+        let Some(audio_track_id) = self.audio_track_id else {
+            return;
+        };
         if !self.started.load(Ordering::Acquire) {
             return;
         }
@@ -247,22 +651,118 @@ impl SessionHandler for HlsHandler {
         let Some(pkt) = ctx.make_packet(unit) else {
             return;
         };
+        if self.video_track_id.is_none() {
+            // audio only 이면 모든 frame 이 독립적이므로 audio 로 part, segment 를 자른다.
+            self.write_hls_segment(&pkt, unit, true).await;
+        }
 
-        {
-            if let Some(data) = pkt.data() {
-                let bytes = bytes::Bytes::copy_from_slice(data);
-                let mut fmp4 = self.fmp4.lock().await;
-                let sample = mp4::Mp4Sample {
-                    start_time: unit.pts as u64,
-                    duration: unit.duration as u32,
-                    rendering_offset: 0,
-                    is_sync: false,
-                    bytes,
-                };
-                if let Err(err) = fmp4.write_sample(1, &sample) {
-                    log::warn!("failed to write sample: {}", err);
-                }
+        if let Some(data) = pkt.data() {
+            let mut writer = self.writer.lock().await;
+            if let Err(err) = writer.write_sample(audio_track_id, unit, data, false) {
+                log::warn!("failed to write sample: {}", err);
             }
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TARGET: CutTarget = CutTarget {
+        part: 200,
+        segment: 1000,
+        max_segment: 2000,
+    };
+    const FRAME_DURATION: i64 = 40;
+
+    // timescale 1000 으로 40ms 마다 frame 을 넣고 닫힌 part 를 (dts, part) 로 모은다.
+    fn run(
+        state: &mut HlsState,
+        frames: std::ops::Range<i64>,
+        keyframes: &[i64],
+    ) -> Vec<(i64, PartCut)> {
+        state.timescale = 1000;
+        frames
+            .step_by(FRAME_DURATION as usize)
+            .filter_map(|dts| {
+                let keyframe = keyframes.contains(&dts);
+                state
+                    .cut(dts, FRAME_DURATION, keyframe, dts as f64 / 1000.0, TARGET)
+                    .map(|cut| (dts, cut))
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_cut_parts_and_segments() {
+        let mut state = HlsState::new();
+        let cuts = run(&mut state, 0..1240, &[0, 400, 1200]);
+
+        // part 는 part target 을 넘기 전에 닫힌다.
+        let ends: Vec<i64> = cuts.iter().map(|(dts, _)| *dts).collect();
+        assert_eq!(ends, vec![200, 400, 600, 800, 1000, 1200]);
+        assert!(cuts.iter().all(|(_, cut)| cut.duration == 0.2));
+
+        // keyframe 으로 시작한 part 만 independent
+        let independent: Vec<bool> = cuts.iter().map(|(_, cut)| cut.independent).collect();
+        assert_eq!(independent, vec![true, false, true, false, false, false]);
+        assert_eq!(cuts[2].1.media_start, 0.4);
+
+        // segment 는 segment target 을 채운 뒤의 keyframe 에서만 끝난다.
+        let segments: Vec<bool> = cuts.iter().map(|(_, cut)| cut.end_segment).collect();
+        assert_eq!(segments, vec![false, false, false, false, false, true]);
+        assert_eq!(state.segment_start, 1200);
+        assert!(state.part_independent);
+    }
+
+    #[test]
+    fn test_cut_segment_waits_for_keyframe() {
+        let mut state = HlsState::new();
+        state.keyframe_requested = true;
+        // segment target 이 지나도 keyframe 이 오기 전까지는 part 만 닫는다.
+        let cuts = run(&mut state, 0..1600, &[0]);
+        assert!(cuts.iter().all(|(_, cut)| !cut.end_segment));
+        assert_eq!(state.segment_start, 0);
+
+        let cuts = run(&mut state, 1600..1640, &[1600]);
+        assert_eq!(cuts.len(), 1);
+        assert!(cuts[0].1.end_segment);
+        assert!(!state.keyframe_requested);
+    }
+
+    #[test]
+    fn test_cut_segment_at_target_duration() {
+        let mut state = HlsState::new();
+        state.codec_changed = true;
+        // keyframe 이 오지 않으면 TARGETDURATION 을 넘기 전에 자른다.
+        let cuts = run(&mut state, 0..2400, &[0]);
+        let segments: Vec<i64> = cuts
+            .iter()
+            .filter(|(_, cut)| cut.end_segment)
+            .map(|(dts, _)| *dts)
+            .collect();
+        assert_eq!(segments, vec![2000]);
+        let (_, cut) = cuts.iter().find(|(_, cut)| cut.end_segment).unwrap();
+        assert_eq!(cut.duration, 0.2);
+        // init segment 는 keyframe 에서 바꾼다.
+        assert!(!cut.reinit);
+        assert!(state.codec_changed);
+        assert!(!state.part_independent);
+    }
+
+    #[test]
+    fn test_cut_codec_change() {
+        let mut state = HlsState::new();
+        run(&mut state, 0..80, &[0]);
+        state.codec_changed = true;
+        // codec 이 바뀌면 segment target 전이라도 다음 keyframe 에서 끊는다.
+        let cuts = run(&mut state, 80..160, &[80]);
+        assert_eq!(cuts.len(), 1);
+        let (dts, cut) = &cuts[0];
+        assert_eq!(*dts, 80);
+        assert!(cut.end_segment && cut.reinit);
+        assert_eq!(cut.duration, 0.08);
+        assert!(!state.codec_changed);
+    }
+}
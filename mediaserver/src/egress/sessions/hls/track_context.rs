@@ -27,6 +27,10 @@ impl TrackContext {
         }
     }
 
+    pub fn set_codec(&mut self, codec: &Codec) {
+        self.codec = codec.clone();
+    }
+
     pub fn track_id(&self) -> u32 {
         (self.idx + 1) as u32
     }
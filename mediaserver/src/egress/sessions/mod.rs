@@ -1,3 +1,4 @@
 pub mod hls;
+pub mod record;
 pub mod session;
 pub mod whep;
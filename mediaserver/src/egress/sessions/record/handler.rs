@@ -0,0 +1,48 @@
+use crate::codecs::codec::Codec;
+use crate::egress::services::record::config::RecordConfig;
+use crate::egress::services::record::service::RecordService;
+use crate::egress::sessions::hls::handler::track_configs;
+use crate::egress::sessions::record::recorder::{RecordWriter, Recorder};
+use crate::utils;
+
+pub type RecordHandler = Recorder<RecordService>;
+
+impl RecordWriter for RecordService {
+    fn supports_video(codec: &Codec) -> bool {
+        codec.sps().is_some() && codec.pps().is_some()
+    }
+
+    fn config(&self) -> &RecordConfig {
+        &self.config
+    }
+
+    async fn file_size(&self) -> Option<u64> {
+        RecordService::file_size(self).await
+    }
+
+    async fn start_file(&self, audio: Option<&Codec>, video: Option<&Codec>) -> anyhow::Result<()> {
+        let configs = track_configs(audio, video)?;
+        RecordService::start_file(self, &configs).await
+    }
+
+    async fn write(&self, track: u32, pkt: &utils::packet::packet::Packet, keyframe: bool) {
+        let (Some(dts), Some(data)) = (pkt.dts, pkt.data()) else {
+            return;
+        };
+        let pts = pkt.pkt.unwrap_or(dts);
+        let sample = mp4::Mp4Sample {
+            start_time: dts as u64,
+            duration: pkt.duration() as u32,
+            rendering_offset: (pts - dts) as i32,
+            is_sync: keyframe,
+            bytes: bytes::Bytes::copy_from_slice(data),
+        };
+        if let Err(err) = self.write_sample(track, sample).await {
+            log::warn!("failed to write record sample: {}", err);
+        }
+    }
+
+    async fn finish_file(&self) -> anyhow::Result<()> {
+        RecordService::finish_file(self).await
+    }
+}
@@ -0,0 +1,105 @@
+use crate::codecs::codec::Codec;
+use crate::egress::services::record::config::RecordConfig;
+use crate::egress::services::record::mkv_service::MkvRecordService;
+use crate::egress::sessions::record::recorder::{RecordWriter, Recorder};
+use crate::protocols::matroska::muxer::{opus_head, MkvTrack, TrackKind};
+use crate::utils;
+
+// Opus decoder 가 seek 후 안정되기까지 필요한 시간(ns). WebM 권장값
+const OPUS_SEEK_PRE_ROLL: u64 = 80_000_000;
+
+pub type MkvRecordHandler = Recorder<MkvRecordService>;
+
+impl RecordWriter for MkvRecordService {
+    fn supports_video(codec: &Codec) -> bool {
+        matches!(codec, Codec::H264(_))
+    }
+
+    fn config(&self) -> &RecordConfig {
+        &self.config
+    }
+
+    async fn file_size(&self) -> Option<u64> {
+        MkvRecordService::file_size(self).await
+    }
+
+    async fn start_file(&self, audio: Option<&Codec>, video: Option<&Codec>) -> anyhow::Result<()> {
+        let (doc_type, tracks) = mkv_tracks(audio, video)?;
+        MkvRecordService::start_file(self, doc_type, &tracks).await
+    }
+
+    async fn write(&self, track: u32, pkt: &utils::packet::packet::Packet, keyframe: bool) {
+        let Some(pts) = pkt.pkt.or(pkt.dts) else {
+            return;
+        };
+        if pkt.data().is_none() {
+            return;
+        }
+        // Matroska 는 presentation time 을 ms 로 쓴다.
+        let timestamp = pts.max(0) as u64 * 1000 / pkt.time_base().den as u64;
+        if let Err(err) = self
+            .write_frame(track as u64, timestamp, keyframe, pkt.payload.clone())
+            .await
+        {
+            log::warn!("failed to write record frame: {}", err);
+        }
+    }
+
+    async fn finish_file(&self) -> anyhow::Result<()> {
+        MkvRecordService::finish_file(self).await
+    }
+}
+
+// audio 는 track 1, video 는 그 다음. Opus 만 있으면 webm 으로 쓴다.
+pub fn mkv_tracks(
+    audio: Option<&Codec>,
+    video: Option<&Codec>,
+) -> anyhow::Result<(&'static str, Vec<MkvTrack>)> {
+    let mut tracks = vec![];
+    let mut webm = true;
+    if let Some(codec) = audio {
+        let (codec_id, codec_private, channels, seek_pre_roll) = match codec {
+            Codec::Opus(opus) => (
+                "A_OPUS",
+                opus_head(opus.channels(), opus.clock_rate()),
+                opus.channels(),
+                Some(OPUS_SEEK_PRE_ROLL),
+            ),
+            Codec::Aac(aac) => {
+                webm = false;
+                ("A_AAC", aac.config().payload.clone(), aac.channels(), None)
+            }
+            _ => return Err(anyhow::anyhow!("unsupported audio codec")),
+        };
+        tracks.push(MkvTrack {
+            number: tracks.len() as u64 + 1,
+            kind: TrackKind::Audio {
+                sample_rate: codec.clock_rate(),
+                channels,
+            },
+            codec_id: codec_id.to_string(),
+            codec_private: Some(codec_private),
+            codec_delay: None,
+            seek_pre_roll,
+        });
+    }
+    if let Some(codec) = video {
+        let Codec::H264(h264) = codec else {
+            return Err(anyhow::anyhow!("unsupported video codec"));
+        };
+        // WebM 은 H.264 를 허용하지 않는다.
+        webm = false;
+        tracks.push(MkvTrack {
+            number: tracks.len() as u64 + 1,
+            kind: TrackKind::Video {
+                width: codec.width(),
+                height: codec.height(),
+            },
+            codec_id: "V_MPEG4/ISO/AVC".to_string(),
+            codec_private: Some(h264.config().extradata()),
+            codec_delay: None,
+            seek_pre_roll: None,
+        });
+    }
+    Ok((if webm { "webm" } else { "matroska" }, tracks))
+}
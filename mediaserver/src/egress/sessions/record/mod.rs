@@ -0,0 +1,3 @@
+pub mod handler;
+pub mod mkv_handler;
+pub mod recorder;
@@ -0,0 +1,243 @@
+use crate::codecs::codec::Codec;
+use crate::codecs::h264::format::NALUType;
+use crate::egress::services::record::config::RecordConfig;
+use crate::egress::sessions::hls::track_context::TrackContext;
+use crate::egress::sessions::session::{SessionHandler, CODEC_WAIT_TIMEOUT};
+use crate::hubs::source::HubSource;
+use crate::hubs::stream::HubStream;
+use crate::hubs::unit::HubUnit;
+use crate::utils;
+use crate::utils::types::types;
+use std::future::Future;
+use std::sync::Arc;
+use tokio::sync::RwLock;
+use tokio_util::sync::CancellationToken;
+
+// 녹화 container(mp4, mkv) 마다 다른 부분. 파일 나누기와 keyframe 요청은 Recorder 가 한다.
+pub trait RecordWriter: Send + Sync + 'static {
+    // 이 container 에 쓸 수 있는 video codec 인지
+    fn supports_video(codec: &Codec) -> bool;
+    fn config(&self) -> &RecordConfig;
+    // 지금 파일에 쓴 크기. 파일이 없으면 None
+    fn file_size(&self) -> impl Future<Output = Option<u64>> + Send;
+    // 지금 파일을 닫고 audio 는 track 1, video 는 그 다음 track 으로 새 파일을 시작한다.
+    fn start_file(
+        &self,
+        audio: Option<&Codec>,
+        video: Option<&Codec>,
+    ) -> impl Future<Output = anyhow::Result<()>> + Send;
+    fn write(
+        &self,
+        track: u32,
+        pkt: &utils::packet::packet::Packet,
+        keyframe: bool,
+    ) -> impl Future<Output = ()> + Send;
+    fn finish_file(&self) -> impl Future<Output = anyhow::Result<()>> + Send;
+}
+
+struct RecordState {
+    // 지금 파일이 시작된 시각(초). None 이면 아직 파일을 시작하지 않음
+    file_start: Option<f64>,
+    // codec 이 바뀌어서 다음 keyframe 에서 새 파일로 넘어가야 하는지
+    codec_changed: bool,
+    // 파일을 시작하거나 나누려고 keyframe 을 요청했는지. 새 파일을 시작하면 다시 요청할 수 있다.
+    keyframe_requested: bool,
+}
+
+pub struct Recorder<W: RecordWriter> {
+    token: CancellationToken,
+    state: RwLock<RecordState>,
+
+    sources: Vec<Arc<HubSource>>,
+    // keyframe 을 요청할 video source
+    video_source: Option<Arc<HubSource>>,
+    target: Arc<W>,
+    // source 가 없는 track 은 None
+    audio_track: Option<u32>,
+    video_track: Option<u32>,
+    audio_codec: RwLock<Option<Codec>>,
+    video_codec: RwLock<Option<Codec>>,
+    gop_cache: bool,
+}
+
+impl<W: RecordWriter> Recorder<W> {
+    pub async fn new(
+        hub_stream: &Arc<HubStream>,
+        target: Arc<W>,
+        gop_cache: bool,
+    ) -> anyhow::Result<Self> {
+        let mut sources = vec![];
+        let mut video_source = None;
+        let mut audio_codec = None;
+        let mut video_codec = None;
+        for (source, codec) in hub_stream.get_ready_sources(CODEC_WAIT_TIMEOUT).await {
+            if codec.kind() == types::MediaKind::Audio {
+                if audio_codec.is_some() {
+                    continue;
+                }
+                audio_codec = Some(codec);
+            } else if codec.kind() == types::MediaKind::Video {
+                if video_codec.is_some() || !W::supports_video(&codec) {
+                    continue;
+                }
+                video_codec = Some(codec);
+                video_source = Some(source.clone());
+            } else {
+                continue;
+            }
+            sources.push(source);
+        }
+        if sources.is_empty() {
+            return Err(anyhow::anyhow!("no supported source for record"));
+        }
+
+        let audio_track = audio_codec.as_ref().map(|_| 1);
+        let video_track = video_codec
+            .as_ref()
+            .map(|_| 1 + audio_track.is_some() as u32);
+
+        Ok(Recorder {
+            token: CancellationToken::new(),
+            state: RwLock::new(RecordState {
+                file_start: None,
+                codec_changed: false,
+                keyframe_requested: false,
+            }),
+            sources,
+            video_source,
+            target,
+            audio_track,
+            video_track,
+            audio_codec: RwLock::new(audio_codec),
+            video_codec: RwLock::new(video_codec),
+            gop_cache,
+        })
+    }
+
+    // 파일을 나누는 기준이 되는 track(video, 없으면 audio)의 sample 을 쓰기 전에 호출한다.
+    // 첫 keyframe 에서 파일을 시작하고, 길이나 크기를 넘으면 keyframe 에서 새 파일로 넘어간다.
+    async fn rollover(&self, pkt: &utils::packet::packet::Packet, keyframe: bool) -> bool {
+        let Some(dts) = pkt.dts else {
+            return false;
+        };
+        let time = dts as f64 / pkt.time_base().den as f64;
+
+        let mut state = self.state.write().await;
+        if let Some(file_start) = state.file_start {
+            let too_long = self
+                .target
+                .config()
+                .max_duration
+                .is_some_and(|max_duration| time - file_start >= max_duration.as_secs_f64());
+            if !keyframe {
+                // 나눌 때가 됐는데 keyframe 이 오지 않으면 publisher 에 요청한다.
+                if too_long || state.codec_changed {
+                    self.request_keyframe(&mut state);
+                }
+                return true;
+            }
+            let too_big = match self.target.config().max_size {
+                Some(max_size) => self.target.file_size().await.unwrap_or(0) >= max_size,
+                None => false,
+            };
+            if !too_long && !too_big && !state.codec_changed {
+                return true;
+            }
+        } else if !keyframe {
+            self.request_keyframe(&mut state);
+            return false;
+        }
+
+        let audio_codec = self.audio_codec.read().await.clone();
+        let video_codec = self.video_codec.read().await.clone();
+        if let Err(err) = self
+            .target
+            .start_file(audio_codec.as_ref(), video_codec.as_ref())
+            .await
+        {
+            log::warn!("failed to start record file: {}", err);
+            return false;
+        }
+        state.file_start = Some(time);
+        state.codec_changed = false;
+        state.keyframe_requested = false;
+        true
+    }
+
+    // keyframe 간격이 긴 publisher 도 바로 파일을 시작하고 나눌 수 있도록 한번만 요청한다.
+    fn request_keyframe(&self, state: &mut RecordState) {
+        if state.keyframe_requested {
+            return;
+        }
+        state.keyframe_requested = true;
+        if let Some(source) = self.video_source.as_ref() {
+            source.request_keyframe();
+        }
+    }
+}
+
+impl<W: RecordWriter> SessionHandler for Recorder<W> {
+    type TrackContext = TrackContext;
+
+    fn cancel_token(&self) -> CancellationToken {
+        self.token.clone()
+    }
+
+    async fn on_finalize(&self) -> anyhow::Result<()> {
+        self.target.finish_file().await
+    }
+
+    fn get_sources(&self) -> Vec<Arc<HubSource>> {
+        self.sources.clone()
+    }
+
+    fn use_gop_cache(&self) -> bool {
+        self.gop_cache
+    }
+
+    fn on_track_context(&self, idx: usize, codec: &Codec) -> Self::TrackContext {
+        TrackContext::new(idx, codec)
+    }
+
+    async fn on_codec_change(&self, ctx: &mut Self::TrackContext, codec: &Codec) {
+        ctx.set_codec(codec);
+        if codec.kind() == types::MediaKind::Audio {
+            *self.audio_codec.write().await = Some(codec.clone());
+        } else if codec.kind() == types::MediaKind::Video {
+            if !W::supports_video(codec) {
+                return;
+            }
+            *self.video_codec.write().await = Some(codec.clone());
+        }
+        // 새 codec 의 sample 은 새 파일에 쓴다.
+        self.state.write().await.codec_changed = true;
+    }
+
+    async fn on_video(&self, ctx: &mut Self::TrackContext, unit: &HubUnit) {
+        let Some(video_track) = self.video_track else {
+            return;
+        };
+        let Some(pkt) = ctx.make_packet(unit) else {
+            return;
+        };
+        let keyframe = NALUType::from_byte(unit.payload[0]) == NALUType::IDR;
+        if !self.rollover(&pkt, keyframe).await {
+            return;
+        }
+        self.target.write(video_track, &pkt, keyframe).await;
+    }
+
+    async fn on_audio(&self, ctx: &mut Self::TrackContext, unit: &HubUnit) {
+        let Some(audio_track) = self.audio_track else {
+            return;
+        };
+        let Some(pkt) = ctx.make_packet(unit) else {
+            return;
+        };
+        // audio only 이면 모든 frame 에서 파일을 나눌 수 있다.
+        if self.video_track.is_none() && !self.rollover(&pkt, true).await {
+            return;
+        }
+        self.target.write(audio_track, &pkt, true).await;
+    }
+}
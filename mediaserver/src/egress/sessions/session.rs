@@ -3,8 +3,12 @@ use crate::hubs::source::HubSource;
 use crate::hubs::unit::HubUnit;
 use crate::utils::types::types;
 use std::sync::Arc;
+use std::time::Duration;
 use tokio_util::sync::CancellationToken;
 
+// egress 를 만들 때 ingress 가 codec 을 알아낼 때까지 기다리는 최대 시간
+pub const CODEC_WAIT_TIMEOUT: Duration = Duration::from_secs(3);
+
 pub trait SessionHandler {
     type TrackContext: Send + Sync + 'static;
     fn on_initialize(&self) -> impl std::future::Future<Output = anyhow::Result<()>> + Send {
@@ -14,7 +18,19 @@ pub trait SessionHandler {
         async { Ok(()) }
     }
     fn get_sources(&self) -> Vec<Arc<HubSource>>;
+    // true 이면 HubTrack 의 GOP cache 를 live unit 보다 먼저 받는다.
+    fn use_gop_cache(&self) -> bool {
+        false
+    }
     fn on_track_context(&self, idx: usize, codec: &Codec) -> Self::TrackContext;
+    // ingress 에서 codec 이 바뀌면 그 뒤의 unit 보다 먼저 호출된다.
+    fn on_codec_change(
+        &self,
+        _ctx: &mut Self::TrackContext,
+        _codec: &Codec,
+    ) -> impl std::future::Future<Output = ()> + Send {
+        async {}
+    }
     fn on_video(
         &self,
         ctx: &mut Self::TrackContext,
@@ -64,12 +80,42 @@ where
     pub async fn run(self: &Arc<Self>) -> anyhow::Result<()> {
         self.handler.on_initialize().await?;
 
+        let use_gop_cache = self.handler.use_gop_cache();
+        let mut tracks = vec![];
+        for source in self.handler.get_sources() {
+            let mut codec_rx = source.subscribe_codec();
+            let codec = codec_rx
+                .borrow_and_update()
+                .clone()
+                .ok_or(anyhow::anyhow!("no codec"))?;
+            let track = source.get_track(&codec, use_gop_cache).await?;
+            tracks.push((source, codec_rx, codec, track));
+        }
+
+        // video 의 sink 를 먼저 만들어서 GOP cache 가 시작된 시각을 얻고, audio 는 그 시각부터 받아서 시작을 맞춘다.
+        let mut sinks = vec![None; tracks.len()];
+        let mut since = None;
+        for (idx, (_, _, codec, track)) in tracks.iter().enumerate() {
+            if codec.kind() == types::MediaKind::Video {
+                let (sink, start) = track.add_sink(None).await;
+                since = since.or(start);
+                sinks[idx] = Some(sink);
+            }
+        }
+        for (idx, (_, _, _, track)) in tracks.iter().enumerate() {
+            if sinks[idx].is_none() {
+                sinks[idx] = Some(track.add_sink(since).await.0);
+            }
+        }
+
         let mut join_handles = vec![];
-        for (idx, source) in self.handler.get_sources().iter().enumerate() {
-            let codec = source.get_codec().await.unwrap();
-            let track = source.get_track(&codec).await?;
+        for (idx, ((source, mut codec_rx, mut codec, track), sink)) in
+            tracks.into_iter().zip(sinks).enumerate()
+        {
+            let Some(sink) = sink else {
+                continue;
+            };
             let source_token = source.token();
-            let sink = track.add_sink().await;
             let cancel_token = self.token.clone();
             let self_ = self.clone();
 
@@ -78,12 +124,27 @@ where
             let handle = tokio::spawn(async move {
                 loop {
                     tokio::select! {
+                        // codec 변경은 ingress 가 새 codec 의 unit 을 보내기 전에 알리므로 unit 보다 먼저 처리한다.
+                        biased;
                         _ = source_token.cancelled() => {
                             break;
                         }
                         _ = cancel_token.cancelled() => {
                             break;
                         }
+                        result = codec_rx.changed() => {
+                            if result.is_err() {
+                                break;
+                            }
+                            let Some(new_codec) = codec_rx.borrow_and_update().clone() else {
+                                continue;
+                            };
+                            if new_codec != codec {
+                                log::info!("codec changed: {}", new_codec.mime_type());
+                                self_.handler.on_codec_change(&mut ctx, &new_codec).await;
+                                codec = new_codec;
+                            }
+                        }
                         result = sink.read_unit() => {
                              let Ok(hub_unit) = result else {
                                 log::warn!("read unit failed");
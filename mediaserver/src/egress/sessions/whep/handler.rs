@@ -1,19 +1,23 @@
 use crate::codecs::codec::Codec;
 use crate::codecs::h264::format::NALUType;
-use crate::egress::sessions::session::SessionHandler;
+use crate::codecs::rtp_payloader::RtpPayloader;
+use crate::egress::sessions::session::{SessionHandler, CODEC_WAIT_TIMEOUT};
+use crate::egress::sessions::whep::layer;
 use crate::egress::sessions::whep::local_track::LocalTrack;
+use crate::egress::sessions::whep::rtp_history::{RetransmissionStats, RtpHistory};
 use crate::egress::sessions::whep::track_context;
 use crate::hubs::source::HubSource;
 use crate::hubs::stream::HubStream;
 use crate::hubs::unit::HubUnit;
 use crate::utils::packet;
 use crate::utils::types::types;
+use crate::webrtc_wrapper::ice;
 use crate::webrtc_wrapper::webrtc_api::WebRtcApi;
 use anyhow::anyhow;
 use bitstreams::h264::nal_unit::NalUnit;
 use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
-use tokio::sync::mpsc;
+use tokio::sync::{mpsc, Mutex};
 use tokio::time;
 use tokio_util::sync::CancellationToken;
 use webrtc::api::media_engine::MediaEngine;
@@ -24,11 +28,29 @@ use webrtc::peer_connection::sdp::session_description::RTCSessionDescription;
 use webrtc::peer_connection::{
     OnPeerConnectionStateChangeHdlrFn, OnTrackHdlrFn, RTCPeerConnection,
 };
+use webrtc::rtcp::payload_feedbacks::full_intra_request::FullIntraRequest;
+use webrtc::rtcp::payload_feedbacks::picture_loss_indication::PictureLossIndication;
+use webrtc::rtcp::payload_feedbacks::receiver_estimated_maximum_bitrate::ReceiverEstimatedMaximumBitrate;
+use webrtc::rtcp::transport_feedbacks::transport_layer_nack::TransportLayerNack;
 use webrtc::rtp_transceiver::rtp_codec::{RTCRtpCodecParameters, RTPCodecType};
 use webrtc::rtp_transceiver::rtp_receiver::RTCRtpReceiver;
+use webrtc::rtp_transceiver::rtp_sender::RTCRtpSender;
+use webrtc::rtp_transceiver::RTCPFeedback;
 use webrtc::track::track_local::TrackLocalWriter;
 use webrtc::track::track_remote::TrackRemote;
 
+// simulcast layer 전환 상태. index 는 video_layers 의 순서
+struct LayerState {
+    // viewer 에게 보내고 있는 layer. 첫 keyframe 을 보내기 전에는 None
+    current: Option<usize>,
+    // 이 layer 의 keyframe 이 오면 바꾼다.
+    target: usize,
+    // client 가 고른 layer. None 이면 viewer 의 REMB 로 고른다.
+    requested: Option<usize>,
+    // 마지막으로 보낸 layer 의 다음 (sequence, timestamp). 새 layer 가 이어서 쓴다.
+    position: Option<(u16, u32)>,
+}
+
 pub struct WhepHandler {
     id: String,
 
@@ -36,27 +58,81 @@ pub struct WhepHandler {
     token: CancellationToken,
     local_track: LocalTrack,
     sources: Vec<Arc<HubSource>>,
+    // 하나의 video track 으로 보낼 수 있는 video source. simulcast 이면 해상도가 높은 순서의 layer 들
+    video_layers: Vec<Arc<HubSource>>,
+    layers: Mutex<LayerState>,
+    // viewer 의 NACK 에 재전송하기 위해 최근에 보낸 video packet 을 보관한다.
+    video_history: Arc<RtpHistory>,
+    gop_cache: bool,
 
     started: AtomicBool,
+    // GOP 중간에 들어온 viewer 가 keyframe 을 한번만 요청하도록 한다.
+    keyframe_requested: AtomicBool,
 }
 
 impl WhepHandler {
-    pub async fn new(id: &str, hub_stream: &Arc<HubStream>) -> anyhow::Result<Arc<Self>> {
+    pub async fn new(
+        id: &str,
+        hub_stream: &Arc<HubStream>,
+        gop_cache: bool,
+    ) -> anyhow::Result<Arc<Self>> {
         let token = CancellationToken::new();
-        let local_track = LocalTrack::new();
         let mut sources = vec![];
+        let mut video_layers = vec![];
+        let mut has_audio = false;
+        let mut has_video = false;
         let mut media_engine = MediaEngine::default();
-        for source in hub_stream.get_sources().await.iter() {
-            let codec = source.get_codec().await.unwrap();
+        let ready_sources = hub_stream.get_ready_sources(CODEC_WAIT_TIMEOUT).await;
+        // simulcast 이면 rid 가 있는 video source 를 모두 layer 로 받고, 아니면 첫 video 하나만 쓴다.
+        let simulcast = ready_sources.iter().any(|(source, codec)| {
+            codec.kind() == types::MediaKind::Video && source.rid().is_some()
+        });
+        for (source, codec) in ready_sources {
+            if RtpPayloader::new(&codec, codec.mime_type()).is_err() {
+                log::warn!("unsupported codec for whep: {}", codec.mime_type());
+                continue;
+            }
             let mut payload_type = 96;
             let mut kind = RTPCodecType::Video;
+            let mut capability = codec.rtp_codec_capability();
             if codec.kind() == types::MediaKind::Audio {
                 payload_type = 111;
                 kind = RTPCodecType::Audio;
+                has_audio = true;
+            } else {
+                if simulcast != source.rid().is_some() || (!simulcast && has_video) {
+                    continue;
+                }
+                video_layers.push(source.clone());
+                // layer 들은 같은 video track 으로 보내므로 codec 은 한번만 등록한다.
+                if has_video {
+                    sources.push(source);
+                    continue;
+                }
+                has_video = true;
+                capability.rtcp_feedback = vec![
+                    RTCPFeedback {
+                        typ: "nack".to_string(),
+                        parameter: "".to_string(),
+                    },
+                    RTCPFeedback {
+                        typ: "nack".to_string(),
+                        parameter: "pli".to_string(),
+                    },
+                    RTCPFeedback {
+                        typ: "ccm".to_string(),
+                        parameter: "fir".to_string(),
+                    },
+                    // layer 를 고를 수 있도록 viewer 가 REMB 로 대역폭 추정치를 보내게 한다.
+                    RTCPFeedback {
+                        typ: "goog-remb".to_string(),
+                        parameter: "".to_string(),
+                    },
+                ];
             }
             if let Err(err) = media_engine.register_codec(
                 RTCRtpCodecParameters {
-                    capability: codec.rtp_codec_capability(),
+                    capability,
                     payload_type,
                     stats_id: "".to_string(),
                 },
@@ -64,8 +140,18 @@ impl WhepHandler {
             ) {
                 log::warn!("register codec failed: {:?}", err);
             };
-            sources.push(source.clone());
+            sources.push(source);
         }
+        if sources.is_empty() {
+            return Err(anyhow!("no source for whep"));
+        }
+        let local_track = LocalTrack::new(has_audio, has_video);
+
+        let active = video_layers
+            .iter()
+            .map(|source| source.is_active())
+            .collect::<Vec<_>>();
+        let initial_layer = layer::initial_layer(&active);
 
         let api = WebRtcApi::new_with_media_engine(media_engine);
         let pc = api.new_peer_connection().await;
@@ -76,7 +162,19 @@ impl WhepHandler {
             token,
             local_track,
             sources,
-            started: AtomicBool::new(false),
+            video_layers,
+            // 처음에는 보내고 있는 가장 높은 layer 부터 보낸다.
+            layers: Mutex::new(LayerState {
+                current: None,
+                target: initial_layer,
+                requested: None,
+                position: None,
+            }),
+            video_history: RtpHistory::new(),
+            gop_cache,
+            // video 가 없으면 keyframe 을 기다리지 않는다.
+            started: AtomicBool::new(!has_video),
+            keyframe_requested: AtomicBool::new(false),
         }))
     }
 
@@ -88,15 +186,20 @@ impl WhepHandler {
             .on_peer_connection_state_change(self.on_peer_connection_state_change());
         self.pc.on_track(self.on_track());
 
-        let _video_transceiver = self
-            .pc
-            .add_transceiver_from_track(self.local_track.video_local_track.clone(), None)
-            .await?;
+        if let Some(video_local_track) = self.local_track.video_local_track.clone() {
+            let video_transceiver = self
+                .pc
+                .add_transceiver_from_track(video_local_track, None)
+                .await?;
+            self.read_rtcp(video_transceiver.sender().await);
+        }
 
-        let _audio_transceiver = self
-            .pc
-            .add_transceiver_from_track(self.local_track.audio_local_track.clone(), None)
-            .await?;
+        if let Some(audio_local_track) = self.local_track.audio_local_track.clone() {
+            let _audio_transceiver = self
+                .pc
+                .add_transceiver_from_track(audio_local_track, None)
+                .await?;
+        }
 
         self.pc
             .set_remote_description(RTCSessionDescription::offer(offer.to_string())?)
@@ -116,6 +219,118 @@ impl WhepHandler {
 
         Ok(answer_sdp.sdp)
     }
+
+    pub async fn patch(&self, sdpfrag: &str) -> anyhow::Result<Option<String>> {
+        ice::patch(&self.pc, sdpfrag).await
+    }
+
+    fn read_rtcp(self: &Arc<Self>, sender: Arc<RTCRtpSender>) {
+        let self_ = self.clone();
+        tokio::spawn(async move {
+            loop {
+                tokio::select! {
+                    _ = self_.token.cancelled() => {
+                        break;
+                    }
+                    result = sender.read_rtcp() => {
+                        let Ok((packets, _)) = result else { break };
+                        for packet in packets.iter() {
+                            if let Some(nack) = packet.as_any().downcast_ref::<TransportLayerNack>() {
+                                self_.on_nack(nack).await;
+                            } else if packet.as_any().is::<PictureLossIndication>()
+                                || packet.as_any().is::<FullIntraRequest>()
+                            {
+                                self_.request_keyframe().await;
+                            } else if let Some(remb) =
+                                packet.as_any().downcast_ref::<ReceiverEstimatedMaximumBitrate>()
+                            {
+                                self_.on_remb(remb.bitrate as u64).await;
+                            }
+                        }
+                    }
+                }
+            }
+        });
+    }
+
+    // 보내고 있는 layer 에 keyframe 을 요청한다. 아직 보내기 전이면 처음 보낼 layer
+    async fn request_keyframe(&self) {
+        let layers = self.layers.lock().await;
+        let layer = layers.current.unwrap_or(layers.target);
+        if let Some(source) = self.video_layers.get(layer) {
+            source.request_keyframe();
+        }
+    }
+
+    // viewer 의 대역폭 추정치로 layer 를 고른다. client 가 layer 를 골랐으면 그대로 둔다.
+    async fn on_remb(&self, bitrate: u64) {
+        if self.video_layers.len() < 2 {
+            return;
+        }
+        let mut layers = self.layers.lock().await;
+        if layers.requested.is_some() {
+            return;
+        }
+        let bitrates = self
+            .video_layers
+            .iter()
+            .map(|source| source.bitrate())
+            .collect::<Vec<_>>();
+        let current = layers.current.unwrap_or(layers.target);
+        let target = layer::select_layer(&bitrates, current, bitrate);
+        self.set_target(&mut layers, target);
+    }
+
+    // client 가 rid 로 layer 를 고른다. None 이면 다시 REMB 로 고른다.
+    // 지금까지 viewer 의 NACK 에 재전송한 통계
+    pub async fn retransmission_stats(&self) -> RetransmissionStats {
+        self.video_history.stats().await
+    }
+
+    pub async fn select_layer(&self, rid: Option<&str>) -> anyhow::Result<()> {
+        let mut layers = self.layers.lock().await;
+        let Some(rid) = rid else {
+            layers.requested = None;
+            return Ok(());
+        };
+        let target = self
+            .video_layers
+            .iter()
+            .position(|source| source.rid() == Some(rid))
+            .ok_or(anyhow!("unknown layer: {}", rid))?;
+        layers.requested = Some(target);
+        self.set_target(&mut layers, target);
+        Ok(())
+    }
+
+    // 다음 keyframe 에서 바꾸도록 새 layer 에 keyframe 을 요청한다.
+    fn set_target(&self, layers: &mut LayerState, target: usize) {
+        if layers.target == target {
+            return;
+        }
+        log::info!(
+            "whep {} layer target: {:?}",
+            self.id,
+            self.video_layers[target].rid()
+        );
+        layers.target = target;
+        if layers.current != Some(target) {
+            self.video_layers[target].request_keyframe();
+            self.keyframe_requested.store(true, Ordering::Release);
+        }
+    }
+
+    async fn on_nack(&self, nack: &TransportLayerNack) {
+        let Some(local_track) = self.local_track.get_local_track(types::MediaKind::Video) else {
+            return;
+        };
+        for packet in self.video_history.get_for_nack(nack).await.iter() {
+            if let Err(err) = local_track.write_rtp(packet).await {
+                log::warn!("retransmit rtp failed: {:?}", err);
+            };
+        }
+    }
+
     fn on_ice_candidate(
         self: &Arc<Self>,
         candidate_tx: mpsc::Sender<()>,
@@ -144,9 +359,8 @@ impl WhepHandler {
 
             match state {
                 RTCPeerConnectionState::Connected => {}
-                RTCPeerConnectionState::Disconnected
-                | RTCPeerConnectionState::Failed
-                | RTCPeerConnectionState::Closed => {
+                // Disconnected 는 ICE restart 로 복구될 수 있으므로 종료하지 않는다.
+                RTCPeerConnectionState::Failed | RTCPeerConnectionState::Closed => {
                     arc.token.cancel();
                 }
                 _ => {}
@@ -171,26 +385,86 @@ impl SessionHandler for WhepHandler {
         self.token.clone()
     }
 
+    async fn on_finalize(&self) -> anyhow::Result<()> {
+        log::info!(
+            "whep {} retransmission stats: {:?}",
+            self.id,
+            self.video_history.stats().await
+        );
+        self.pc.close().await?;
+        Ok(())
+    }
+
     fn get_sources(&self) -> Vec<Arc<HubSource>> {
         self.sources.clone()
     }
 
-    fn on_track_context(&self, _: usize, codec: &Codec) -> track_context::TrackContext {
-        track_context::TrackContext::new(codec)
+    fn use_gop_cache(&self) -> bool {
+        self.gop_cache
+    }
+
+    fn on_track_context(&self, idx: usize, codec: &Codec) -> track_context::TrackContext {
+        let layer = self
+            .video_layers
+            .iter()
+            .position(|source| Arc::ptr_eq(source, &self.sources[idx]));
+        track_context::TrackContext::new(codec, layer)
+    }
+
+    async fn on_codec_change(&self, ctx: &mut track_context::TrackContext, codec: &Codec) {
+        ctx.set_codec(codec);
     }
 
     async fn on_video(&self, ctx: &mut track_context::TrackContext, unit: &HubUnit) {
-        if !self.started.load(Ordering::Acquire) {
-            if unit.frame_info.flag != 1 {
-                return;
+        let Some(layer) = ctx.layer() else {
+            return;
+        };
+        let packets = {
+            let mut layers = self.layers.lock().await;
+            if layers.current != Some(layer) {
+                // target 이 멈췄으면 client 가 고른 layer 가 아닌 한 보내고 있는 layer 로 바꾼다.
+                if layer != layers.target
+                    && layers.requested.is_none()
+                    && !self.video_layers[layers.target].is_active()
+                    && layers
+                        .current
+                        .map_or(true, |current| !self.video_layers[current].is_active())
+                {
+                    self.set_target(&mut layers, layer);
+                }
+                // 다른 layer 로는 target 의 keyframe 에서만 바꾼다. 그때까지는 지금 layer 를 계속 보낸다.
+                if layer != layers.target {
+                    return;
+                }
+                if unit.frame_info.flag != 1 {
+                    if !self.keyframe_requested.swap(true, Ordering::AcqRel) {
+                        self.video_layers[layer].request_keyframe();
+                    }
+                    return;
+                }
+                if let Some(position) = layers.position {
+                    ctx.continue_from(position);
+                    log::info!(
+                        "whep {} switch layer: {:?}",
+                        self.id,
+                        self.video_layers[layer].rid()
+                    );
+                }
+                layers.current = Some(layer);
+                self.keyframe_requested.store(false, Ordering::Release);
+                self.started.store(true, Ordering::Release);
             }
-            self.started.store(true, Ordering::Release);
-        }
 
-        let Ok(packets) = ctx.make_packet(unit) else {
+            let Ok(packets) = ctx.make_packet(unit) else {
+                return;
+            };
+            layers.position = Some(ctx.position());
+            packets
+        };
+        self.video_history.push(&packets).await;
+        let Some(local_track) = self.local_track.get_local_track(types::MediaKind::Video) else {
             return;
         };
-        let local_track = self.local_track.get_local_track(types::MediaKind::Video);
         for packet in packets.iter() {
             if let Err(err) = local_track.write_rtp(packet).await {
                 log::warn!("write rtp failed: {:?}", err);
@@ -206,7 +480,9 @@ impl SessionHandler for WhepHandler {
         let Ok(packets) = ctx.make_packet(unit) else {
             return;
         };
-        let local_track = self.local_track.get_local_track(types::MediaKind::Audio);
+        let Some(local_track) = self.local_track.get_local_track(types::MediaKind::Audio) else {
+            return;
+        };
         for packet in packets.iter() {
             // println!("write audio rtp sn:{}, ts:{}", packet.header.sequence_number, packet.header.timestamp);
             if let Err(err) = local_track.write_rtp(packet).await {
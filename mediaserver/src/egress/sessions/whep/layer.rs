@@ -0,0 +1,66 @@
+// 높은 layer 로 올릴 때는 viewer 의 추정치가 bitrate 보다 이만큼 여유가 있어야 한다. 오르내림이 반복되지 않도록
+const UPGRADE_MARGIN: f64 = 1.2;
+
+// viewer 의 대역폭 추정치(bps)로 보낼 simulcast layer 를 고른다. bitrates 는 해상도가 높은 순서
+// 지금 layer 보다 낮은 layer 는 추정치 안에 들어오면 바로 내리고, 높은 layer 는 margin 이 있을 때만 올린다.
+// bitrate 가 0 인 layer 는 publisher 가 멈춘 것이므로 고르지 않는다.
+pub fn select_layer(bitrates: &[u64], current: usize, estimate: u64) -> usize {
+    let estimate = estimate as f64;
+    let mut lowest = None;
+    for (index, bitrate) in bitrates.iter().enumerate() {
+        if *bitrate == 0 {
+            continue;
+        }
+        lowest = Some(index);
+        let required = if index < current {
+            *bitrate as f64 * UPGRADE_MARGIN
+        } else {
+            *bitrate as f64
+        };
+        if required <= estimate {
+            return index;
+        }
+    }
+    // 가장 낮은 layer 도 넘치면 그것이라도 보낸다. 보내는 layer 가 없으면 그대로 둔다.
+    lowest.unwrap_or(current)
+}
+
+// 처음 보낼 layer. 해상도가 높은 순서로 보내고 있는 첫 layer, 없으면 가장 높은 layer
+pub fn initial_layer(active: &[bool]) -> usize {
+    active.iter().position(|active| *active).unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_select_layer() {
+        let bitrates = [2_500_000, 1_000_000, 300_000];
+        // 내릴 때는 추정치 안에 들어오는 가장 높은 layer
+        assert_eq!(select_layer(&bitrates, 0, 1_500_000), 1);
+        assert_eq!(select_layer(&bitrates, 0, 100_000), 2);
+        // 올릴 때는 margin 이 필요하다.
+        assert_eq!(select_layer(&bitrates, 1, 2_600_000), 1);
+        assert_eq!(select_layer(&bitrates, 1, 3_000_000), 0);
+        assert_eq!(select_layer(&bitrates, 2, 1_100_000), 2);
+        assert_eq!(select_layer(&bitrates, 2, 1_200_000), 1);
+    }
+
+    #[test]
+    fn test_select_layer_skip_paused() {
+        // 가장 높은 layer 가 멈췄다.
+        let bitrates = [0, 1_000_000, 300_000];
+        assert_eq!(select_layer(&bitrates, 0, 5_000_000), 1);
+        assert_eq!(select_layer(&bitrates, 1, 500_000), 2);
+        // 가장 낮은 layer 가 멈췄으면 그 다음으로 낮은 layer 를 보낸다.
+        let bitrates = [2_500_000, 1_000_000, 0];
+        assert_eq!(select_layer(&bitrates, 2, 100_000), 1);
+        // 모두 멈췄으면 바꾸지 않는다.
+        assert_eq!(select_layer(&[0, 0, 0], 1, 100_000), 1);
+
+        assert_eq!(initial_layer(&[false, true, true]), 1);
+        assert_eq!(initial_layer(&[true, true, true]), 0);
+        assert_eq!(initial_layer(&[false, false, false]), 0);
+    }
+}
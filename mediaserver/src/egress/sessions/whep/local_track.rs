@@ -3,13 +3,14 @@ use std::sync::Arc;
 use webrtc::rtp_transceiver::rtp_codec::RTCRtpCodecCapability;
 use webrtc::track::track_local::track_local_static_rtp::TrackLocalStaticRTP;
 
+// source 가 있는 kind 만 track 을 만든다.
 pub struct LocalTrack {
-    pub audio_local_track: Arc<TrackLocalStaticRTP>,
-    pub video_local_track: Arc<TrackLocalStaticRTP>,
+    pub audio_local_track: Option<Arc<TrackLocalStaticRTP>>,
+    pub video_local_track: Option<Arc<TrackLocalStaticRTP>>,
 }
 
 impl LocalTrack {
-    pub fn new() -> LocalTrack {
+    pub fn new(has_audio: bool, has_video: bool) -> LocalTrack {
         let stream_id = uuid::Uuid::new_v4().to_string();
         let video_track_id = uuid::Uuid::new_v4().to_string();
         let audio_track_id = uuid::Uuid::new_v4().to_string();
@@ -22,11 +23,13 @@ impl LocalTrack {
                 .to_string(),
             rtcp_feedback: vec![],
         };
-        let video_local_track = Arc::new(TrackLocalStaticRTP::new(
-            video_codec,
-            video_track_id,
-            stream_id.to_string(),
-        ));
+        let video_local_track = has_video.then(|| {
+            Arc::new(TrackLocalStaticRTP::new(
+                video_codec,
+                video_track_id,
+                stream_id.to_string(),
+            ))
+        });
 
         let audio_codec = RTCRtpCodecCapability {
             mime_type: "audio/opus".to_string(),
@@ -35,17 +38,19 @@ impl LocalTrack {
             sdp_fmtp_line: "minptime=10;useinbandfec=1".to_string(),
             rtcp_feedback: vec![],
         };
-        let audio_local_track = Arc::new(TrackLocalStaticRTP::new(
-            audio_codec,
-            audio_track_id,
-            stream_id.to_string(),
-        ));
+        let audio_local_track = has_audio.then(|| {
+            Arc::new(TrackLocalStaticRTP::new(
+                audio_codec,
+                audio_track_id,
+                stream_id.to_string(),
+            ))
+        });
         LocalTrack {
             audio_local_track,
             video_local_track,
         }
     }
-    pub fn get_local_track(&self, kind: types::MediaKind) -> Arc<TrackLocalStaticRTP> {
+    pub fn get_local_track(&self, kind: types::MediaKind) -> Option<Arc<TrackLocalStaticRTP>> {
         match kind {
             types::MediaKind::Audio => self.audio_local_track.clone(),
             types::MediaKind::Video => self.video_local_track.clone(),
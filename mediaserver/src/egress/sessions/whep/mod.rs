@@ -1,3 +1,5 @@
 pub mod handler;
+mod layer;
 pub mod local_track;
+mod rtp_history;
 mod track_context;
@@ -0,0 +1,64 @@
+use std::sync::Arc;
+use tokio::sync::RwLock;
+use webrtc::rtcp::transport_feedbacks::transport_layer_nack::TransportLayerNack;
+use webrtc::rtp::packet::Packet;
+
+// 보관하는 최근 packet 개수. sequence number 로 바로 찾을 수 있도록 2의 거듭제곱을 사용한다.
+const HISTORY_SIZE: usize = 1024;
+
+#[derive(Debug, Default, Clone, Copy)]
+pub struct RetransmissionStats {
+    pub nack_received: u64,
+    pub requested: u64,
+    pub retransmitted: u64,
+    // history 에서 이미 밀려나서 재전송하지 못한 packet 수
+    pub missed: u64,
+}
+
+// viewer 에게 최근에 보낸 RTP packet 을 NACK 에 재전송할 수 있도록 보관한다.
+pub struct RtpHistory {
+    packets: RwLock<Vec<Option<Packet>>>,
+    stats: RwLock<RetransmissionStats>,
+}
+
+impl RtpHistory {
+    pub fn new() -> Arc<Self> {
+        Arc::new(RtpHistory {
+            packets: RwLock::new(vec![None; HISTORY_SIZE]),
+            stats: RwLock::new(RetransmissionStats::default()),
+        })
+    }
+
+    pub async fn push(&self, packets: &[Packet]) {
+        let mut history = self.packets.write().await;
+        for packet in packets {
+            let index = packet.header.sequence_number as usize % HISTORY_SIZE;
+            history[index] = Some(packet.clone());
+        }
+    }
+
+    // NACK 이 요청한 packet 들 중 history 에 남아있는 것을 돌려준다.
+    pub async fn get_for_nack(&self, nack: &TransportLayerNack) -> Vec<Packet> {
+        let history = self.packets.read().await;
+        let mut stats = self.stats.write().await;
+        stats.nack_received += 1;
+
+        let mut packets = vec![];
+        for sn in nack.nacks.iter().flat_map(|pair| pair.packet_list()) {
+            stats.requested += 1;
+            let index = sn as usize % HISTORY_SIZE;
+            match &history[index] {
+                Some(packet) if packet.header.sequence_number == sn => {
+                    stats.retransmitted += 1;
+                    packets.push(packet.clone());
+                }
+                _ => stats.missed += 1,
+            }
+        }
+        packets
+    }
+
+    pub async fn stats(&self) -> RetransmissionStats {
+        *self.stats.read().await
+    }
+}
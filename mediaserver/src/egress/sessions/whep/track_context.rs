@@ -7,18 +7,35 @@ use webrtc::rtp::packet::Packet;
 pub struct TrackContext {
     codec: Codec,
     rtp_packetizer: RtpPacketizer,
+    // video 이면 handler 의 simulcast layer index
+    layer: Option<usize>,
 }
 
 impl TrackContext {
-    pub fn new(codec: &Codec) -> Self {
+    pub fn new(codec: &Codec, layer: Option<usize>) -> Self {
         let payloader = RtpPayloader::new(codec, codec.mime_type()).unwrap();
         let rtp_packetizer = RtpPacketizer::new(payloader, codec, codec.clock_rate());
 
         TrackContext {
             codec: codec.clone(),
             rtp_packetizer,
+            layer,
         }
     }
+    pub fn layer(&self) -> Option<usize> {
+        self.layer
+    }
+    pub fn position(&self) -> (u16, u32) {
+        self.rtp_packetizer.position()
+    }
+    pub fn continue_from(&mut self, position: (u16, u32)) {
+        self.rtp_packetizer.continue_from(position);
+    }
+    pub fn set_codec(&mut self, codec: &Codec) {
+        self.rtp_packetizer.set_codec(codec);
+        self.codec = codec.clone();
+    }
+
     pub fn make_packet(&mut self, unit: &HubUnit) -> anyhow::Result<Vec<Packet>> {
         self.rtp_packetizer.packetize(&unit.payload, unit.duration)
     }
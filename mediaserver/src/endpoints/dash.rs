@@ -0,0 +1,135 @@
+use crate::egress::servers::hls::HlsSession;
+use crate::egress::services::hls::config::HlsFormat;
+use crate::egress::services::hls::service::HlsService;
+use crate::endpoints::Container;
+use actix_web::{http, web, HttpResponse};
+use std::sync::Arc;
+
+const MANIFEST_MPD: &str = "manifest.mpd";
+
+// HLS session 의 fmp4 segment 를 그대로 DASH 로 보낸다.
+pub async fn handle_get_dash(
+    handler: web::Data<Container>,
+    path: web::Path<(String, String)>,
+) -> actix_web::Result<HttpResponse> {
+    let (session_id, filename) = path.into_inner();
+
+    log::info!(
+        "get dash body streamID:{}, filename:{} messageType:request",
+        session_id,
+        filename
+    );
+
+    let session = match handler.hls_server.get_session(&session_id).await {
+        Ok(session) => session,
+        Err(err) => {
+            log::error!("get dash error:{}", err);
+            return Err(actix_web::error::ErrorNotFound("Session not found"));
+        }
+    };
+
+    serve_dash(&session, &filename).await
+}
+
+// hls 와 같은 stream 단위 공유 session 으로 보낸다.
+pub async fn handle_get_stream_dash(
+    handler: web::Data<Container>,
+    path: web::Path<(String, String)>,
+) -> actix_web::Result<HttpResponse> {
+    let (stream_id, filename) = path.into_inner();
+
+    log::info!(
+        "get dash stream body streamID:{}, filename:{} messageType:request",
+        stream_id,
+        filename
+    );
+
+    let session = match handler.hls_server.get_stream_session(&stream_id).await {
+        Ok(session) => session,
+        Err(err) => {
+            log::error!("get dash stream error:{}", err);
+            return Err(actix_web::error::ErrorNotFound("Stream not found"));
+        }
+    };
+
+    serve_dash(&session, &filename).await
+}
+
+async fn serve_dash(session: &HlsSession, filename: &str) -> actix_web::Result<HttpResponse> {
+    // simulcast 이면 가장 높은 layer 하나로 보낸다.
+    let variant = session.primary();
+    // TS segment 는 DASH 로 보낼 수 없다.
+    if variant.config.format != HlsFormat::Fmp4 {
+        return Err(actix_web::error::ErrorBadRequest(
+            "dash needs fmp4 segments",
+        ));
+    }
+
+    if filename == MANIFEST_MPD {
+        // SegmentTimeline 을 만들 수 있도록 첫 segment 가 끝날 때까지 기다린다.
+        if !variant.service.wait_for(0, None).await {
+            return Err(actix_web::error::ErrorServiceUnavailable("no segment yet"));
+        }
+        let manifest = variant
+            .service
+            .dash_manifest()
+            .await
+            .map_err(actix_web::error::ErrorInternalServerError)?;
+        return Ok(HttpResponse::Ok()
+            .content_type("application/dash+xml")
+            .insert_header((http::header::CACHE_CONTROL, "no-cache"))
+            .body(manifest));
+    }
+
+    let Some(content_type) = content_type(filename) else {
+        return Err(actix_web::error::ErrorBadRequest("Bad request"));
+    };
+
+    // 만들고 있는 segment 는 part 가 만들어지는 대로 chunked transfer 로 보낸다.
+    if let Some(segment_index) = variant.config.parse_segment_filename(filename) {
+        if variant.service.is_producing(segment_index).await {
+            return Ok(HttpResponse::Ok()
+                .content_type(content_type)
+                .insert_header((http::header::CACHE_CONTROL, "no-cache"))
+                .streaming(stream_segment(variant.service.clone(), segment_index)));
+        }
+    }
+
+    let data = match variant.service.read(filename).await {
+        Ok(Some(data)) => data,
+        Ok(None) => return Err(actix_web::error::ErrorNotFound("File not found")),
+        Err(err) => {
+            log::error!("failed to read dash file: {}", err);
+            return Err(actix_web::error::ErrorNotFound("File not found"));
+        }
+    };
+
+    Ok(HttpResponse::Ok()
+        .content_type(content_type)
+        .insert_header((http::header::CACHE_CONTROL, "max-age=3600, public"))
+        .body(data))
+}
+
+// segment 의 part 를 0 번부터 차례로 내보낸다. segment 가 끝나면 stream 도 끝난다.
+fn stream_segment(
+    service: Arc<HlsService>,
+    segment_index: i32,
+) -> impl futures::Stream<Item = Result<bytes::Bytes, actix_web::Error>> {
+    futures::stream::unfold(0, move |part_index| {
+        let service = service.clone();
+        async move {
+            let part = service.read_part(segment_index, part_index).await?;
+            Some((Ok(part), part_index + 1))
+        }
+    })
+}
+
+fn content_type(filename: &str) -> Option<&'static str> {
+    if filename.ends_with(".mp4") {
+        Some("video/mp4")
+    } else if filename.ends_with(".m4s") {
+        Some("video/iso.segment")
+    } else {
+        None
+    }
+}
@@ -1,6 +1,7 @@
+use crate::egress::servers::hls::HlsSession;
+use crate::egress::services::hls::config::HlsFormat;
 use crate::endpoints::Container;
-use actix_files::NamedFile;
-use actix_web::{http, web, HttpRequest, HttpResponse, Responder};
+use actix_web::{http, web, HttpResponse, Responder};
 use actix_web_httpauth::extractors::bearer::BearerAuth;
 use serde::{Deserialize, Serialize};
 
@@ -10,18 +11,32 @@ struct HlsResponse {
     session_id: String,
 }
 
+#[derive(Deserialize)]
+pub struct CreateHlsQuery {
+    // fmp4(LL-HLS) 또는 ts. 없으면 config 의 hls.format
+    format: Option<String>,
+}
+
 pub async fn handle_create_session(
     handler: web::Data<Container>,
     auth: BearerAuth,
+    query: web::Query<CreateHlsQuery>,
 ) -> impl Responder {
     let token = auth.token().to_owned();
+    let format = match query.format.as_deref().map(HlsFormat::from).transpose() {
+        Ok(format) => format,
+        Err(e) => {
+            log::error!("hls error:{}", e);
+            return HttpResponse::BadRequest().finish();
+        }
+    };
 
     log::info!(
         "hls_server file body streamID:{}, messageType:request",
         token,
     );
 
-    let session_id = match handler.hls_server.start_session(&token).await {
+    let session_id = match handler.hls_server.start_session(&token, format).await {
         Ok(session_id) => session_id,
         Err(e) => {
             log::error!("hls error:{}", e);
@@ -75,10 +90,12 @@ pub struct HlsQuery {
     pub _hls_msn: Option<u32>,
     #[serde(rename = "_HLS_part")]
     pub _hls_part: Option<u32>,
+    // YES 또는 v2 이면 delta playlist 로 응답한다.
+    #[serde(rename = "_HLS_skip")]
+    pub _hls_skip: Option<String>,
 }
 
 pub async fn handle_get_hls(
-    req: HttpRequest,
     handler: web::Data<Container>,
     path: web::Path<(String, String)>,
     query: web::Query<HlsQuery>,
@@ -100,53 +117,130 @@ pub async fn handle_get_hls(
         }
     };
 
-    let hls_path = _session.config.clone();
-    let Ok(filepath) = hls_path.get_path(&filename) else {
-        log::error!("failed to get path");
-        return Err(actix_web::error::ErrorNotFound("File not found"));
-    };
+    serve_hls(&_session, &filename, query).await
+}
+
+// 모든 viewer 가 같이 쓰는 stream 단위 session. 첫 요청에 시작하고 요청이 없으면 정리된다.
+pub async fn handle_get_stream_hls(
+    handler: web::Data<Container>,
+    path: web::Path<(String, String)>,
+    query: web::Query<HlsQuery>,
+) -> actix_web::Result<HttpResponse> {
+    let (stream_id, filename) = path.into_inner();
+
+    log::info!(
+        "get hls stream body streamID:{}, filename:{} messageType:request",
+        stream_id,
+        filename
+    );
+    let query = query.into_inner();
 
-    if let (Some(msn), Some(part)) = (query._hls_msn, query._hls_part) {
-        // 두 값이 모두 있는 경우 처리
-        let mut rx = _session.service.subscribe_signal();
-        if rx.changed().await.is_ok() {
-            let (recv_msn, recv_part) = *rx.borrow();
+    let _session = match handler.hls_server.get_stream_session(&stream_id).await {
+        Ok(session) => session,
+        Err(err) => {
+            log::error!("get hls stream error:{}", err);
+            return Err(actix_web::error::ErrorNotFound("Stream not found"));
         }
     };
 
-    if !filename.ends_with(".m3u8") && !filename.ends_with(".mp4") && !filename.ends_with(".m4s") {
+    serve_hls(&_session, &filename, query).await
+}
+
+async fn serve_hls(
+    _session: &HlsSession,
+    filename: &str,
+    query: HlsQuery,
+) -> actix_web::Result<HttpResponse> {
+    // simulcast 이면 filename 의 layer 가 응답한다. master playlist 는 첫 layer 가 쓴다.
+    let variant = _session.variant(filename);
+    let hls_path = variant.config.clone();
+    let Some(content_type) = content_type(filename) else {
         return Err(actix_web::error::ErrorBadRequest("Bad request"));
+    };
+
+    if filename.ends_with(&hls_path.video_m3u8_path()) {
+        // blocking playlist reload: 요청한 msn/part 가 playlist 에 들어갈 때까지 응답을 보류한다.
+        match (query._hls_msn, query._hls_part) {
+            (None, Some(_)) => {
+                return Err(actix_web::error::ErrorBadRequest(
+                    "_HLS_part without _HLS_msn",
+                ));
+            }
+            (Some(msn), part) => {
+                let msn = msn as i32;
+                let part = part.map(|part| part as i32);
+                if variant.service.is_too_far(msn, part).await {
+                    return Err(actix_web::error::ErrorBadRequest("too far in the future"));
+                }
+                if !variant.service.wait_for(msn, part).await {
+                    return Err(actix_web::error::ErrorServiceUnavailable(
+                        "blocking reload timeout",
+                    ));
+                }
+            }
+            (None, None) => {
+                // 막 시작한 session 이면 첫 part 가 playlist 에 들어갈 때까지 기다린다.
+                let _ = variant.service.wait_for(0, Some(0)).await;
+            }
+        }
+    } else if let Some((segment_index, part_index)) = hls_path.parse_part_filename(filename) {
+        // preload hint 로 알려준 part 는 만들어질 때까지 기다렸다가 응답한다.
+        if !variant
+            .service
+            .wait_for_part(segment_index, part_index)
+            .await
+        {
+            return Err(actix_web::error::ErrorNotFound("File not found"));
+        }
     }
 
-    if filename.ends_with(".m3u8") {
-        let v = tokio::fs::read_to_string(filepath.to_string()).await?;
-    } else if filename.ends_with(".mp4") || filename.ends_with(".m4s") {
-        let v = tokio::fs::read(filepath.to_string()).await?;
+    let skip = matches!(query._hls_skip.as_deref(), Some("YES") | Some("v2"));
+    if skip && filename.ends_with(&hls_path.video_m3u8_path()) {
+        let delta = variant
+            .service
+            .delta_playlist()
+            .await
+            .map_err(actix_web::error::ErrorInternalServerError)?;
+        return Ok(HttpResponse::Ok()
+            .content_type(content_type)
+            .insert_header((http::header::CACHE_CONTROL, "max-age=1, public"))
+            .body(delta));
     }
 
     let cache_control = if filename.ends_with(".m3u8") {
         "max-age=1, public"
-    } else if filename.ends_with(".mp4") || filename.ends_with(".m4s") {
+    } else if filename.ends_with(".mp4") || filename.ends_with(".m4s") || filename.ends_with(".ts")
+    {
         "max-age=3600, public"
     } else {
         "no-cache"
     };
 
-    // HttpResponse로 변환 후 헤더 추가
-    let response = NamedFile::open(filepath)?
-        .use_last_modified(true)
-        .prefer_utf8(true)
-        .into_response(&req)
-        .map_into_boxed_body()
-        .customize()
-        .insert_header((http::header::CACHE_CONTROL, cache_control));
-
-    Ok(response.respond_to(&req).map_into_boxed_body())
-    // let new_resp = NamedFile::open(filepath)?
-    //     .customize()
-    //     .respond_to(res.request())
-    //     .map_into_boxed_body()
-    //     .map_into_right_body();
-
-    // Ok(response)
+    let data = match variant.service.read(filename).await {
+        Ok(Some(data)) => data,
+        Ok(None) => return Err(actix_web::error::ErrorNotFound("File not found")),
+        Err(err) => {
+            log::error!("failed to read hls file: {}", err);
+            return Err(actix_web::error::ErrorNotFound("File not found"));
+        }
+    };
+
+    Ok(HttpResponse::Ok()
+        .content_type(content_type)
+        .insert_header((http::header::CACHE_CONTROL, cache_control))
+        .body(data))
+}
+
+fn content_type(filename: &str) -> Option<&'static str> {
+    if filename.ends_with(".m3u8") {
+        Some("application/vnd.apple.mpegurl")
+    } else if filename.ends_with(".mp4") {
+        Some("video/mp4")
+    } else if filename.ends_with(".m4s") {
+        Some("video/iso.segment")
+    } else if filename.ends_with(".ts") {
+        Some("video/mp2t")
+    } else {
+        None
+    }
 }
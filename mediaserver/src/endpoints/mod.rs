@@ -1,23 +1,110 @@
 use crate::hubs::hub::Hub;
+use crate::webrtc_wrapper::ice::SDPFRAG_CONTENT_TYPE;
 use crate::{egress, ingress};
 use actix_web::dev::{ServiceRequest, ServiceResponse};
 use actix_web::middleware::{from_fn, Logger};
 use actix_web::Error;
-use actix_web::{web, App, HttpRequest, HttpServer};
+use actix_web::{web, App, HttpResponse, HttpServer};
+use config::Config;
 use std::sync::Arc;
+use std::time::Duration;
 
+mod dash;
 pub mod error;
 mod hls;
+mod record;
+mod rtsp;
 pub mod whep;
 pub mod whip;
 
-pub async fn build(hub: Arc<Hub>) -> std::io::Result<()> {
+pub async fn build(hub: Arc<Hub>, config: Arc<Config>) -> std::io::Result<()> {
+    let whip_jitter_latency =
+        Duration::from_millis(config.get::<u64>("whip.jitter_latency_ms").unwrap_or(100));
+    // 0 이면 주기적인 PLI 를 보내지 않고 egress 의 요청이 있을 때만 보낸다.
+    let whip_pli_interval = config
+        .get::<u64>("whip.pli_interval_ms")
+        .ok()
+        .filter(|interval| *interval > 0)
+        .map(Duration::from_millis);
+    let gop_cache = config.get::<bool>("hub.gop_cache").unwrap_or(true);
+    let hls_idle_timeout = Duration::from_secs(
+        config
+            .get::<u64>("hls.idle_timeout_secs")
+            .unwrap_or(30)
+            .max(1),
+    );
+    // 0 이면 DVR 없이 live 구간만 남긴다.
+    let hls_dvr_window = config
+        .get::<u64>("hls.dvr_window_secs")
+        .ok()
+        .filter(|window| *window > 0)
+        .map(Duration::from_secs);
+    // fmp4(LL-HLS) 또는 ts
+    let hls_format = egress::services::hls::config::HlsFormat::from(
+        &config
+            .get::<String>("hls.format")
+            .unwrap_or("fmp4".to_string()),
+    )
+    .unwrap_or(egress::services::hls::config::HlsFormat::Fmp4);
+    let hls_vod_retention =
+        Duration::from_secs(config.get::<u64>("hls.vod_retention_secs").unwrap_or(600));
+    // memory 또는 file
+    let hls_store = egress::services::hls::store::new_store(
+        &config
+            .get::<String>("hls.store")
+            .unwrap_or("memory".to_string()),
+    );
+    // 0 이면 제한 없이 한 파일에 기록한다.
+    let record_max_duration = config
+        .get::<u64>("record.max_duration_secs")
+        .ok()
+        .filter(|duration| *duration > 0)
+        .map(Duration::from_secs);
+    let record_max_size = config
+        .get::<u64>("record.max_size_mb")
+        .ok()
+        .filter(|size| *size > 0)
+        .map(|size| size * 1024 * 1024);
+    // worker 마다 만들지 않도록 밖에서 하나만 만든다.
+    let record_server = egress::servers::record::RecordServer::new(
+        hub.clone(),
+        gop_cache,
+        egress::servers::record::RecordFormat::from(
+            &config
+                .get::<String>("record.format")
+                .unwrap_or("mp4".to_string()),
+        )
+        .unwrap_or(egress::servers::record::RecordFormat::Mp4),
+        config
+            .get::<String>("record.dir")
+            .unwrap_or("public/record".to_string()),
+        record_max_duration,
+        record_max_size,
+    );
+    if config.get::<bool>("record.auto_start").unwrap_or(false) {
+        record_server.auto_start();
+    }
+
     HttpServer::new(move || {
         App::new()
             .app_data(web::Data::new(Container {
-                whip_server: ingress::servers::whip::WhipServer::new(hub.clone()),
-                whep_server: egress::servers::whep::WhepServer::new(hub.clone()),
-                hls_server: egress::servers::hls::HlsServer::new(hub.clone()),
+                whip_server: ingress::servers::whip::WhipServer::new(
+                    hub.clone(),
+                    whip_jitter_latency,
+                    whip_pli_interval,
+                ),
+                rtsp_server: ingress::servers::rtsp::RtspServer::new(hub.clone()),
+                whep_server: egress::servers::whep::WhepServer::new(hub.clone(), gop_cache),
+                hls_server: egress::servers::hls::HlsServer::new(
+                    hub.clone(),
+                    gop_cache,
+                    hls_store.clone(),
+                    hls_format,
+                    hls_idle_timeout,
+                    hls_dvr_window,
+                    hls_vod_retention,
+                ),
+                record_server: record_server.clone(),
             }))
             .wrap(from_fn(my_middleware))
             .wrap(Logger::default())
@@ -31,26 +118,80 @@ pub async fn build(hub: Arc<Hub>) -> std::io::Result<()> {
 
 fn routes(app: &mut web::ServiceConfig) {
     app.service(web::resource("/v1/whip").route(web::post().to(whip::handle_whip)))
+        .service(
+            web::resource("/v1/whip/{session_id}")
+                .route(web::patch().to(whip::handle_patch_whip))
+                .route(web::delete().to(whip::handle_delete_whip)),
+        )
         .service(web::resource("/v1/whep").route(web::post().to(whep::handle_whep)))
+        .service(
+            web::resource("/v1/whep/{session_id}")
+                .route(web::patch().to(whep::handle_patch_whep))
+                .route(web::delete().to(whep::handle_delete_whep))
+                .route(web::get().to(whep::handle_get_whep)),
+        )
+        // ex) PATCH {"encodingId":"h"}
+        .service(
+            web::resource("/v1/whep/{session_id}/layer")
+                .route(web::patch().to(whep::handle_patch_whep_layer)),
+        )
+        .service(web::resource("/v1/rtsp").route(web::post().to(rtsp::handle_start_session)))
+        .service(
+            web::resource("/v1/rtsp/{session_id}")
+                .route(web::delete().to(rtsp::handle_stop_session)),
+        )
         .service(web::resource("/v1/hls").route(web::post().to(hls::handle_create_session)))
         .service(
             web::resource("/v1/hls/{session_id}")
                 .route(web::delete().to(hls::handle_delete_session))
                 .route(web::get().to(hls::handle_get_session)),
         )
+        // /v1/public/hls/{session_id} 보다 먼저 등록해야 stream 이 session_id 로 잡히지 않는다.
         .service(
-            web::resource("/v1/public/hls/{session_id}/{filename:.*}").route(web::get().to(
-                |req: HttpRequest, handler: web::Data<Container>, path, query| {
-                    hls::handle_get_hls(req, handler, path, query)
-                },
-            )),
+            web::resource("/v1/public/hls/stream/{stream_id}/{filename:.*}")
+                .route(web::get().to(hls::handle_get_stream_hls)),
+        )
+        .service(
+            web::resource("/v1/public/hls/{session_id}/{filename:.*}")
+                .route(web::get().to(hls::handle_get_hls)),
+        )
+        // hls session 의 fmp4 segment 를 DASH 로 보낸다. ex) /v1/public/dash/{session_id}/manifest.mpd
+        .service(
+            web::resource("/v1/public/dash/stream/{stream_id}/{filename:.*}")
+                .route(web::get().to(dash::handle_get_stream_dash)),
+        )
+        .service(
+            web::resource("/v1/public/dash/{session_id}/{filename:.*}")
+                .route(web::get().to(dash::handle_get_dash)),
+        )
+        .service(
+            web::resource("/v1/record/{stream_id}")
+                .route(web::post().to(record::handle_start_record))
+                .route(web::delete().to(record::handle_stop_record))
+                .route(web::get().to(record::handle_get_record)),
         );
 }
 
+// WHIP/WHEP PATCH 응답. ICE restart 인 경우에만 새 sdpfrag 를 돌려준다.
+fn sdpfrag_response(result: anyhow::Result<Option<String>>) -> HttpResponse {
+    match result {
+        Ok(Some(sdpfrag)) => HttpResponse::Ok()
+            .insert_header(("Content-Type", SDPFRAG_CONTENT_TYPE))
+            .body(sdpfrag),
+        Ok(None) => HttpResponse::NoContent().finish(),
+        Err(e) => {
+            log::error!("patch error:{}", e);
+            HttpResponse::BadRequest().finish()
+        }
+    }
+}
+
 pub struct Container {
     pub whip_server: Arc<ingress::servers::whip::WhipServer>,
+    pub rtsp_server: Arc<ingress::servers::rtsp::RtspServer>,
     pub whep_server: Arc<egress::servers::whep::WhepServer>,
     pub hls_server: Arc<egress::servers::hls::HlsServer>,
+    pub record_server: Arc<egress::servers::record::RecordServer>,
 }
 
 async fn my_middleware(
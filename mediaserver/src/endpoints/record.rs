@@ -0,0 +1,84 @@
+use crate::egress::servers::record::RecordFormat;
+use crate::endpoints::Container;
+use actix_web::{web, HttpResponse, Responder};
+use serde::{Deserialize, Serialize};
+
+#[derive(Deserialize)]
+pub struct RecordQuery {
+    // mp4, mkv(webm). 없으면 config 의 record.format
+    format: Option<String>,
+}
+
+#[derive(Serialize)]
+struct RecordResponse {
+    #[serde(rename = "streamId")]
+    stream_id: String,
+}
+
+#[derive(Serialize)]
+struct RecordFilesResponse {
+    #[serde(rename = "streamId")]
+    stream_id: String,
+    files: Vec<String>,
+}
+
+pub async fn handle_start_record(
+    handler: web::Data<Container>,
+    stream_id_: web::Path<String>,
+    query: web::Query<RecordQuery>,
+) -> impl Responder {
+    let stream_id = stream_id_.to_string();
+
+    log::info!("start record streamID:{}, messageType:request", stream_id);
+
+    let format = match query.format.as_deref().map(RecordFormat::from).transpose() {
+        Ok(format) => format,
+        Err(e) => {
+            log::error!("record error:{}", e);
+            return HttpResponse::BadRequest().finish();
+        }
+    };
+    if let Err(e) = handler.record_server.start(&stream_id, format).await {
+        log::error!("record error:{}", e);
+        return HttpResponse::BadRequest().finish();
+    }
+
+    HttpResponse::Ok().json(RecordResponse { stream_id })
+}
+
+pub async fn handle_stop_record(
+    handler: web::Data<Container>,
+    stream_id_: web::Path<String>,
+) -> impl Responder {
+    let stream_id = stream_id_.to_string();
+
+    log::info!("stop record streamID:{}, messageType:request", stream_id);
+
+    match handler.record_server.stop(&stream_id).await {
+        Ok(()) => HttpResponse::NoContent().finish(),
+        Err(e) => {
+            log::error!("record error:{}", e);
+            HttpResponse::NotFound().finish()
+        }
+    }
+}
+
+pub async fn handle_get_record(
+    handler: web::Data<Container>,
+    stream_id_: web::Path<String>,
+) -> impl Responder {
+    let stream_id = stream_id_.to_string();
+
+    let session = match handler.record_server.get(&stream_id).await {
+        Ok(session) => session,
+        Err(e) => {
+            log::error!("record error:{}", e);
+            return HttpResponse::NotFound().finish();
+        }
+    };
+
+    HttpResponse::Ok().json(RecordFilesResponse {
+        stream_id,
+        files: session.files().await,
+    })
+}
@@ -0,0 +1,64 @@
+use crate::endpoints::Container;
+use crate::ingress::sessions::rtsp::rtsp::RtspTransport;
+use actix_web::{web, HttpResponse, Responder};
+use actix_web_httpauth::extractors::bearer::BearerAuth;
+use serde::{Deserialize, Serialize};
+
+#[derive(Deserialize)]
+pub struct RtspRequest {
+    url: String,
+    #[serde(default)]
+    transport: RtspTransport,
+}
+
+#[derive(Serialize)]
+struct RtspResponse {
+    #[serde(rename = "sessionId")]
+    session_id: String,
+}
+
+pub async fn handle_start_session(
+    handler: web::Data<Container>,
+    auth: BearerAuth,
+    request: web::Json<RtspRequest>,
+) -> impl Responder {
+    let token = auth.token().to_owned();
+    let request = request.into_inner();
+
+    log::info!(
+        "rtsp stream_id:{}, url:{}, transport:{:?}",
+        token,
+        request.url,
+        request.transport
+    );
+
+    let session_id = match handler
+        .rtsp_server
+        .start_session(token, &request.url, request.transport)
+        .await
+    {
+        Ok(session_id) => session_id,
+        Err(e) => {
+            log::error!("rtsp error:{}", e);
+            return HttpResponse::InternalServerError().finish();
+        }
+    };
+
+    HttpResponse::Ok().json(RtspResponse { session_id })
+}
+
+pub async fn handle_stop_session(
+    handler: web::Data<Container>,
+    session_id: web::Path<String>,
+) -> impl Responder {
+    let session_id = session_id.into_inner();
+
+    log::info!("delete rtsp session_id:{}", session_id);
+
+    if let Err(e) = handler.rtsp_server.stop_session(&session_id).await {
+        log::error!("delete rtsp error:{}", e);
+        return HttpResponse::NotFound().finish();
+    }
+
+    HttpResponse::Ok().finish()
+}
@@ -1,6 +1,27 @@
-use crate::endpoints::Container;
-use actix_web::{web, HttpResponse, Responder};
+use crate::endpoints::{sdpfrag_response, Container};
+use crate::webrtc_wrapper::ice::SDPFRAG_CONTENT_TYPE;
+use actix_web::{web, HttpMessage, HttpRequest, HttpResponse, Responder};
 use actix_web_httpauth::extractors::bearer::BearerAuth;
+use serde::{Deserialize, Serialize};
+
+#[derive(Deserialize)]
+pub struct LayerRequest {
+    // 보낼 simulcast layer 의 rid. 없으면 viewer 의 REMB 로 고른다.
+    #[serde(rename = "encodingId")]
+    encoding_id: Option<String>,
+}
+
+#[derive(Serialize)]
+struct WhepSessionResponse {
+    #[serde(rename = "sessionId")]
+    session_id: String,
+    #[serde(rename = "nackReceived")]
+    nack_received: u64,
+    requested: u64,
+    retransmitted: u64,
+    // history 에서 밀려나서 재전송하지 못한 packet 수
+    missed: u64,
+}
 
 pub async fn handle_whep(
     handler: web::Data<Container>,
@@ -15,12 +36,12 @@ pub async fn handle_whep(
         offer
     );
 
-    let answer = match handler
+    let (session_id, answer) = match handler
         .whep_server
         .start_session(token.to_owned(), &offer)
         .await
     {
-        Ok(answer) => answer,
+        Ok(result) => result,
         Err(e) => {
             log::error!("whep error:{}", e);
             return HttpResponse::InternalServerError().finish();
@@ -31,6 +52,106 @@ pub async fn handle_whep(
 
     HttpResponse::Created()
         .insert_header(("Content-Type", "application/sdp"))
-        .insert_header(("Location", "http://127.0.0.1/v1/whip/candidates"))
+        .insert_header(("Location", format!("/v1/whep/{}", session_id)))
         .body(answer)
 }
+
+pub async fn handle_patch_whep(
+    req: HttpRequest,
+    handler: web::Data<Container>,
+    session_id: web::Path<String>,
+    sdpfrag: String,
+) -> impl Responder {
+    let session_id = session_id.into_inner();
+
+    log::info!("whep patch session_id:{}, sdpfrag:{}", session_id, sdpfrag);
+
+    if req.content_type() != SDPFRAG_CONTENT_TYPE {
+        return HttpResponse::UnsupportedMediaType().finish();
+    }
+    let session = match handler.whep_server.get_session(&session_id).await {
+        Ok(session) => session,
+        Err(e) => {
+            log::error!("whep patch error:{}", e);
+            return HttpResponse::NotFound().finish();
+        }
+    };
+
+    sdpfrag_response(session.handler.patch(&sdpfrag).await)
+}
+
+// viewer 가 받을 simulcast layer 를 고른다. 다음 keyframe 부터 바뀐다.
+pub async fn handle_patch_whep_layer(
+    handler: web::Data<Container>,
+    session_id: web::Path<String>,
+    request: web::Json<LayerRequest>,
+) -> impl Responder {
+    let session_id = session_id.into_inner();
+
+    log::info!(
+        "whep layer session_id:{}, encodingId:{:?}",
+        session_id,
+        request.encoding_id
+    );
+
+    let session = match handler.whep_server.get_session(&session_id).await {
+        Ok(session) => session,
+        Err(e) => {
+            log::error!("whep layer error:{}", e);
+            return HttpResponse::NotFound().finish();
+        }
+    };
+
+    match session
+        .handler
+        .select_layer(request.encoding_id.as_deref())
+        .await
+    {
+        Ok(()) => HttpResponse::NoContent().finish(),
+        Err(e) => {
+            log::error!("whep layer error:{}", e);
+            HttpResponse::BadRequest().finish()
+        }
+    }
+}
+
+// viewer 의 NACK 재전송 통계
+pub async fn handle_get_whep(
+    handler: web::Data<Container>,
+    session_id: web::Path<String>,
+) -> impl Responder {
+    let session_id = session_id.into_inner();
+
+    let session = match handler.whep_server.get_session(&session_id).await {
+        Ok(session) => session,
+        Err(e) => {
+            log::error!("whep get error:{}", e);
+            return HttpResponse::NotFound().finish();
+        }
+    };
+
+    let stats = session.handler.retransmission_stats().await;
+    HttpResponse::Ok().json(WhepSessionResponse {
+        session_id,
+        nack_received: stats.nack_received,
+        requested: stats.requested,
+        retransmitted: stats.retransmitted,
+        missed: stats.missed,
+    })
+}
+
+pub async fn handle_delete_whep(
+    handler: web::Data<Container>,
+    session_id: web::Path<String>,
+) -> impl Responder {
+    let session_id = session_id.into_inner();
+
+    log::info!("whep delete session_id:{}", session_id);
+
+    if let Err(e) = handler.whep_server.stop_session(&session_id).await {
+        log::error!("whep delete error:{}", e);
+        return HttpResponse::NotFound().finish();
+    }
+
+    HttpResponse::Ok().finish()
+}
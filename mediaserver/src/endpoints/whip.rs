@@ -1,5 +1,6 @@
-use crate::endpoints::Container;
-use actix_web::{web, HttpResponse, Responder};
+use crate::endpoints::{sdpfrag_response, Container};
+use crate::webrtc_wrapper::ice::SDPFRAG_CONTENT_TYPE;
+use actix_web::{web, HttpMessage, HttpRequest, HttpResponse, Responder};
 use actix_web_httpauth::extractors::bearer::BearerAuth;
 
 pub async fn handle_whip(
@@ -13,8 +14,8 @@ pub async fn handle_whip(
 
     let result = handler.whip_server.start_session(token, &offer).await;
 
-    let answer = match result {
-        Ok(answer) => answer,
+    let (session_id, answer) = match result {
+        Ok(result) => result,
         Err(e) => {
             log::error!("whip error:{}", e);
             return HttpResponse::InternalServerError().finish();
@@ -25,6 +26,46 @@ pub async fn handle_whip(
 
     HttpResponse::Created()
         .insert_header(("Content-Type", "application/sdp"))
-        .insert_header(("Location", "http://127.0.0.1/v1/whip/candidates"))
+        .insert_header(("Location", format!("/v1/whip/{}", session_id)))
         .body(answer)
 }
+
+pub async fn handle_patch_whip(
+    req: HttpRequest,
+    handler: web::Data<Container>,
+    session_id: web::Path<String>,
+    sdpfrag: String,
+) -> impl Responder {
+    let session_id = session_id.into_inner();
+
+    log::info!("whip patch session_id:{}, sdpfrag:{}", session_id, sdpfrag);
+
+    if req.content_type() != SDPFRAG_CONTENT_TYPE {
+        return HttpResponse::UnsupportedMediaType().finish();
+    }
+    let session = match handler.whip_server.get_session(&session_id).await {
+        Ok(session) => session,
+        Err(e) => {
+            log::error!("whip patch error:{}", e);
+            return HttpResponse::NotFound().finish();
+        }
+    };
+
+    sdpfrag_response(session.patch(&sdpfrag).await)
+}
+
+pub async fn handle_delete_whip(
+    handler: web::Data<Container>,
+    session_id: web::Path<String>,
+) -> impl Responder {
+    let session_id = session_id.into_inner();
+
+    log::info!("whip delete session_id:{}", session_id);
+
+    if let Err(e) = handler.whip_server.stop_session(&session_id).await {
+        log::error!("whip delete error:{}", e);
+        return HttpResponse::NotFound().finish();
+    }
+
+    HttpResponse::Ok().finish()
+}
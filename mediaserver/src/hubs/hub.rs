@@ -2,16 +2,20 @@ use std::collections::HashMap;
 use std::sync::Arc;
 
 use crate::hubs::stream::HubStream;
-use tokio::sync::RwLock;
+use tokio::sync::{broadcast, RwLock};
 
 pub struct Hub {
     streams: RwLock<HashMap<String, Arc<HubStream>>>,
+    // publish 된 stream id. 녹화 자동 시작 등에 쓴다.
+    published: broadcast::Sender<String>,
 }
 
 impl Hub {
     pub fn new() -> Arc<Self> {
+        let (published, _) = broadcast::channel(16);
         Arc::new(Hub {
             streams: RwLock::new(HashMap::new()),
+            published,
         })
     }
 
@@ -20,6 +24,11 @@ impl Hub {
             .write()
             .await
             .insert(id.to_string(), stream.clone());
+        let _ = self.published.send(id.to_string());
+    }
+
+    pub fn subscribe_published(&self) -> broadcast::Receiver<String> {
+        self.published.subscribe()
     }
 
     pub async fn remove_stream(&self, id: &str, stream: &Arc<HubStream>) {
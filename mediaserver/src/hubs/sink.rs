@@ -1,4 +1,5 @@
 use crate::hubs::unit::HubUnit;
+use std::collections::VecDeque;
 use std::sync::Arc;
 use tokio::sync::broadcast::error::RecvError;
 use tokio::sync::broadcast::Receiver;
@@ -6,16 +7,22 @@ use tokio::sync::RwLock;
 
 pub struct HubSink {
     rx: RwLock<Receiver<HubUnit>>,
+    // live unit 보다 먼저 전달할 GOP cache
+    cached: RwLock<VecDeque<HubUnit>>,
 }
 
 impl HubSink {
-    pub fn new(rx: Receiver<HubUnit>) -> Arc<Self> {
+    pub fn new(rx: Receiver<HubUnit>, cached: Vec<HubUnit>) -> Arc<Self> {
         Arc::new(HubSink {
             rx: RwLock::new(rx),
+            cached: RwLock::new(cached.into()),
         })
     }
 
     pub async fn read_unit(self: &Arc<Self>) -> Result<HubUnit, RecvError> {
+        if let Some(unit) = self.cached.write().await.pop_front() {
+            return Ok(unit);
+        }
         self.rx.write().await.recv().await
     }
 }
@@ -2,30 +2,82 @@ use crate::codecs::codec::Codec;
 use crate::hubs::track::HubTrack;
 use crate::hubs::unit::HubUnit;
 use std::collections::{hash_map::Entry, HashMap};
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::Arc;
-use tokio::sync::{broadcast, RwLock};
+use std::time::{Duration, Instant};
+use tokio::sync::{broadcast, watch, Notify, RwLock};
 use tokio_util::sync::CancellationToken;
 
+// 이 시간 동안 받은 payload 로 bitrate 를 다시 잰다.
+const BITRATE_WINDOW: Duration = Duration::from_secs(2);
+
 pub struct HubSource {
     tracks: RwLock<HashMap<Codec, Arc<HubTrack>>>,
     tx: broadcast::Sender<HubUnit>,
     token: CancellationToken,
-    codec: RwLock<Option<Codec>>,
+    // ingress 가 codec 을 알아내기 전까지는 None
+    codec: watch::Sender<Option<Codec>>,
+    // egress 가 keyframe 을 필요로 할 때 ingress 에 알린다.
+    keyframe_request: Notify,
+    // simulcast layer 의 rid. simulcast 가 아니면 None
+    rid: Option<String>,
+    // (window 시작 시각, 받은 byte). BITRATE_WINDOW 가 지나면 bitrate 를 갱신한다.
+    meter: RwLock<(Instant, u64)>,
+    bitrate: AtomicU64,
+    // 마지막 unit 을 받은 시각. created 부터의 ms 이고, 받은 적이 없으면 u64::MAX
+    created: Instant,
+    last_unit: AtomicU64,
 }
 
 impl HubSource {
     pub fn new() -> Arc<Self> {
+        Self::create(None)
+    }
+
+    // simulcast 의 한 layer
+    pub fn with_rid(rid: &str) -> Arc<Self> {
+        Self::create(Some(rid.to_string()))
+    }
+
+    fn create(rid: Option<String>) -> Arc<Self> {
         println!("new hub source");
         let tracks = RwLock::new(HashMap::new());
         let (tx, _) = broadcast::channel(100);
+        let (codec, _) = watch::channel(None);
         Arc::new(HubSource {
             tracks,
             tx,
             token: CancellationToken::new(),
-            codec: RwLock::new(None),
+            codec,
+            keyframe_request: Notify::new(),
+            rid,
+            meter: RwLock::new((Instant::now(), 0)),
+            bitrate: AtomicU64::new(0),
+            created: Instant::now(),
+            last_unit: AtomicU64::new(u64::MAX),
         })
     }
 
+    pub fn rid(&self) -> Option<&str> {
+        self.rid.as_deref()
+    }
+
+    // 최근 payload 의 bitrate(bps). 아직 잰 적이 없거나 멈춘 source 이면 0
+    pub fn bitrate(&self) -> u64 {
+        if !self.is_active() {
+            return 0;
+        }
+        self.bitrate.load(Ordering::Relaxed)
+    }
+
+    // BITRATE_WINDOW 안에 unit 을 받았는지. simulcast 의 layer 는 publisher 가 멈출 수 있다.
+    pub fn is_active(&self) -> bool {
+        let last_unit = self.last_unit.load(Ordering::Relaxed);
+        last_unit != u64::MAX
+            && (self.created.elapsed().as_millis() as u64).saturating_sub(last_unit)
+                < BITRATE_WINDOW.as_millis() as u64
+    }
+
     pub fn token(self: &Arc<Self>) -> CancellationToken {
         self.token.clone()
     }
@@ -35,16 +87,32 @@ impl HubSource {
     }
 
     pub async fn set_codec(self: &Arc<Self>, codec: Codec) {
-        self.codec.write().await.replace(codec);
+        self.codec.send_replace(Some(codec));
     }
 
     pub async fn get_codec(self: &Arc<Self>) -> Option<Codec> {
-        self.codec.read().await.clone()
+        self.codec.borrow().clone()
     }
 
+    // codec 이 바뀔 때마다 (SPS/PPS, 해상도 변경 등) 알림을 받는다.
+    pub fn subscribe_codec(&self) -> watch::Receiver<Option<Codec>> {
+        self.codec.subscribe()
+    }
+
+    // codec 이 아직 없으면 timeout 까지 ingress 가 알아내기를 기다린다.
+    pub async fn wait_codec(self: &Arc<Self>, timeout: Duration) -> Option<Codec> {
+        let mut rx = self.codec.subscribe();
+        match tokio::time::timeout(timeout, rx.wait_for(|codec| codec.is_some())).await {
+            Ok(Ok(codec)) => codec.clone(),
+            _ => None,
+        }
+    }
+
+    // use_gop_cache 는 track 을 처음 만들 때 쓴다. egress 는 모두 같은 hub.gop_cache 설정을 쓴다.
     pub async fn get_track(
         self: &Arc<Self>,
         transcoding_codec: &Codec,
+        use_gop_cache: bool,
     ) -> anyhow::Result<Arc<HubTrack>> {
         // 출력 코덱이 다를경우 transcoding을 고려해주어야 한다. _source_codec 은 그부분을 체크하기 위한 부분임.
         let _source_codec = self.get_codec().await.ok_or(anyhow::anyhow!("no codec"))?;
@@ -54,7 +122,8 @@ impl HubSource {
             match tracks.entry(transcoding_codec.clone()) {
                 Entry::Occupied(entry) => (entry.get().clone(), false),
                 Entry::Vacant(entry) => {
-                    let hub_track = HubTrack::new(self.token.clone());
+                    let hub_track =
+                        HubTrack::new(self.token.clone(), transcoding_codec.kind(), use_gop_cache);
                     entry.insert(hub_track.clone());
                     (hub_track, true)
                 }
@@ -77,7 +146,28 @@ impl HubSource {
         Ok(hub_track)
     }
 
+    // 여러 요청이 겹쳐도 ingress 에는 한번만 전달된다.
+    pub fn request_keyframe(&self) {
+        self.keyframe_request.notify_one();
+    }
+
+    pub async fn keyframe_requested(&self) {
+        self.keyframe_request.notified().await;
+    }
+
     pub async fn write_unit(&self, unit: HubUnit) {
+        self.last_unit
+            .store(self.created.elapsed().as_millis() as u64, Ordering::Relaxed);
+        {
+            let mut meter = self.meter.write().await;
+            meter.1 += unit.payload.len() as u64;
+            let elapsed = meter.0.elapsed();
+            if elapsed >= BITRATE_WINDOW {
+                let bitrate = meter.1 * 8 * 1000 / elapsed.as_millis() as u64;
+                self.bitrate.store(bitrate, Ordering::Relaxed);
+                *meter = (Instant::now(), 0);
+            }
+        }
         let _ = self.tx.send(unit);
     }
 }
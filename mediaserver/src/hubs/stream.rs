@@ -1,5 +1,8 @@
+use crate::codecs::codec::Codec;
 use crate::hubs::source::HubSource;
+use std::cmp::Reverse;
 use std::sync::Arc;
+use std::time::Duration;
 use tokio::sync::RwLock;
 
 pub struct HubStream {
@@ -23,6 +26,32 @@ impl HubStream {
         self.sources.read().await.clone()
     }
 
+    // codec 을 알고 있는 source 들. 아직 감지 중인 source 는 timeout 까지 기다리고, 그래도 없으면 뺀다.
+    // simulcast layer 는 뒤에 해상도가 높은 순서로 두어서 kind 별 첫 source 가 가장 높은 layer 가 되게 한다.
+    pub async fn get_ready_sources(&self, timeout: Duration) -> Vec<(Arc<HubSource>, Codec)> {
+        let sources = self.get_sources().await;
+        let codecs =
+            futures::future::join_all(sources.iter().map(|source| source.wait_codec(timeout)))
+                .await;
+        let mut ready: Vec<_> = sources
+            .into_iter()
+            .zip(codecs)
+            .filter_map(|(source, codec)| match codec {
+                Some(codec) => Some((source, codec)),
+                None => {
+                    log::warn!("codec detection timed out, skip source");
+                    None
+                }
+            })
+            .collect();
+        ready.sort_by_key(|(source, codec)| {
+            source
+                .rid()
+                .map(|_| Reverse(codec.width() * codec.height()))
+        });
+        ready
+    }
+
     pub async fn remove_source(&self, source: Arc<HubSource>) {
         let mut sources = self.sources.write().await;
         sources.retain(|s| !Arc::ptr_eq(s, &source));
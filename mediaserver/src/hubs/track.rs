@@ -1,19 +1,36 @@
 use crate::hubs::sink::HubSink;
 use crate::hubs::unit::HubUnit;
+use crate::utils::types::types::MediaKind;
+use std::collections::VecDeque;
 use std::sync::Arc;
+use std::time::Instant;
 use tokio::sync::broadcast::Receiver;
 use tokio::sync::{broadcast, RwLock};
 use tokio_util::sync::CancellationToken;
 
+// GOP 가 너무 길면 다음 keyframe 까지 cache 하지 않는다. audio 는 이만큼만 보관한다.
+const MAX_GOP_CACHE_UNITS: usize = 4096;
+
+struct CachedUnit {
+    unit: HubUnit,
+    // track 끼리 timestamp 기준이 다르므로 cache 의 시작을 맞출 때는 받은 시각을 쓴다.
+    received: Instant,
+}
+
 pub struct HubTrack {
     source_token: CancellationToken,
     id: String,
+    kind: MediaKind,
     sinks: RwLock<Vec<Arc<HubSink>>>,
     tx: broadcast::Sender<HubUnit>,
+    // 새 sink 가 keyframe 을 기다리지 않고 시작할 수 있도록 마지막 keyframe 부터의 unit 을 보관한다.
+    // hub.gop_cache 가 꺼져 있으면 쌓지 않는다.
+    use_gop_cache: bool,
+    gop_cache: RwLock<VecDeque<CachedUnit>>,
 }
 
 impl HubTrack {
-    pub fn new(token: CancellationToken) -> Arc<Self> {
+    pub fn new(token: CancellationToken, kind: MediaKind, use_gop_cache: bool) -> Arc<Self> {
         let (tx, _) = broadcast::channel(100);
 
         let id = uuid::Uuid::new_v4().to_string();
@@ -21,8 +38,11 @@ impl HubTrack {
         let hub_track = Arc::new(HubTrack {
             source_token: token,
             id,
+            kind,
             sinks,
             tx,
+            use_gop_cache,
+            gop_cache: RwLock::new(VecDeque::new()),
         });
 
         hub_track
@@ -46,6 +66,14 @@ impl HubTrack {
                     };
                     // todo transcode?
 
+                    // add_sink 가 cache 를 복사하는 동안에는 unit 을 보내지 않아야 빠지거나 중복되는 unit 이 없다.
+                    let _gop_cache = if self.use_gop_cache {
+                        let mut gop_cache = self.gop_cache.write().await;
+                        update_gop_cache(&mut gop_cache, &hub_unit, self.kind, Instant::now());
+                        Some(gop_cache)
+                    } else {
+                        None
+                    };
                     if self.tx.receiver_count() == 0 {
                         continue;
                     }
@@ -57,12 +85,21 @@ impl HubTrack {
         }
     }
 
-    pub async fn add_sink(self: &Arc<Self>) -> Arc<HubSink> {
-        let rx = self.tx.subscribe();
-        let hub_sink = HubSink::new(rx);
+    // since 는 같은 stream 의 video cache 가 시작된 시각으로, audio 는 그 뒤에 받은 unit 부터 넘긴다.
+    // video 이면 넘겨준 cache 가 시작된 시각도 돌려준다.
+    pub async fn add_sink(
+        self: &Arc<Self>,
+        since: Option<Instant>,
+    ) -> (Arc<HubSink>, Option<Instant>) {
+        let (rx, cached, start) = {
+            let gop_cache = self.gop_cache.read().await;
+            let (cached, start) = cached_units(&gop_cache, self.kind, since);
+            (self.tx.subscribe(), cached, start)
+        };
+        let hub_sink = HubSink::new(rx, cached);
         let _hub_sink = hub_sink.clone();
         self.sinks.write().await.push(hub_sink.clone());
-        hub_sink
+        (hub_sink, start)
     }
 
     pub async fn remove_sink(self: &Arc<Self>, sink: &Arc<HubSink>) {
@@ -71,8 +108,166 @@ impl HubTrack {
     }
 }
 
+// video 는 keyframe(SPS/PPS/IDR) 이 시작되면 cache 를 비우고 새 GOP 를 쌓는다.
+// audio 는 keyframe 이 없으므로 최근 unit 을 계속 쌓아두고, sink 를 만들 때 video 의 GOP 에 맞춰 자른다.
+fn update_gop_cache(
+    gop_cache: &mut VecDeque<CachedUnit>,
+    unit: &HubUnit,
+    kind: MediaKind,
+    received: Instant,
+) {
+    if kind == MediaKind::Audio {
+        if gop_cache.len() >= MAX_GOP_CACHE_UNITS {
+            gop_cache.pop_front();
+        }
+        gop_cache.push_back(CachedUnit {
+            unit: unit.clone(),
+            received,
+        });
+        return;
+    }
+
+    let keyframe = unit.frame_info.flag == 1;
+    if keyframe
+        && gop_cache
+            .back()
+            .map_or(true, |last| last.unit.frame_info.flag != 1)
+    {
+        gop_cache.clear();
+    }
+    if gop_cache.is_empty() && !keyframe {
+        return;
+    }
+    if gop_cache.len() >= MAX_GOP_CACHE_UNITS {
+        gop_cache.clear();
+        return;
+    }
+    gop_cache.push_back(CachedUnit {
+        unit: unit.clone(),
+        received,
+    });
+}
+
+// sink 에 넘길 cache 와 그 시작 시각. video 는 GOP 전체를 넘긴다.
+// audio 는 video cache 의 keyframe 이후에 받은 unit 만 넘기고, 맞출 video 가 없으면 넘기지 않는다.
+fn cached_units(
+    gop_cache: &VecDeque<CachedUnit>,
+    kind: MediaKind,
+    since: Option<Instant>,
+) -> (Vec<HubUnit>, Option<Instant>) {
+    match kind {
+        MediaKind::Video => (
+            gop_cache.iter().map(|cached| cached.unit.clone()).collect(),
+            gop_cache.front().map(|cached| cached.received),
+        ),
+        MediaKind::Audio => {
+            let Some(since) = since else {
+                return (vec![], None);
+            };
+            let cached = gop_cache
+                .iter()
+                .filter(|cached| cached.received >= since)
+                .map(|cached| cached.unit.clone())
+                .collect();
+            (cached, None)
+        }
+    }
+}
+
 impl Drop for HubTrack {
     fn drop(&mut self) {
         println!("HubTrack dropped");
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hubs::unit::FrameInfo;
+    use std::time::Duration;
+
+    fn unit(sn: u32, keyframe: bool) -> HubUnit {
+        HubUnit {
+            sn,
+            frame_info: FrameInfo {
+                flag: keyframe as i32,
+            },
+            ..Default::default()
+        }
+    }
+
+    fn sns(units: &[HubUnit]) -> Vec<u32> {
+        units.iter().map(|unit| unit.sn).collect()
+    }
+
+    #[test]
+    fn test_update_gop_cache_video() {
+        let now = Instant::now();
+        let mut cache = VecDeque::new();
+        // keyframe 전의 unit 은 쌓지 않는다.
+        update_gop_cache(&mut cache, &unit(0, false), MediaKind::Video, now);
+        assert!(cache.is_empty());
+
+        // SPS/PPS/IDR 처럼 이어진 keyframe unit 은 같은 GOP 이다.
+        for (sn, keyframe) in [(1, true), (2, true), (3, false), (4, false)] {
+            update_gop_cache(&mut cache, &unit(sn, keyframe), MediaKind::Video, now);
+        }
+        let (cached, start) = cached_units(&cache, MediaKind::Video, None);
+        assert_eq!(sns(&cached), vec![1, 2, 3, 4]);
+        assert_eq!(start, Some(now));
+
+        // 다음 keyframe 에서 새 GOP 로 바뀐다.
+        let later = now + Duration::from_secs(2);
+        update_gop_cache(&mut cache, &unit(5, true), MediaKind::Video, later);
+        update_gop_cache(&mut cache, &unit(6, false), MediaKind::Video, later);
+        let (cached, start) = cached_units(&cache, MediaKind::Video, None);
+        assert_eq!(sns(&cached), vec![5, 6]);
+        assert_eq!(start, Some(later));
+    }
+
+    #[tokio::test]
+    async fn test_gop_cache_disabled() {
+        let token = CancellationToken::new();
+        let (tx, rx) = broadcast::channel(16);
+        let track = HubTrack::new(token.clone(), MediaKind::Video, false);
+        let handle = tokio::spawn({
+            let track = track.clone();
+            let token = token.clone();
+            async move { track.run(rx, token).await }
+        });
+        for (sn, keyframe) in [(0, true), (1, false)] {
+            tx.send(unit(sn, keyframe)).unwrap();
+        }
+        token.cancel();
+        handle.await.unwrap();
+
+        // gop_cache 가 꺼져 있으면 unit 을 쌓지 않고, 새 sink 도 live 부터 받는다.
+        assert!(track.gop_cache.read().await.is_empty());
+        let (_, start) = track.add_sink(None).await;
+        assert_eq!(start, None);
+    }
+
+    #[test]
+    fn test_update_gop_cache_audio() {
+        let now = Instant::now();
+        let mut cache = VecDeque::new();
+        for sn in 0..10 {
+            let received = now + Duration::from_millis(20 * sn as u64);
+            update_gop_cache(&mut cache, &unit(sn, false), MediaKind::Audio, received);
+        }
+        // video 의 keyframe 을 받은 뒤의 audio 부터 넘긴다.
+        let since = now + Duration::from_millis(100);
+        let (cached, _) = cached_units(&cache, MediaKind::Audio, Some(since));
+        assert_eq!(sns(&cached), vec![5, 6, 7, 8, 9]);
+        // 맞출 video 가 없으면 live 부터 받는다.
+        let (cached, _) = cached_units(&cache, MediaKind::Audio, None);
+        assert!(cached.is_empty());
+
+        // 오래된 audio 부터 버린다.
+        for sn in 10..(MAX_GOP_CACHE_UNITS as u32 + 10) {
+            update_gop_cache(&mut cache, &unit(sn, false), MediaKind::Audio, now);
+        }
+        assert_eq!(cache.len(), MAX_GOP_CACHE_UNITS);
+        assert_eq!(cache.front().unwrap().unit.sn, 10);
+    }
+}
@@ -1 +1,4 @@
+pub mod rtmp;
+pub mod rtsp;
+pub mod srt;
 pub mod whip;
@@ -0,0 +1,32 @@
+use crate::hubs::hub::Hub;
+use crate::ingress::sessions::rtmp::rtmp::RtmpSession;
+use std::sync::Arc;
+use tokio::net::TcpListener;
+
+pub struct RtmpServer {
+    hub: Arc<Hub>,
+}
+
+impl RtmpServer {
+    pub fn new(hub: Arc<Hub>) -> Arc<Self> {
+        Arc::new(RtmpServer { hub })
+    }
+
+    pub async fn listen(self: &Arc<Self>, address: &str) -> anyhow::Result<()> {
+        let listener = TcpListener::bind(address).await?;
+        log::info!("rtmp server listening on {}", address);
+
+        loop {
+            let (socket, peer) = listener.accept().await?;
+            log::info!("rtmp connection accepted: {}", peer);
+
+            let hub = self.hub.clone();
+            tokio::spawn(async move {
+                if let Err(err) = RtmpSession::new().run(socket, hub).await {
+                    log::warn!("rtmp session error: {}", err);
+                }
+                log::info!("rtmp connection closed: {}", peer);
+            });
+        }
+    }
+}
@@ -0,0 +1,61 @@
+use crate::hubs::hub::Hub;
+use crate::ingress::sessions::rtsp::rtsp::{RtspSession, RtspTransport};
+use std::collections::HashMap;
+use std::sync::Arc;
+use tokio::sync::RwLock;
+use tokio_util::sync::CancellationToken;
+use uuid::Uuid;
+
+pub struct RtspServer {
+    hub: Arc<Hub>,
+
+    sessions: RwLock<HashMap<String, CancellationToken>>,
+}
+
+impl RtspServer {
+    pub fn new(hub: Arc<Hub>) -> Arc<Self> {
+        Arc::new(RtspServer {
+            hub,
+            sessions: RwLock::new(HashMap::new()),
+        })
+    }
+
+    pub async fn start_session(
+        self: &Arc<Self>,
+        stream_id: String,
+        url: &str,
+        transport: RtspTransport,
+    ) -> anyhow::Result<String> {
+        let session_id = Uuid::new_v4().to_string();
+        let session = RtspSession::new(url, transport);
+        self.sessions
+            .write()
+            .await
+            .insert(session_id.clone(), session.token());
+        log::info!("rtsp session started: {}, url:{}", session_id, url);
+
+        let server = self.clone();
+        let session_id_ = session_id.clone();
+        tokio::spawn(async move {
+            if let Err(err) = session.run(server.hub.clone(), stream_id).await {
+                log::warn!("rtsp session error: {}", err);
+            }
+            server.sessions.write().await.remove(&session_id_);
+            log::info!("rtsp session ended: {}", session_id_);
+        });
+
+        Ok(session_id)
+    }
+
+    pub async fn stop_session(&self, session_id: &str) -> anyhow::Result<()> {
+        let token = self
+            .sessions
+            .write()
+            .await
+            .remove(session_id)
+            .ok_or(anyhow::anyhow!("session not found"))?;
+        token.cancel();
+        log::info!("rtsp session stopped: {}", session_id);
+        Ok(())
+    }
+}
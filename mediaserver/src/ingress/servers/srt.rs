@@ -0,0 +1,98 @@
+use crate::hubs::hub::Hub;
+use crate::ingress::sessions::srt::srt::{parse_stream_id, SrtSession};
+use futures::StreamExt;
+use srt_tokio::access::{RejectReason, ServerRejectReason};
+use srt_tokio::{SrtListener, SrtSocket};
+use std::sync::Arc;
+use std::time::Duration;
+
+const RECONNECT_INTERVAL: Duration = Duration::from_secs(3);
+
+pub struct SrtServer {
+    hub: Arc<Hub>,
+    passphrase: Option<String>,
+    latency: Duration,
+}
+
+impl SrtServer {
+    pub fn new(hub: Arc<Hub>, passphrase: Option<String>, latency: Duration) -> Arc<Self> {
+        Arc::new(SrtServer {
+            hub,
+            passphrase,
+            latency,
+        })
+    }
+
+    // listener 모드: encoder 가 caller 로 접속해 온다.
+    pub async fn listen(self: &Arc<Self>, address: &str) -> anyhow::Result<()> {
+        let mut builder = SrtListener::builder().latency(self.latency);
+        if let Some(ref passphrase) = self.passphrase {
+            // key size 0 은 caller 가 요청한 key size 를 따른다.
+            builder = builder.encryption(0, passphrase.as_str());
+        }
+        let (_listener, mut incoming) = builder.bind(address).await?;
+        log::info!("srt server listening on {}", address);
+
+        while let Some(request) = incoming.incoming().next().await {
+            let peer = request.remote();
+            let stream_id = match request.stream_id() {
+                Some(stream_id) => parse_stream_id(&stream_id.to_string()),
+                None => Err(anyhow::anyhow!("no srt streamid")),
+            };
+            let stream_id = match stream_id {
+                Ok(stream_id) => stream_id,
+                Err(err) => {
+                    log::warn!("srt connection rejected: {}, err:{}", peer, err);
+                    let _ = request
+                        .reject(RejectReason::Server(ServerRejectReason::BadRequest))
+                        .await;
+                    continue;
+                }
+            };
+            let socket = match request.accept(None).await {
+                Ok(socket) => socket,
+                Err(err) => {
+                    log::warn!("failed to accept srt connection: {}, err:{}", peer, err);
+                    continue;
+                }
+            };
+            log::info!("srt connection accepted: {}, stream_id:{}", peer, stream_id);
+
+            let hub = self.hub.clone();
+            tokio::spawn(async move {
+                if let Err(err) = SrtSession::new().run(socket, hub, stream_id).await {
+                    log::warn!("srt session error: {}", err);
+                }
+                log::info!("srt connection closed: {}", peer);
+            });
+        }
+        Ok(())
+    }
+
+    // caller 모드: listener 로 동작하는 encoder 에 접속한다. 연결이 끊기면 다시 접속한다.
+    pub async fn call(self: &Arc<Self>, address: &str, stream_id: &str) -> anyhow::Result<()> {
+        let hub_stream_id = parse_stream_id(stream_id)?;
+        loop {
+            let mut builder = SrtSocket::builder().latency(self.latency);
+            if let Some(ref passphrase) = self.passphrase {
+                builder = builder.encryption(16, passphrase.as_str());
+            }
+            match builder.call(address, Some(stream_id)).await {
+                Ok(socket) => {
+                    log::info!("srt connected: {}, stream_id:{}", address, hub_stream_id);
+                    let result = SrtSession::new()
+                        .run(socket, self.hub.clone(), hub_stream_id.clone())
+                        .await;
+                    if let Err(err) = result {
+                        log::warn!("srt session error: {}", err);
+                    }
+                    log::info!("srt connection closed: {}", address);
+                }
+                Err(err) => {
+                    log::warn!("failed to connect srt: {}, err:{}", address, err);
+                }
+            }
+            tokio::time::sleep(RECONNECT_INTERVAL).await;
+        }
+    }
+}
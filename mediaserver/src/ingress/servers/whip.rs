@@ -1,32 +1,80 @@
 use crate::hubs::hub::Hub;
 use crate::ingress::sessions::whip::whip::WhipSession;
+use std::collections::HashMap;
 use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::RwLock;
+use uuid::Uuid;
 
 pub struct WhipServer {
     hub: Arc<Hub>,
+    jitter_latency: Duration,
+    pli_interval: Option<Duration>,
+
+    sessions: RwLock<HashMap<String, Arc<WhipSession>>>,
 }
 
 impl WhipServer {
-    pub fn new(hub: Arc<Hub>) -> Arc<Self> {
-        Arc::new(WhipServer { hub })
+    pub fn new(
+        hub: Arc<Hub>,
+        jitter_latency: Duration,
+        pli_interval: Option<Duration>,
+    ) -> Arc<Self> {
+        Arc::new(WhipServer {
+            hub,
+            jitter_latency,
+            pli_interval,
+            sessions: RwLock::new(HashMap::new()),
+        })
     }
     pub async fn start_session(
         self: &Arc<Self>,
         stream_id: String,
         offer: &str,
-    ) -> anyhow::Result<String> {
-        let whip_session = WhipSession::new().await?;
+    ) -> anyhow::Result<(String, String)> {
+        let whip_session = WhipSession::new(self.jitter_latency, self.pli_interval).await?;
         let answer = whip_session.init(offer).await?;
 
+        let session_id = Uuid::new_v4().to_string();
+        log::info!("whip session started: {}", &session_id);
+        self.sessions
+            .write()
+            .await
+            .insert(session_id.clone(), whip_session.clone());
+
         let server = self.clone();
+        let session_id_ = session_id.clone();
         tokio::spawn(async move {
             let stream_id = stream_id.to_string();
             let hub_stream = whip_session.hub_stream();
             server.hub.insert_stream(&stream_id, &hub_stream).await;
             whip_session.run().await;
             server.hub.remove_stream(&stream_id, &hub_stream).await;
+            server.sessions.write().await.remove(&session_id_);
+            log::info!("whip session ended: {}", session_id_);
         });
 
-        Ok(answer)
+        Ok((session_id, answer))
+    }
+
+    pub async fn stop_session(&self, session_id: &str) -> anyhow::Result<()> {
+        let session = self
+            .sessions
+            .write()
+            .await
+            .remove(session_id)
+            .ok_or(anyhow::anyhow!("session not found"))?;
+        session.stop();
+        log::info!("whip session stopped: {}", session_id);
+        Ok(())
+    }
+
+    pub async fn get_session(&self, session_id: &str) -> anyhow::Result<Arc<WhipSession>> {
+        self.sessions
+            .read()
+            .await
+            .get(session_id)
+            .cloned()
+            .ok_or(anyhow::anyhow!("session not found"))
     }
 }
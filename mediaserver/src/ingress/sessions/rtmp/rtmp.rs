@@ -0,0 +1,322 @@
+use crate::codecs::aac::codec::AacCodec;
+use crate::codecs::aac::config::Config as AacConfig;
+use crate::codecs::codec::Codec;
+use crate::codecs::h264::avcc::{self, DecoderConfigurationRecord};
+use crate::codecs::h264::codec::H264Codec;
+use crate::codecs::h264::format::NALUType;
+use crate::hubs::hub::Hub;
+use crate::hubs::source::HubSource;
+use crate::hubs::stream::HubStream;
+use crate::hubs::unit::{FrameInfo, HubUnit};
+use crate::protocols::flv::tag::{AacPacketType, AudioTag, AvcPacketType, VideoTag};
+use anyhow::anyhow;
+use bytes::Bytes;
+use rml_rtmp::handshake::{Handshake, HandshakeProcessResult, PeerType};
+use rml_rtmp::sessions::{
+    ServerSession, ServerSessionConfig, ServerSessionEvent, ServerSessionResult,
+};
+use std::sync::Arc;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+use tokio_util::sync::CancellationToken;
+
+const VIDEO_CLOCK_RATE: u32 = 90000;
+
+struct TrackState {
+    source: Arc<HubSource>,
+    timebase: u32,
+    unit_sn: u32,
+    start_ts: Option<u32>,
+    last_dts: u32,
+}
+
+impl TrackState {
+    fn new(source: Arc<HubSource>, timebase: u32) -> Self {
+        TrackState {
+            source,
+            timebase,
+            unit_sn: 0,
+            start_ts: None,
+            last_dts: 0,
+        }
+    }
+
+    // rtmp timestamp(ms) 를 track timebase 로 변환한다.
+    fn rescale(&self, ms: i64) -> u32 {
+        (ms * self.timebase as i64 / 1000) as u32
+    }
+
+    async fn write_frame(&mut self, units: Vec<Bytes>, timestamp: u32, cts: i32, keyframe: bool) {
+        let start_ts = *self.start_ts.get_or_insert(timestamp);
+        let dts_ms = timestamp.wrapping_sub(start_ts) as i64;
+        let dts = self.rescale(dts_ms);
+        let pts = self.rescale(dts_ms + cts as i64);
+        let duration = dts.wrapping_sub(self.last_dts);
+        self.last_dts = dts;
+
+        let flag = if keyframe { 1 } else { 0 };
+        let len = units.len();
+        for (index, payload) in units.into_iter().enumerate() {
+            self.source
+                .write_unit(HubUnit {
+                    sn: self.unit_sn,
+                    payload,
+                    pts,
+                    dts,
+                    duration,
+                    timebase: self.timebase,
+                    marker: index == len - 1,
+                    frame_info: FrameInfo { flag },
+                })
+                .await;
+            self.unit_sn += 1;
+        }
+    }
+}
+
+pub struct RtmpSession {
+    token: CancellationToken,
+    hub_stream: Arc<HubStream>,
+
+    nal_length_size: usize,
+    video: Option<TrackState>,
+    audio: Option<TrackState>,
+}
+
+impl RtmpSession {
+    pub fn new() -> Self {
+        RtmpSession {
+            token: CancellationToken::new(),
+            hub_stream: HubStream::new(),
+            nal_length_size: 4,
+            video: None,
+            audio: None,
+        }
+    }
+
+    pub async fn run(mut self, mut socket: TcpStream, hub: Arc<Hub>) -> anyhow::Result<()> {
+        let mut stream_id: Option<String> = None;
+        let result = self.serve(&mut socket, &hub, &mut stream_id).await;
+        self.close(&hub, stream_id.as_deref()).await;
+        result
+    }
+
+    async fn serve(
+        &mut self,
+        socket: &mut TcpStream,
+        hub: &Arc<Hub>,
+        stream_id: &mut Option<String>,
+    ) -> anyhow::Result<()> {
+        let remaining = handshake(socket).await?;
+
+        let (mut session, mut pending) = ServerSession::new(ServerSessionConfig::new())
+            .map_err(|err| anyhow!("failed to create rtmp session: {:?}", err))?;
+        pending.extend(
+            session
+                .handle_input(&remaining)
+                .map_err(|err| anyhow!("rtmp input error: {:?}", err))?,
+        );
+
+        let mut buffer = vec![0u8; 4096];
+        loop {
+            while !pending.is_empty() {
+                for result in std::mem::take(&mut pending) {
+                    match result {
+                        ServerSessionResult::OutboundResponse(packet) => {
+                            socket.write_all(&packet.bytes).await?;
+                        }
+                        ServerSessionResult::RaisedEvent(event) => {
+                            let results = self
+                                .handle_event(&mut session, event, hub, stream_id)
+                                .await?;
+                            pending.extend(results);
+                        }
+                        ServerSessionResult::UnhandleableMessageReceived(_) => {}
+                    }
+                }
+            }
+
+            let n = tokio::select! {
+                _ = self.token.cancelled() => {
+                    return Ok(());
+                }
+                result = socket.read(&mut buffer) => result?,
+            };
+            if n == 0 {
+                return Ok(());
+            }
+            pending.extend(
+                session
+                    .handle_input(&buffer[..n])
+                    .map_err(|err| anyhow!("rtmp input error: {:?}", err))?,
+            );
+        }
+    }
+
+    async fn handle_event(
+        &mut self,
+        session: &mut ServerSession,
+        event: ServerSessionEvent,
+        hub: &Arc<Hub>,
+        stream_id: &mut Option<String>,
+    ) -> anyhow::Result<Vec<ServerSessionResult>> {
+        match event {
+            ServerSessionEvent::ConnectionRequested {
+                request_id,
+                app_name,
+            } => {
+                log::info!("rtmp connection requested app:{}", app_name);
+                session
+                    .accept_request(request_id)
+                    .map_err(|err| anyhow!("failed to accept rtmp connection: {:?}", err))
+            }
+            ServerSessionEvent::PublishStreamRequested {
+                request_id,
+                app_name,
+                stream_key,
+                ..
+            } => {
+                if stream_id.is_some() {
+                    return Err(anyhow!("rtmp session already publishing"));
+                }
+                log::info!("rtmp publish app:{}, stream_id:{}", app_name, stream_key);
+                hub.insert_stream(&stream_key, &self.hub_stream).await;
+                *stream_id = Some(stream_key);
+                session
+                    .accept_request(request_id)
+                    .map_err(|err| anyhow!("failed to accept rtmp publish: {:?}", err))
+            }
+            ServerSessionEvent::PublishStreamFinished { stream_key, .. } => {
+                log::info!("rtmp publish finished stream_id:{}", stream_key);
+                self.token.cancel();
+                Ok(vec![])
+            }
+            ServerSessionEvent::VideoDataReceived {
+                data, timestamp, ..
+            } => {
+                if let Err(err) = self.on_video(data, timestamp.value).await {
+                    log::warn!("rtmp video error: {}", err);
+                }
+                Ok(vec![])
+            }
+            ServerSessionEvent::AudioDataReceived {
+                data, timestamp, ..
+            } => {
+                if let Err(err) = self.on_audio(data, timestamp.value).await {
+                    log::warn!("rtmp audio error: {}", err);
+                }
+                Ok(vec![])
+            }
+            _ => Ok(vec![]),
+        }
+    }
+
+    async fn on_video(&mut self, data: Bytes, timestamp: u32) -> anyhow::Result<()> {
+        let tag = VideoTag::from(data)?;
+        match tag.packet_type {
+            AvcPacketType::SequenceHeader => {
+                let record = DecoderConfigurationRecord::from(&tag.data)?;
+                let codec = Codec::H264(H264Codec::new(record.config()?));
+                self.nal_length_size = record.nal_length_size;
+
+                if self.video.is_none() {
+                    let source = HubSource::new();
+                    self.hub_stream.add_source(source.clone()).await;
+                    self.video = Some(TrackState::new(source, VIDEO_CLOCK_RATE));
+                }
+                if let Some(ref video) = self.video {
+                    log::info!("rtmp set codec: {:?}", codec.mime_type());
+                    video.source.set_codec(codec).await;
+                }
+            }
+            AvcPacketType::Nalu => {
+                let Some(ref mut video) = self.video else {
+                    return Err(anyhow!("video data before sequence header"));
+                };
+                let units: Vec<Bytes> = avcc::split_nal_units(&tag.data, self.nal_length_size)?
+                    .into_iter()
+                    .filter(|payload| {
+                        !matches!(
+                            NALUType::from_byte(payload[0]),
+                            NALUType::SEI | NALUType::AccessUnitDelimiter | NALUType::FillerData
+                        )
+                    })
+                    .collect();
+                if units.is_empty() {
+                    return Ok(());
+                }
+                video
+                    .write_frame(units, timestamp, tag.composition_time, tag.keyframe)
+                    .await;
+            }
+            AvcPacketType::EndOfSequence => {}
+        }
+        Ok(())
+    }
+
+    async fn on_audio(&mut self, data: Bytes, timestamp: u32) -> anyhow::Result<()> {
+        let tag = AudioTag::from(data)?;
+        match tag.packet_type {
+            AacPacketType::SequenceHeader => {
+                let config = AacConfig::from(&tag.data)?;
+                let codec = Codec::Aac(AacCodec::new(config));
+
+                if self.audio.is_none() {
+                    let source = HubSource::new();
+                    self.hub_stream.add_source(source.clone()).await;
+                    self.audio = Some(TrackState::new(source, codec.clock_rate()));
+                }
+                if let Some(ref audio) = self.audio {
+                    log::info!("rtmp set codec: {:?}", codec.mime_type());
+                    audio.source.set_codec(codec).await;
+                }
+            }
+            AacPacketType::Raw => {
+                let Some(ref mut audio) = self.audio else {
+                    return Err(anyhow!("audio data before sequence header"));
+                };
+                if tag.data.is_empty() {
+                    return Ok(());
+                }
+                audio.write_frame(vec![tag.data], timestamp, 0, false).await;
+            }
+        }
+        Ok(())
+    }
+
+    async fn close(&mut self, hub: &Arc<Hub>, stream_id: Option<&str>) {
+        for track in [self.video.take(), self.audio.take()].into_iter().flatten() {
+            self.hub_stream.remove_source(track.source.clone()).await;
+            track.source.stop();
+        }
+        if let Some(stream_id) = stream_id {
+            hub.remove_stream(stream_id, &self.hub_stream).await;
+        }
+    }
+}
+
+async fn handshake(socket: &mut TcpStream) -> anyhow::Result<Vec<u8>> {
+    let mut handshake = Handshake::new(PeerType::Server);
+    let mut buffer = vec![0u8; 4096];
+    loop {
+        let n = socket.read(&mut buffer).await?;
+        if n == 0 {
+            return Err(anyhow!("connection closed during rtmp handshake"));
+        }
+        match handshake
+            .process_bytes(&buffer[..n])
+            .map_err(|err| anyhow!("rtmp handshake failed: {:?}", err))?
+        {
+            HandshakeProcessResult::InProgress { response_bytes } => {
+                socket.write_all(&response_bytes).await?;
+            }
+            HandshakeProcessResult::Completed {
+                response_bytes,
+                remaining_bytes,
+            } => {
+                socket.write_all(&response_bytes).await?;
+                return Ok(remaining_bytes);
+            }
+        }
+    }
+}
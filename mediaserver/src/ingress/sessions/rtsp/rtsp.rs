@@ -0,0 +1,480 @@
+use crate::codecs::codec::Codec;
+use crate::codecs::rtp_parser::RtpParser;
+use crate::hubs::hub::Hub;
+use crate::hubs::source::HubSource;
+use crate::hubs::stream::HubStream;
+use crate::hubs::unit::HubUnit;
+use crate::protocols::rtsp::client::RtspClient;
+use crate::protocols::rtsp::message::{read_message, Message, Request};
+use crate::protocols::rtsp::sdp::MediaDescription;
+use crate::protocols::rtsp::url::RtspUrl;
+use anyhow::anyhow;
+use bytes::Bytes;
+use serde::Deserialize;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::BufReader;
+use tokio::net::tcp::OwnedReadHalf;
+use tokio::net::UdpSocket;
+use tokio::sync::mpsc;
+use tokio_util::sync::CancellationToken;
+use webrtc::rtp::packet::Packet;
+use webrtc::util::Unmarshal;
+
+// 서버가 timeout=0 처럼 짧은 session timeout 을 보내도 keepalive 를 너무 자주 보내지 않는다.
+const MIN_KEEPALIVE_INTERVAL: Duration = Duration::from_secs(1);
+
+#[derive(Debug, Clone, Copy, PartialEq, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RtspTransport {
+    #[default]
+    Tcp,
+    Udp,
+}
+
+struct TrackState {
+    // interleaved channel 또는 udp track 구분용 channel
+    channel: u8,
+    payload_type: u8,
+    source: Arc<HubSource>,
+    parser: RtpParser,
+    // sprop-parameter-sets 로 받은 SPS/PPS 는 첫 frame 앞에 붙여 보낸다.
+    parameter_sets: Vec<Bytes>,
+    timebase: u32,
+    unit_sn: u32,
+    start_ts: Option<u32>,
+    last_ts: u32,
+    duration: u32,
+}
+
+impl TrackState {
+    async fn on_rtp(&mut self, data: Bytes) -> anyhow::Result<()> {
+        let mut buffer = data;
+        let packet = Packet::unmarshal(&mut buffer)?;
+        if packet.header.payload_type != self.payload_type || packet.payload.is_empty() {
+            return Ok(());
+        }
+
+        let timestamp = packet.header.timestamp;
+        let start_ts = match self.start_ts {
+            Some(start_ts) => start_ts,
+            None => {
+                self.start_ts = Some(timestamp);
+                self.last_ts = timestamp;
+                timestamp
+            }
+        };
+        if timestamp != self.last_ts {
+            self.duration = timestamp.wrapping_sub(self.last_ts);
+            self.last_ts = timestamp;
+        }
+        let pts = timestamp.wrapping_sub(start_ts);
+
+        let Some((mut payloads, frame_info)) = self.parser.parse(packet.payload).await else {
+            return Ok(());
+        };
+        if !self.parameter_sets.is_empty() {
+            let mut units = std::mem::take(&mut self.parameter_sets);
+            units.extend(payloads);
+            payloads = units;
+        }
+
+        let len = payloads.len();
+        for (index, payload) in payloads.into_iter().enumerate() {
+            self.source
+                .write_unit(HubUnit {
+                    sn: self.unit_sn,
+                    payload,
+                    pts,
+                    dts: pts,
+                    duration: self.duration,
+                    timebase: self.timebase,
+                    marker: index == len - 1,
+                    frame_info: frame_info.clone(),
+                })
+                .await;
+            self.unit_sn += 1;
+        }
+        Ok(())
+    }
+}
+
+pub struct RtspSession {
+    url: String,
+    transport: RtspTransport,
+    token: CancellationToken,
+    hub_stream: Arc<HubStream>,
+    tracks: Vec<TrackState>,
+}
+
+impl RtspSession {
+    pub fn new(url: &str, transport: RtspTransport) -> Self {
+        RtspSession {
+            url: url.to_string(),
+            transport,
+            token: CancellationToken::new(),
+            hub_stream: HubStream::new(),
+            tracks: Vec::new(),
+        }
+    }
+
+    pub fn token(&self) -> CancellationToken {
+        self.token.clone()
+    }
+
+    pub async fn run(mut self, hub: Arc<Hub>, stream_id: String) -> anyhow::Result<()> {
+        hub.insert_stream(&stream_id, &self.hub_stream).await;
+        let result = self.serve().await;
+        self.close(&hub, &stream_id).await;
+        result
+    }
+
+    async fn serve(&mut self) -> anyhow::Result<()> {
+        let mut client = RtspClient::connect(&self.url).await?;
+        let url = client.url().to_string();
+        client.request(Request::new("OPTIONS", &url)).await?;
+        let (sdp, base) = client.describe().await?;
+
+        let (tx, mut rx) = mpsc::channel::<(u8, Bytes)>(256);
+        for media in &sdp.medias {
+            if let Err(err) = self.setup_track(&mut client, &base, media, &tx).await {
+                log::warn!("rtsp skip track {}: {}", media.mime_type, err);
+            }
+        }
+        if self.tracks.is_empty() {
+            return Err(anyhow!("no supported track in rtsp stream: {}", url));
+        }
+
+        let play_url = RtspUrl::resolve(&base, sdp.control.as_deref());
+        client.play(&play_url).await?;
+        log::info!("rtsp play: {}, transport:{:?}", url, self.transport);
+
+        let reader = client
+            .take_reader()
+            .ok_or(anyhow!("rtsp reader already taken"))?;
+        self.read_connection(reader, tx);
+
+        let mut keepalive =
+            tokio::time::interval((client.session_timeout() / 2).max(MIN_KEEPALIVE_INTERVAL));
+        keepalive.tick().await;
+        loop {
+            tokio::select! {
+                _ = self.token.cancelled() => {
+                    break;
+                }
+                _ = keepalive.tick() => {
+                    if let Err(err) = client.send(Request::new("OPTIONS", &url)).await {
+                        log::warn!("rtsp keepalive failed: {}", err);
+                        break;
+                    }
+                }
+                received = rx.recv() => {
+                    let Some((channel, payload)) = received else {
+                        break;
+                    };
+                    let Some(track) = self.tracks.iter_mut().find(|t| t.channel == channel) else {
+                        continue;
+                    };
+                    if let Err(err) = track.on_rtp(payload).await {
+                        log::debug!("rtsp invalid rtp packet: {}", err);
+                    }
+                }
+            }
+        }
+
+        let _ = client.send(Request::new("TEARDOWN", &play_url)).await;
+        Ok(())
+    }
+
+    async fn setup_track(
+        &mut self,
+        client: &mut RtspClient,
+        base: &str,
+        media: &MediaDescription,
+        tx: &mpsc::Sender<(u8, Bytes)>,
+    ) -> anyhow::Result<()> {
+        if media.clock_rate == 0 {
+            return Err(anyhow!("no rtpmap"));
+        }
+
+        let source = HubSource::new();
+        let source_1 = source.clone();
+        let mut parser = RtpParser::new(
+            &media.mime_type,
+            Box::new(move |codec: Codec| {
+                let source = source_1.clone();
+                Box::pin(async move {
+                    log::info!("rtsp set codec: {:?}", codec.mime_type());
+                    source.set_codec(codec).await;
+                })
+            }),
+        )?;
+
+        let uri = RtspUrl::resolve(base, media.control.as_deref());
+        let channel = (self.tracks.len() * 2) as u8;
+        let channel = match self.transport {
+            RtspTransport::Tcp => {
+                let transport = format!(
+                    "RTP/AVP/TCP;unicast;interleaved={}-{}",
+                    channel,
+                    channel + 1
+                );
+                let response = client.setup(&uri, &transport).await?;
+                // 서버가 다른 channel 을 지정할 수 있다.
+                response
+                    .header("Transport")
+                    .and_then(|transport| transport_param(transport, "interleaved"))
+                    .and_then(|channels| channels.split('-').next())
+                    .and_then(|channel| channel.parse().ok())
+                    .unwrap_or(channel)
+            }
+            RtspTransport::Udp => {
+                let (rtp, rtcp) = bind_udp_pair().await?;
+                let port = rtp.local_addr()?.port();
+                let transport = format!("RTP/AVP;unicast;client_port={}-{}", port, port + 1);
+                client.setup(&uri, &transport).await?;
+                self.read_udp(channel, rtp, rtcp, tx.clone());
+                channel
+            }
+        };
+
+        // 카메라가 SPS/PPS 를 in-band 로 보내지 않는 경우가 있어 SDP 의 값으로 codec 을 먼저 설정한다.
+        let parameter_sets = media.sprop_parameter_sets()?;
+        for set in &parameter_sets {
+            let _ = parser.parse(set.clone()).await;
+        }
+
+        self.hub_stream.add_source(source.clone()).await;
+        self.tracks.push(TrackState {
+            channel,
+            payload_type: media.payload_type,
+            source,
+            parser,
+            parameter_sets,
+            timebase: media.clock_rate,
+            unit_sn: 0,
+            start_ts: None,
+            last_ts: 0,
+            duration: 0,
+        });
+        Ok(())
+    }
+
+    // rtsp 연결에서 interleaved 데이터와 keepalive 응답을 읽는다. 연결이 끊기면 session 을 종료한다.
+    fn read_connection(&self, mut reader: BufReader<OwnedReadHalf>, tx: mpsc::Sender<(u8, Bytes)>) {
+        let token = self.token.clone();
+        tokio::spawn(async move {
+            loop {
+                tokio::select! {
+                    _ = token.cancelled() => {
+                        break;
+                    }
+                    result = read_message(&mut reader) => {
+                        match result {
+                            Ok(Message::Interleaved { channel, payload }) => {
+                                if tx.send((channel, payload)).await.is_err() {
+                                    break;
+                                }
+                            }
+                            Ok(Message::Response(_)) => {}
+                            Err(err) => {
+                                log::info!("rtsp connection closed: {}", err);
+                                break;
+                            }
+                        }
+                    }
+                }
+            }
+            token.cancel();
+        });
+    }
+
+    fn read_udp(
+        &self,
+        channel: u8,
+        rtp: UdpSocket,
+        rtcp: UdpSocket,
+        tx: mpsc::Sender<(u8, Bytes)>,
+    ) {
+        let token = self.token.clone();
+        tokio::spawn(async move {
+            // rtcp 는 사용하지 않지만 port 를 유지하기 위해 소켓을 잡아둔다.
+            let _rtcp = rtcp;
+            let mut buffer = vec![0u8; 2048];
+            loop {
+                tokio::select! {
+                    _ = token.cancelled() => {
+                        break;
+                    }
+                    result = rtp.recv(&mut buffer) => {
+                        let Ok(n) = result else {
+                            break;
+                        };
+                        let payload = Bytes::copy_from_slice(&buffer[..n]);
+                        if tx.send((channel, payload)).await.is_err() {
+                            break;
+                        }
+                    }
+                }
+            }
+        });
+    }
+
+    async fn close(&mut self, hub: &Arc<Hub>, stream_id: &str) {
+        self.token.cancel();
+        for track in self.tracks.drain(..) {
+            self.hub_stream.remove_source(track.source.clone()).await;
+            track.source.stop();
+        }
+        hub.remove_stream(stream_id, &self.hub_stream).await;
+    }
+}
+
+fn transport_param<'a>(transport: &'a str, name: &str) -> Option<&'a str> {
+    transport
+        .split(';')
+        .filter_map(|param| param.trim().split_once('='))
+        .find(|(key, _)| *key == name)
+        .map(|(_, value)| value)
+}
+
+// RTP 는 짝수, RTCP 는 그 다음 port 를 사용한다.
+async fn bind_udp_pair() -> anyhow::Result<(UdpSocket, UdpSocket)> {
+    for _ in 0..10 {
+        let rtp = UdpSocket::bind("0.0.0.0:0").await?;
+        let port = rtp.local_addr()?.port();
+        if port % 2 != 0 {
+            continue;
+        }
+        if let Ok(rtcp) = UdpSocket::bind(("0.0.0.0", port + 1)).await {
+            return Ok((rtp, rtcp));
+        }
+    }
+    Err(anyhow!("failed to bind rtp/rtcp port pair"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::{AsyncBufReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+
+    const TEST_SDP: &str = "v=0\r\n\
+        o=- 0 0 IN IP4 127.0.0.1\r\n\
+        s=test camera\r\n\
+        t=0 0\r\n\
+        m=video 0 RTP/AVP 96\r\n\
+        a=rtpmap:96 H264/90000\r\n\
+        a=fmtp:96 packetization-mode=1;sprop-parameter-sets=Z0LAHtoFB+Q=,aM48gA==\r\n\
+        a=control:trackID=0\r\n";
+
+    // DESCRIBE/SETUP/PLAY 에 응답하고 IDR 을 interleaved RTP 로 계속 보내는 테스트용 카메라
+    async fn serve_test_camera(listener: TcpListener) -> anyhow::Result<()> {
+        let (socket, _) = listener.accept().await?;
+        let (reader, mut writer) = socket.into_split();
+        let mut reader = BufReader::new(reader);
+
+        loop {
+            let mut method = String::new();
+            let mut cseq = String::new();
+            let mut line = String::new();
+            loop {
+                line.clear();
+                if reader.read_line(&mut line).await? == 0 {
+                    return Ok(());
+                }
+                let header = line.trim_end();
+                if header.is_empty() {
+                    break;
+                }
+                if method.is_empty() {
+                    method = header.split(' ').next().unwrap_or_default().to_string();
+                } else if let Some(value) = header.strip_prefix("CSeq: ") {
+                    cseq = value.to_string();
+                }
+            }
+
+            let (headers, body) = match method.as_str() {
+                "DESCRIBE" => (
+                    format!(
+                        "Content-Type: application/sdp\r\nContent-Length: {}\r\n",
+                        TEST_SDP.len()
+                    ),
+                    TEST_SDP,
+                ),
+                "SETUP" => (
+                    "Transport: RTP/AVP/TCP;unicast;interleaved=0-1\r\nSession: 12345678;timeout=60\r\n"
+                        .to_string(),
+                    "",
+                ),
+                _ => (String::new(), ""),
+            };
+            let response = format!(
+                "RTSP/1.0 200 OK\r\nCSeq: {}\r\n{}\r\n{}",
+                cseq, headers, body
+            );
+            writer.write_all(response.as_bytes()).await?;
+            if method == "PLAY" {
+                break;
+            }
+        }
+
+        let mut sn: u16 = 0;
+        loop {
+            let timestamp = sn as u32 * 3000;
+            let mut packet = vec![0x80, 0x80 | 96];
+            packet.extend_from_slice(&sn.to_be_bytes());
+            packet.extend_from_slice(&timestamp.to_be_bytes());
+            packet.extend_from_slice(&0x1234u32.to_be_bytes());
+            packet.extend_from_slice(&[0x65, 0x88, 0x84, 0x00]);
+
+            let mut frame = vec![b'$', 0];
+            frame.extend_from_slice(&(packet.len() as u16).to_be_bytes());
+            frame.extend_from_slice(&packet);
+            if writer.write_all(&frame).await.is_err() {
+                return Ok(());
+            }
+            sn = sn.wrapping_add(1);
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+    }
+
+    #[tokio::test]
+    async fn test_rtsp_pull_tcp() -> anyhow::Result<()> {
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let url = format!("rtsp://{}/live", listener.local_addr()?);
+        tokio::spawn(serve_test_camera(listener));
+
+        let hub = Hub::new();
+        let session = RtspSession::new(&url, RtspTransport::Tcp);
+        let token = session.token();
+        let handle = tokio::spawn(session.run(hub.clone(), "camera".to_string()));
+
+        let (source, codec) = tokio::time::timeout(Duration::from_secs(3), async {
+            loop {
+                if let Some(stream) = hub.get_stream("camera").await {
+                    for source in stream.get_sources().await {
+                        if let Some(codec) = source.get_codec().await {
+                            return (source, codec);
+                        }
+                    }
+                }
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await?;
+        assert_eq!(codec.mime_type(), "video/h264");
+        assert_eq!(codec.width(), 320);
+        assert_eq!(codec.height(), 240);
+
+        let (sink, _) = source.get_track(&codec, false).await?.add_sink(None).await;
+        let unit = tokio::time::timeout(Duration::from_secs(3), sink.read_unit()).await??;
+        assert_eq!(unit.timebase, 90000);
+        assert!(matches!(unit.payload[0] & 0x1F, 5 | 7 | 8));
+
+        token.cancel();
+        handle.await??;
+        assert!(hub.get_stream("camera").await.is_none());
+        Ok(())
+    }
+}
@@ -0,0 +1,343 @@
+use crate::codecs::aac::adts;
+use crate::codecs::aac::codec::AacCodec;
+use crate::codecs::codec::Codec;
+use crate::codecs::h264::annexb;
+use crate::codecs::h264::codec::H264Codec;
+use crate::codecs::h264::config::Config as H264Config;
+use crate::codecs::h264::format::NALUType;
+use crate::codecs::opus::codec::OpusCodec;
+use crate::hubs::hub::Hub;
+use crate::hubs::source::HubSource;
+use crate::hubs::stream::HubStream;
+use crate::hubs::unit::{FrameInfo, HubUnit};
+use crate::protocols::mpegts::demuxer::{Demuxer, Frame, StreamKind};
+use crate::protocols::mpegts::opus;
+use anyhow::anyhow;
+use bitstreams::h264::nal_unit::NalUnit;
+use bitstreams::h264::pps::PPS;
+use bitstreams::h264::sps::SPS;
+use bytes::Bytes;
+use futures::TryStreamExt;
+use srt_tokio::SrtSocket;
+use std::collections::HashMap;
+use std::sync::Arc;
+use tokio_util::sync::CancellationToken;
+
+const MPEGTS_CLOCK_RATE: u64 = 90000;
+const TIMESTAMP_MASK: u64 = (1 << 33) - 1;
+const TIMESTAMP_PERIOD: i64 = 1 << 33;
+
+struct TrackState {
+    source: Arc<HubSource>,
+    codec: Option<Codec>,
+    unit_sn: u32,
+    last_dts: u32,
+    // 33bit rollover 를 풀기 위한 이 track 의 마지막 dts
+    last_ts: Option<i64>,
+
+    sps: Option<Bytes>,
+    pps: Option<Bytes>,
+}
+
+impl TrackState {
+    fn new(source: Arc<HubSource>) -> Self {
+        TrackState {
+            source,
+            codec: None,
+            unit_sn: 0,
+            last_dts: 0,
+            last_ts: None,
+            sps: None,
+            pps: None,
+        }
+    }
+
+    async fn set_codec(&mut self, codec: Codec) {
+        log::info!("srt set codec: {:?}", codec.mime_type());
+        self.source.set_codec(codec.clone()).await;
+        self.codec = Some(codec);
+    }
+
+    async fn write_frame(&mut self, units: Vec<Bytes>, pts: u32, dts: u32, keyframe: bool) {
+        let Some(timebase) = self.codec.as_ref().map(|codec| codec.clock_rate()) else {
+            return;
+        };
+        let duration = dts.wrapping_sub(self.last_dts);
+        self.last_dts = dts;
+
+        let flag = if keyframe { 1 } else { 0 };
+        let len = units.len();
+        for (index, payload) in units.into_iter().enumerate() {
+            self.source
+                .write_unit(HubUnit {
+                    sn: self.unit_sn,
+                    payload,
+                    pts,
+                    dts,
+                    duration,
+                    timebase,
+                    marker: index == len - 1,
+                    frame_info: FrameInfo { flag },
+                })
+                .await;
+            self.unit_sn += 1;
+        }
+    }
+}
+
+pub struct SrtSession {
+    token: CancellationToken,
+    hub_stream: Arc<HubStream>,
+
+    demuxer: Demuxer,
+    // 모든 track 이 같은 기준으로 timestamp 를 맞추도록 처음 받은 dts 를 공유한다.
+    base_ts: Option<i64>,
+    tracks: HashMap<u16, TrackState>,
+}
+
+impl SrtSession {
+    pub fn new() -> Self {
+        SrtSession {
+            token: CancellationToken::new(),
+            hub_stream: HubStream::new(),
+            demuxer: Demuxer::new(),
+            base_ts: None,
+            tracks: HashMap::new(),
+        }
+    }
+
+    pub async fn run(
+        mut self,
+        mut socket: SrtSocket,
+        hub: Arc<Hub>,
+        stream_id: String,
+    ) -> anyhow::Result<()> {
+        hub.insert_stream(&stream_id, &self.hub_stream).await;
+        let result = self.serve(&mut socket).await;
+        self.close(&hub, &stream_id).await;
+        result
+    }
+
+    async fn serve(&mut self, socket: &mut SrtSocket) -> anyhow::Result<()> {
+        loop {
+            let received = tokio::select! {
+                _ = self.token.cancelled() => {
+                    return Ok(());
+                }
+                result = socket.try_next() => result?,
+            };
+            let Some((_, data)) = received else {
+                break;
+            };
+            for frame in self.demuxer.demux(&data) {
+                self.on_frame(frame).await;
+            }
+        }
+
+        for frame in self.demuxer.flush() {
+            self.on_frame(frame).await;
+        }
+        Ok(())
+    }
+
+    async fn on_frame(&mut self, frame: Frame) {
+        let base_ts = *self
+            .base_ts
+            .get_or_insert((frame.dts & TIMESTAMP_MASK) as i64);
+
+        if !self.tracks.contains_key(&frame.pid) {
+            let source = HubSource::new();
+            self.hub_stream.add_source(source.clone()).await;
+            self.tracks.insert(frame.pid, TrackState::new(source));
+        }
+        let Some(track) = self.tracks.get_mut(&frame.pid) else {
+            return;
+        };
+
+        // rollover 는 pid 마다 풀고, 처음 받는 pid 는 base 와 가까운 주기에 놓는다.
+        let dts = unwrap_timestamp(frame.dts, track.last_ts.unwrap_or(base_ts));
+        let pts = unwrap_timestamp(frame.pts, dts);
+        track.last_ts = Some(dts);
+        // base 보다 앞선 timestamp 는 0 으로 맞춘다.
+        let pts = (pts - base_ts).max(0) as u64;
+        let dts = (dts - base_ts).max(0) as u64;
+
+        let result = match frame.kind {
+            StreamKind::H264 => on_h264(track, frame.payload, pts, dts).await,
+            StreamKind::Aac => on_aac(track, frame.payload, pts).await,
+            StreamKind::Opus => on_opus(track, frame.payload, pts).await,
+        };
+        if let Err(err) = result {
+            log::warn!("srt {:?} error pid:{}, err:{}", frame.kind, frame.pid, err);
+        }
+    }
+
+    async fn close(&mut self, hub: &Arc<Hub>, stream_id: &str) {
+        for (_, track) in self.tracks.drain() {
+            self.hub_stream.remove_source(track.source.clone()).await;
+            track.source.stop();
+        }
+        hub.remove_stream(stream_id, &self.hub_stream).await;
+    }
+}
+
+// 33bit 로 돌아가는 mpeg-ts timestamp 를 reference 와 가장 가까운 이어지는 값으로 푼다.
+fn unwrap_timestamp(ts: u64, reference: i64) -> i64 {
+    let ts = (ts & TIMESTAMP_MASK) as i64;
+    let value = reference - reference.rem_euclid(TIMESTAMP_PERIOD) + ts;
+    if value - reference > TIMESTAMP_PERIOD / 2 {
+        value - TIMESTAMP_PERIOD
+    } else if reference - value > TIMESTAMP_PERIOD / 2 {
+        value + TIMESTAMP_PERIOD
+    } else {
+        value
+    }
+}
+
+// 90kHz 인 mpeg-ts timestamp 를 track timebase 로 변환한다.
+// HubUnit 의 timestamp 는 RTP 처럼 32bit 로 돌아가므로 상위 bit 는 버린다.
+fn rescale(ts: u64, timebase: u32) -> u32 {
+    (ts * timebase as u64 / MPEGTS_CLOCK_RATE) as u32
+}
+
+async fn on_h264(track: &mut TrackState, payload: Bytes, pts: u64, dts: u64) -> anyhow::Result<()> {
+    let mut keyframe = false;
+    let mut sps = track.sps.clone();
+    let mut pps = track.pps.clone();
+    let units: Vec<Bytes> = annexb::split_nal_units(&payload)
+        .into_iter()
+        .filter(|payload| {
+            let nalu_type = NALUType::from_byte(payload[0]);
+            match nalu_type {
+                NALUType::IDR => keyframe = true,
+                NALUType::SPS => sps = Some(payload.clone()),
+                NALUType::PPS => pps = Some(payload.clone()),
+                _ => {}
+            }
+            !matches!(
+                nalu_type,
+                NALUType::SEI | NALUType::AccessUnitDelimiter | NALUType::FillerData
+            )
+        })
+        .collect();
+
+    if sps != track.sps || pps != track.pps {
+        track.sps = sps;
+        track.pps = pps;
+        if let (Some(sps_buffer), Some(pps_buffer)) = (&track.sps, &track.pps) {
+            let mut sps_nal_unit = NalUnit::from(sps_buffer.as_ref())?;
+            let sps = SPS::from(&mut sps_nal_unit)?;
+            let mut pps_nal_unit = NalUnit::from(pps_buffer.as_ref())?;
+            let pps = PPS::from(&mut pps_nal_unit)?;
+            let codec = Codec::H264(H264Codec::new(H264Config::from(sps, pps)));
+            track.set_codec(codec).await;
+        }
+    }
+    if units.is_empty() {
+        return Ok(());
+    }
+
+    let timebase = MPEGTS_CLOCK_RATE as u32;
+    track
+        .write_frame(
+            units,
+            rescale(pts, timebase),
+            rescale(dts, timebase),
+            keyframe,
+        )
+        .await;
+    Ok(())
+}
+
+async fn on_aac(track: &mut TrackState, payload: Bytes, pts: u64) -> anyhow::Result<()> {
+    for (index, frame) in adts::split_frames(&payload)?.into_iter().enumerate() {
+        let changed = match track.codec {
+            Some(Codec::Aac(ref codec)) => codec.config().payload != frame.config.payload,
+            _ => true,
+        };
+        if changed {
+            track
+                .set_codec(Codec::Aac(AacCodec::new(frame.config)))
+                .await;
+        }
+        let Some(ref codec) = track.codec else {
+            continue;
+        };
+        // 하나의 PES 에 여러 ADTS frame 이 들어있으면 frame 마다 samples 만큼 진행한다.
+        // rescale 한 값은 u32 로 wrap 되므로 RTP timestamp 처럼 wrapping 으로 더한다.
+        let ts = rescale(pts, codec.clock_rate())
+            .wrapping_add(codec.samples().wrapping_mul(index as u32));
+        track.write_frame(vec![frame.payload], ts, ts, false).await;
+    }
+    Ok(())
+}
+
+async fn on_opus(track: &mut TrackState, payload: Bytes, pts: u64) -> anyhow::Result<()> {
+    if track.codec.is_none() {
+        track.set_codec(Codec::Opus(OpusCodec::new())).await;
+    }
+    let Some(ref codec) = track.codec else {
+        return Ok(());
+    };
+    let clock_rate = codec.clock_rate();
+    let samples = codec.samples();
+    for (index, unit) in opus::split_access_units(&payload)?.into_iter().enumerate() {
+        let ts = rescale(pts, clock_rate).wrapping_add(samples.wrapping_mul(index as u32));
+        track.write_frame(vec![unit], ts, ts, false).await;
+    }
+    Ok(())
+}
+
+// SRT streamid 에서 stream id 를 얻는다.
+// access control 문법(`#!::r=live/stream,m=publish`) 이면 r 값을, 아니면 전체를 사용한다.
+pub fn parse_stream_id(stream_id: &str) -> anyhow::Result<String> {
+    let id = match stream_id.strip_prefix("#!::") {
+        Some(params) => params
+            .split(',')
+            .filter_map(|param| param.split_once('='))
+            .find(|(key, _)| *key == "r")
+            .map(|(_, value)| value)
+            .ok_or(anyhow!("no resource name in srt streamid: {}", stream_id))?,
+        None => stream_id,
+    };
+    if id.is_empty() {
+        return Err(anyhow!("empty srt streamid"));
+    }
+    Ok(id.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_stream_id() -> anyhow::Result<()> {
+        assert_eq!(parse_stream_id("camera1")?, "camera1");
+        assert_eq!(
+            parse_stream_id("#!::r=live/camera1,m=publish")?,
+            "live/camera1"
+        );
+        assert_eq!(parse_stream_id("#!::m=publish,r=camera2")?, "camera2");
+        assert!(parse_stream_id("#!::m=publish").is_err());
+        assert!(parse_stream_id("").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_unwrap_timestamp() {
+        let period = TIMESTAMP_PERIOD;
+        assert_eq!(unwrap_timestamp(1000, 0), 1000);
+        // 앞으로 rollover
+        assert_eq!(unwrap_timestamp(100, period - 3000), period + 100);
+        // rollover 직후에 조금 늦게 온 값
+        assert_eq!(
+            unwrap_timestamp((period - 50) as u64, period + 100),
+            period - 50
+        );
+        // base 보다 조금 앞선 pid 는 음수가 된다.
+        assert_eq!(unwrap_timestamp(900, 1000), 900);
+        assert_eq!(unwrap_timestamp((period - 100) as u64, 1000), -100);
+        // 두 번째 주기
+        assert_eq!(unwrap_timestamp(10, 2 * period - 20), 2 * period + 10);
+    }
+}
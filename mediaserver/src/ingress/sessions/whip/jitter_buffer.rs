@@ -0,0 +1,366 @@
+use crate::hubs::source::HubSource;
+use crate::hubs::unit::FrameInfo;
+use std::collections::BTreeMap;
+use std::time::{Duration, Instant};
+use webrtc::rtp::packet::Packet;
+
+// 버퍼가 이 개수를 넘으면 latency 와 상관없이 비어있는 sn 을 포기한다.
+const MAX_PACKETS: usize = 1000;
+const SEQUENCE_CYCLE: u64 = 1 << 16;
+// max 보다 이만큼 넘게 뒤처진 sn 은 늦게 온 packet 이 아니라 publisher 가 sn 을 건너뛴 것으로 의심한다.
+const MAX_MISORDER: u16 = MAX_PACKETS as u16;
+// 건너뛴 sn 이나 늦은 packet 이 이만큼 이어서 오면 그 sn 을 기준으로 다시 맞춘다.
+const RESYNC_PACKETS: u32 = 3;
+
+// 16bit RTP sequence number 를 wrap-around 를 고려한 64bit 로 확장한다.
+pub struct ExtendedSequence {
+    started: bool,
+    // 첫 packet 보다 앞선 sn 이 와도 음수가 되지 않도록 한 cycle 을 더해서 시작한다.
+    cycle: u64,
+    max_sn: u16,
+    // 건너뛴 것으로 의심되는 sn 이 이어서 온 개수와 그 다음에 올 sn
+    probation: u32,
+    probation_sn: u16,
+}
+
+impl ExtendedSequence {
+    pub fn new() -> Self {
+        ExtendedSequence {
+            started: false,
+            cycle: SEQUENCE_CYCLE,
+            max_sn: 0,
+            probation: 0,
+            probation_sn: 0,
+        }
+    }
+
+    pub fn update(&mut self, sn: u16) -> u64 {
+        if !self.started {
+            self.started = true;
+            self.max_sn = sn;
+        } else if sn.wrapping_sub(self.max_sn) & 0x8000 == 0 {
+            if sn < self.max_sn {
+                self.cycle += SEQUENCE_CYCLE;
+            }
+            self.max_sn = sn;
+            self.probation = 0;
+        } else {
+            let extended = if sn > self.max_sn {
+                // wrap-around 이전 cycle 에서 늦게 도착한 packet
+                self.cycle - SEQUENCE_CYCLE + sn as u64
+            } else {
+                self.cycle + sn as u64
+            };
+            if self.max_sn.wrapping_sub(sn) > MAX_MISORDER {
+                self.on_probation(sn, extended);
+            }
+            return extended;
+        }
+        self.cycle + sn as u64
+    }
+
+    // 반 cycle 넘게 앞으로 건너뛰면 늦은 packet 처럼 보이므로, 이어지는 sn 이 계속 오면 max 를 옮긴다.
+    // 이미 돌려준 값과 이어지도록 cycle 을 맞춘다.
+    fn on_probation(&mut self, sn: u16, extended: u64) {
+        if self.probation > 0 && self.probation_sn == sn {
+            self.probation += 1;
+        } else {
+            self.probation = 1;
+        }
+        self.probation_sn = sn.wrapping_add(1);
+        if self.probation >= RESYNC_PACKETS {
+            self.cycle = extended - sn as u64;
+            self.max_sn = sn;
+            self.probation = 0;
+        }
+    }
+
+    pub fn max(&self) -> u64 {
+        self.cycle + self.max_sn as u64
+    }
+}
+
+#[derive(Debug, Default, Clone, Copy)]
+pub struct JitterBufferStats {
+    pub lost: u64,
+    pub late: u64,
+    pub dropped_frames: u64,
+}
+
+pub struct JitterBuffer {
+    latency: Duration,
+    // video 는 marker bit 로 frame 을 구분하고, audio 는 packet 하나를 frame 으로 본다.
+    frame_by_marker: bool,
+
+    packets: BTreeMap<u64, (Instant, Packet)>,
+    next_sn: Option<u64>,
+    frame: Vec<Packet>,
+    // 유실이 발생한 frame 을 버리는 중인지 여부
+    corrupted: bool,
+    // 마지막 pop_frames 이후에 버린 frame 이 있는지 여부
+    dropped: bool,
+    last_marker: bool,
+    // 이어서 늦게 온 packet 수
+    late_packets: u32,
+    stats: JitterBufferStats,
+}
+
+impl JitterBuffer {
+    pub fn new(latency: Duration, frame_by_marker: bool) -> Self {
+        JitterBuffer {
+            latency,
+            frame_by_marker,
+            packets: BTreeMap::new(),
+            next_sn: None,
+            frame: Vec::new(),
+            corrupted: false,
+            dropped: false,
+            last_marker: true,
+            late_packets: 0,
+            stats: JitterBufferStats::default(),
+        }
+    }
+
+    pub fn stats(&self) -> JitterBufferStats {
+        self.stats
+    }
+
+    pub fn push(&mut self, sn: u64, packet: Packet, now: Instant) {
+        let next_sn = *self.next_sn.get_or_insert(sn);
+        if sn < next_sn {
+            self.stats.late += 1;
+            self.late_packets += 1;
+            if self.late_packets < RESYNC_PACKETS {
+                return;
+            }
+            // 늦은 packet 이 계속 오면 publisher 의 sn 이 뒤로 건너뛴 것이므로 그 sn 부터 다시 시작한다.
+            self.packets.clear();
+            self.next_sn = Some(sn);
+            self.on_loss();
+        }
+        self.late_packets = 0;
+        self.packets.entry(sn).or_insert((now, packet));
+    }
+
+    // 순서대로 완성된 frame 들을 꺼낸다. 그 사이 유실로 버린 frame 이 있었는지도 알려준다.
+    pub fn pop_frames(&mut self, now: Instant) -> (Vec<Vec<Packet>>, bool) {
+        let mut frames = Vec::new();
+        loop {
+            let Some(next_sn) = self.next_sn else {
+                break;
+            };
+            if let Some((_, packet)) = self.packets.remove(&next_sn) {
+                self.next_sn = Some(next_sn + 1);
+                if let Some(frame) = self.on_packet(packet) {
+                    frames.push(frame);
+                }
+                continue;
+            }
+
+            // 다음 sn 이 없으면 가장 오래 기다린 packet 이 latency 를 넘었을 때만 유실로 처리한다.
+            let Some((&first_sn, (arrival, _))) = self.packets.first_key_value() else {
+                break;
+            };
+            let expired = now.duration_since(*arrival) >= self.latency;
+            if !expired && self.packets.len() < MAX_PACKETS {
+                break;
+            }
+            self.stats.lost += first_sn - next_sn;
+            self.next_sn = Some(first_sn);
+            self.on_loss();
+        }
+        (frames, std::mem::take(&mut self.dropped))
+    }
+
+    fn on_packet(&mut self, packet: Packet) -> Option<Vec<Packet>> {
+        if !self.frame_by_marker {
+            return Some(vec![packet]);
+        }
+
+        let marker = packet.header.marker;
+        let frame_start = self.last_marker;
+        self.last_marker = marker;
+        if self.corrupted {
+            // 이전 packet 이 frame 의 끝이었던 경우에만 새 frame 으로 다시 시작한다.
+            if !frame_start {
+                return None;
+            }
+            self.corrupted = false;
+        }
+
+        self.frame.push(packet);
+        if marker {
+            return Some(std::mem::take(&mut self.frame));
+        }
+        None
+    }
+
+    fn on_loss(&mut self) {
+        if !self.frame_by_marker {
+            return;
+        }
+        if !self.corrupted {
+            self.stats.dropped_frames += 1;
+        }
+        self.frame.clear();
+        self.corrupted = true;
+        self.dropped = true;
+        self.last_marker = false;
+    }
+}
+
+// 버린 frame 뒤의 P-frame 은 버린 frame 을 참조하므로, publisher 에 keyframe 을 요청하고 받을 때까지 버린다.
+// marker 로 frame 의 경계만 맞추는 JitterBuffer 와 달리 parse 한 keyframe 여부로 판단한다.
+#[derive(Default)]
+pub struct KeyframeGate {
+    waiting: bool,
+}
+
+impl KeyframeGate {
+    pub fn on_drop(&mut self, source: &HubSource) {
+        self.waiting = true;
+        // PLI 간격은 send_pli 에서 제한한다.
+        source.request_keyframe();
+    }
+
+    pub fn pass(&mut self, frame_info: &FrameInfo) -> bool {
+        if frame_info.flag == 1 {
+            self.waiting = false;
+        }
+        !self.waiting
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use webrtc::rtp::header::Header;
+
+    fn packet(sn: u16, timestamp: u32, marker: bool) -> Packet {
+        Packet {
+            header: Header {
+                sequence_number: sn,
+                timestamp,
+                marker,
+                ..Default::default()
+            },
+            payload: bytes::Bytes::from_static(&[0x01]),
+        }
+    }
+
+    #[test]
+    fn test_extended_sequence() {
+        let mut seq = ExtendedSequence::new();
+        let first = seq.update(65534);
+        assert_eq!(seq.update(65535), first + 1);
+        assert_eq!(seq.update(1), first + 3);
+        assert_eq!(seq.update(0), first + 2);
+        assert_eq!(seq.update(65533), first - 1);
+    }
+
+    #[test]
+    fn test_extended_sequence_jump() {
+        let mut seq = ExtendedSequence::new();
+        let first = seq.update(1000);
+        // 반 cycle 넘게 건너뛰면 처음에는 늦은 packet 으로 보인다.
+        let jumped = seq.update(40000);
+        assert!(jumped < first);
+        assert_eq!(seq.update(40001), jumped + 1);
+        assert_eq!(seq.update(40002), jumped + 2);
+        // 이어서 오면 max 가 옮겨진다.
+        assert_eq!(seq.max(), jumped + 2);
+        assert_eq!(seq.update(40003), jumped + 3);
+        assert_eq!(seq.update(39990), jumped - 10);
+    }
+
+    #[test]
+    fn test_resync_after_late_packets() {
+        let latency = Duration::from_millis(100);
+        let now = Instant::now();
+        let mut buffer = JitterBuffer::new(latency, false);
+        let mut seq = ExtendedSequence::new();
+
+        for sn in 1000..1003 {
+            buffer.push(seq.update(sn), packet(sn, 0, true), now);
+        }
+        assert_eq!(buffer.pop_frames(now).0.len(), 3);
+
+        // publisher 가 다시 시작해서 sn 이 건너뛰었다.
+        for sn in 40000..40005 {
+            buffer.push(seq.update(sn), packet(sn, 0, true), now);
+        }
+        let (frames, _) = buffer.pop_frames(now);
+        let sns: Vec<u16> = frames
+            .iter()
+            .map(|frame| frame[0].header.sequence_number)
+            .collect();
+        assert_eq!(sns, vec![40002, 40003, 40004]);
+        assert_eq!(buffer.stats().late, 3);
+
+        for sn in 40005..40007 {
+            buffer.push(seq.update(sn), packet(sn, 0, true), now);
+        }
+        assert_eq!(buffer.pop_frames(now).0.len(), 2);
+    }
+
+    #[test]
+    fn test_reorder_and_drop_incomplete_frame() {
+        let latency = Duration::from_millis(100);
+        let now = Instant::now();
+        let mut buffer = JitterBuffer::new(latency, true);
+
+        // frame 1: sn 10, 11(marker) 순서가 바뀌어 도착
+        buffer.push(10, packet(10, 1000, false), now);
+        buffer.push(12, packet(12, 2000, false), now);
+        buffer.push(11, packet(11, 1000, true), now);
+        let (frames, dropped) = buffer.pop_frames(now);
+        assert!(!dropped);
+        assert_eq!(frames.len(), 1);
+        assert_eq!(frames[0].len(), 2);
+        assert_eq!(frames[0][1].header.sequence_number, 11);
+
+        // frame 2: sn 13 유실, frame 3: sn 15, 16(marker)
+        buffer.push(14, packet(14, 2000, true), now);
+        buffer.push(15, packet(15, 3000, false), now);
+        buffer.push(16, packet(16, 3000, true), now);
+        assert!(buffer.pop_frames(now).0.is_empty());
+
+        let (frames, dropped) = buffer.pop_frames(now + latency);
+        assert!(dropped);
+        assert_eq!(frames.len(), 1);
+        assert_eq!(frames[0][0].header.sequence_number, 15);
+        let stats = buffer.stats();
+        assert_eq!(stats.lost, 1);
+        assert_eq!(stats.dropped_frames, 1);
+
+        buffer.push(13, packet(13, 2000, false), now);
+        assert_eq!(buffer.stats().late, 1);
+    }
+
+    #[tokio::test]
+    async fn test_drop_requests_keyframe() {
+        let latency = Duration::from_millis(100);
+        let now = Instant::now();
+        let mut buffer = JitterBuffer::new(latency, true);
+        let source = HubSource::new();
+        let mut gate = KeyframeGate::default();
+
+        // sn 1 유실
+        buffer.push(0, packet(0, 1000, true), now);
+        buffer.push(2, packet(2, 2000, true), now);
+        buffer.push(3, packet(3, 3000, true), now);
+        let (frames, dropped) = buffer.pop_frames(now + latency);
+        assert!(dropped);
+        // sn 2 는 이어지는 frame 이 아니어서 버린다.
+        assert_eq!(frames.len(), 2);
+        gate.on_drop(&source);
+        tokio::time::timeout(Duration::from_millis(10), source.keyframe_requested())
+            .await
+            .unwrap();
+
+        // 다음 marker 부터 frame 은 다시 나오지만 keyframe 전까지는 버린다.
+        assert!(!gate.pass(&FrameInfo { flag: 0 }));
+        assert!(gate.pass(&FrameInfo { flag: 1 }));
+        assert!(gate.pass(&FrameInfo { flag: 0 }));
+    }
+}
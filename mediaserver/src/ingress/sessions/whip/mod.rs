@@ -1,2 +1,6 @@
+pub mod jitter_buffer;
+pub mod nack;
+pub mod rtx;
+pub mod simulcast;
 pub mod stats;
 pub mod whip;
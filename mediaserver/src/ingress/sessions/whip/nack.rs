@@ -0,0 +1,111 @@
+use std::collections::BTreeMap;
+use std::time::{Duration, Instant};
+use webrtc::rtcp::transport_feedbacks::transport_layer_nack::NackPair;
+
+// 추적하는 missing packet 의 최대 개수
+const MAX_MISSING_PACKETS: usize = 512;
+// 같은 sn 에 대해 NACK 을 다시 보내기 전에 기다리는 시간
+const NACK_RETRY_INTERVAL: Duration = Duration::from_millis(100);
+const MAX_NACK_RETRIES: u32 = 5;
+
+#[derive(Default)]
+struct MissingPacket {
+    nack_count: u32,
+    last_nack: Option<Instant>,
+}
+
+// 확장 sequence number 기준으로 도착하지 않은 packet 을 기록하고 NACK 대상을 고른다.
+#[derive(Default)]
+pub struct NackList {
+    missing: BTreeMap<u32, MissingPacket>,
+}
+
+impl NackList {
+    pub fn new() -> Self {
+        NackList {
+            missing: BTreeMap::new(),
+        }
+    }
+
+    // max sn 이 prev 에서 current 로 늘어났을 때 사이의 sn 들을 missing 으로 기록한다.
+    pub fn on_advance(&mut self, prev: u32, current: u32) {
+        let start = prev
+            .wrapping_add(1)
+            .max(current.saturating_sub(MAX_MISSING_PACKETS as u32));
+        for sn in start..current {
+            self.missing.insert(sn, MissingPacket::default());
+        }
+        while self.missing.len() > MAX_MISSING_PACKETS {
+            self.missing.pop_first();
+        }
+    }
+
+    // 늦게 도착한 packet(재전송 또는 순서 바뀜)이 missing 이었으면 true, 중복이면 false
+    pub fn on_recovered(&mut self, sn: u32) -> bool {
+        self.missing.remove(&sn).is_some()
+    }
+
+    // 이번에 NACK 을 보낼 sn 목록. 재시도 횟수를 넘긴 sn 은 포기한다.
+    pub fn take_nack_sequence_numbers(&mut self, now: Instant) -> Vec<u16> {
+        let mut sns = vec![];
+        self.missing.retain(|sn, missing| {
+            if missing.nack_count >= MAX_NACK_RETRIES {
+                return false;
+            }
+            if let Some(last_nack) = missing.last_nack {
+                if now.duration_since(last_nack) < NACK_RETRY_INTERVAL {
+                    return true;
+                }
+            }
+            missing.nack_count += 1;
+            missing.last_nack = Some(now);
+            sns.push(*sn as u16);
+            true
+        });
+        sns
+    }
+}
+
+// RFC 4585 Generic NACK: PID 와 뒤따르는 16개 sn 의 bitmask(BLP)로 묶는다.
+pub fn nack_pairs(sns: &[u16]) -> Vec<NackPair> {
+    let mut pairs: Vec<NackPair> = vec![];
+    for &sn in sns {
+        if let Some(pair) = pairs.last_mut() {
+            let diff = sn.wrapping_sub(pair.packet_id);
+            if (1..=16).contains(&diff) {
+                pair.lost_packets |= 1 << (diff - 1);
+                continue;
+            }
+        }
+        pairs.push(NackPair {
+            packet_id: sn,
+            lost_packets: 0,
+        });
+    }
+    pairs
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_nack_list() {
+        let mut list = NackList::new();
+        let now = Instant::now();
+        list.on_advance(10, 14);
+        assert!(list.on_recovered(12));
+        assert!(!list.on_recovered(12));
+
+        assert_eq!(list.take_nack_sequence_numbers(now), vec![11, 13]);
+        assert!(list.take_nack_sequence_numbers(now).is_empty());
+        let later = now + NACK_RETRY_INTERVAL;
+        assert_eq!(list.take_nack_sequence_numbers(later), vec![11, 13]);
+
+        let pairs = nack_pairs(&[65534, 65535, 1, 20]);
+        assert_eq!(pairs.len(), 2);
+        assert_eq!(pairs[0].packet_id, 65534);
+        assert_eq!(pairs[0].lost_packets, 0b101);
+        assert_eq!(pairs[1].packet_id, 20);
+    }
+}
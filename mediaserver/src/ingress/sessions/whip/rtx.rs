@@ -0,0 +1,49 @@
+use webrtc::rtp::packet::Packet;
+
+pub const MIME_TYPE_RTX: &str = "video/rtx";
+
+// fmtp 의 apt(associated payload type) 를 읽는다. ex) "apt=102"
+pub fn parse_apt(fmtp: &str) -> Option<u8> {
+    fmtp.split(';')
+        .filter_map(|param| param.trim().split_once('='))
+        .find(|(key, _)| key.trim().eq_ignore_ascii_case("apt"))
+        .and_then(|(_, value)| value.trim().parse().ok())
+}
+
+// RFC 4588: payload 앞 2byte 의 OSN(original sequence number) 을 꺼내서
+// 원래 stream 의 packet 으로 되돌린다. payload 가 없는 padding packet 은 None
+pub fn unwrap_rtx(mut packet: Packet, apt: u8) -> Option<Packet> {
+    if packet.payload.len() <= 2 {
+        return None;
+    }
+    let osn = u16::from_be_bytes([packet.payload[0], packet.payload[1]]);
+    packet.header.sequence_number = osn;
+    packet.header.payload_type = apt;
+    packet.header.padding = false;
+    packet.payload = packet.payload.slice(2..);
+    Some(packet)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bytes::Bytes;
+
+    #[test]
+    fn test_unwrap_rtx() {
+        assert_eq!(parse_apt("apt=102"), Some(102));
+        assert_eq!(parse_apt("foo=1; apt=96"), Some(96));
+        assert_eq!(parse_apt("profile-level-id=42001f"), None);
+
+        let mut packet = Packet::default();
+        packet.header.sequence_number = 7;
+        packet.header.payload_type = 103;
+        packet.payload = Bytes::from_static(&[0x12, 0x34, 0x65, 0x88]);
+        let packet = unwrap_rtx(packet, 102).unwrap();
+        assert_eq!(packet.header.sequence_number, 0x1234);
+        assert_eq!(packet.header.payload_type, 102);
+        assert_eq!(packet.payload.as_ref(), &[0x65, 0x88]);
+
+        assert!(unwrap_rtx(Packet::default(), 102).is_none());
+    }
+}
@@ -0,0 +1,60 @@
+// simulcast 로 받을 수 있는 최대 layer 수
+pub const MAX_LAYERS: usize = 3;
+
+// RFC 8853 의 rid, mid header extension. layer 마다 ssrc 가 다르므로 rid 로 구분한다.
+pub const HEADER_EXTENSIONS: [&str; 3] = [
+    "urn:ietf:params:rtp-hdrext:sdes:mid",
+    "urn:ietf:params:rtp-hdrext:sdes:rtp-stream-id",
+    "urn:ietf:params:rtp-hdrext:sdes:repaired-rtp-stream-id",
+];
+
+// offer 의 video m-section 에서 보내는 쪽 rid 들을 읽는다. ex) "a=rid:h send"
+// a=simulcast 가 없으면 simulcast 가 아니므로 빈 vec
+pub fn parse_send_rids(sdp: &str) -> Vec<String> {
+    let mut rids = vec![];
+    let mut simulcast = false;
+    let mut video = false;
+    for line in sdp.lines().map(str::trim) {
+        if let Some(media) = line.strip_prefix("m=") {
+            video = media.starts_with("video");
+            continue;
+        }
+        if !video {
+            continue;
+        }
+        if line.starts_with("a=simulcast:") {
+            simulcast = true;
+        } else if let Some(rid) = line.strip_prefix("a=rid:") {
+            let mut fields = rid.split_whitespace();
+            if let (Some(id), Some("send")) = (fields.next(), fields.next()) {
+                rids.push(id.to_string());
+            }
+        }
+    }
+    if !simulcast {
+        return vec![];
+    }
+    rids
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_send_rids() {
+        let sdp = "v=0\r\n\
+            m=audio 9 UDP/TLS/RTP/SAVPF 111\r\n\
+            a=rid:x send\r\n\
+            m=video 9 UDP/TLS/RTP/SAVPF 102\r\n\
+            a=rid:h send\r\n\
+            a=rid:m send max-width=640\r\n\
+            a=rid:l send\r\n\
+            a=rid:r recv\r\n\
+            a=simulcast:send h;m;l\r\n";
+        assert_eq!(parse_send_rids(sdp), vec!["h", "m", "l"]);
+
+        let sdp = "v=0\r\nm=video 9 UDP/TLS/RTP/SAVPF 102\r\na=rid:h send\r\n";
+        assert!(parse_send_rids(sdp).is_empty());
+    }
+}
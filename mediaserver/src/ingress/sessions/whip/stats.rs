@@ -1,11 +1,14 @@
+use crate::ingress::sessions::whip::nack::{nack_pairs, NackList};
 use bytes::Bytes;
 use std::default::Default;
 use std::ops::Sub;
 use std::sync::atomic::Ordering;
 use std::sync::Arc;
+use std::time::Instant;
 use tokio::sync::RwLock;
 use webrtc::rtcp::receiver_report::ReceiverReport;
 use webrtc::rtcp::reception_report::ReceptionReport;
+use webrtc::rtcp::transport_feedbacks::transport_layer_nack::TransportLayerNack;
 use webrtc::rtp;
 use webrtc::util::MarshalSize;
 
@@ -20,6 +23,7 @@ struct ReadStats {
     cycle: u32,
     last_transit: u32,
     jitter: f64,
+    nack_list: NackList,
 }
 
 impl ReadStats {
@@ -33,6 +37,7 @@ impl ReadStats {
             cycle: 0,
             last_transit: 0,
             jitter: 0.0,
+            nack_list: NackList::new(),
         }
     }
 
@@ -42,12 +47,22 @@ impl ReadStats {
             self.base_seq_no = sn;
             self.max_seq_no = sn;
         } else if (sn.wrapping_sub(self.max_seq_no)) & 0x8000 == 0 {
+            let prev = self.cycle | self.max_seq_no as u32;
             if sn < self.max_seq_no {
                 self.cycle += MAX_SEQ_NO + 1;
             }
             self.max_seq_no = sn;
+            self.nack_list.on_advance(prev, self.cycle | sn as u32);
         } else if (sn.wrapping_sub(self.max_seq_no)) & 0x8000 > 0 {
-            // todo 재전송
+            // 재전송 또는 순서가 바뀐 packet. missing 이 아니었으면 중복이므로 무시한다.
+            let cycle = if sn > self.max_seq_no {
+                self.cycle.wrapping_sub(MAX_SEQ_NO + 1)
+            } else {
+                self.cycle
+            };
+            if !self.nack_list.on_recovered(cycle | sn as u32) {
+                return;
+            }
         }
         self.packet_count += 1;
         self.total_bytes = self
@@ -107,6 +122,24 @@ impl Stats {
         read_stat.read_stat(packet, diff_milli).await;
     }
 
+    pub async fn make_nack(self: &Arc<Self>, media_ssrc: u32) -> Option<TransportLayerNack> {
+        let sns = {
+            let mut read_stat = self.read_stat.write().await;
+            read_stat
+                .nack_list
+                .take_nack_sequence_numbers(Instant::now())
+        };
+        if sns.is_empty() {
+            return None;
+        }
+
+        Some(TransportLayerNack {
+            sender_ssrc: 0,
+            media_ssrc,
+            nacks: nack_pairs(&sns),
+        })
+    }
+
     pub async fn make_receiver_report(self: &Arc<Self>, ssrc: u32) -> ReceiverReport {
         let (jitter, max_seq_no, packet_expect, packet_lost) = {
             let read_stats = self.read_stat.read().await;
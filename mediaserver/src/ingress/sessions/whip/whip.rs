@@ -4,12 +4,17 @@ use crate::codecs::rtp_parser::RtpParser;
 use crate::hubs::source::HubSource;
 use crate::hubs::stream::HubStream;
 use crate::hubs::unit::HubUnit;
+use crate::ingress::sessions::whip::jitter_buffer::{ExtendedSequence, JitterBuffer, KeyframeGate};
+use crate::ingress::sessions::whip::rtx;
+use crate::ingress::sessions::whip::simulcast;
 use crate::ingress::sessions::whip::stats::Stats;
+use crate::webrtc_wrapper::ice;
 use crate::webrtc_wrapper::webrtc_api::WebRtcApi;
 use anyhow::anyhow;
+use std::collections::HashMap;
 use std::sync::Arc;
-use std::time::Duration;
-use tokio::sync::mpsc;
+use std::time::{Duration, Instant};
+use tokio::sync::{mpsc, RwLock};
 use tokio::time;
 use tokio_util::sync::CancellationToken;
 use webrtc::api::media_engine::{MediaEngine, MIME_TYPE_H264, MIME_TYPE_OPUS};
@@ -22,20 +27,40 @@ use webrtc::peer_connection::{
 };
 use webrtc::rtcp::payload_feedbacks::picture_loss_indication::PictureLossIndication;
 use webrtc::rtcp::payload_feedbacks::receiver_estimated_maximum_bitrate::ReceiverEstimatedMaximumBitrate;
+use webrtc::rtp::packet::Packet;
 use webrtc::rtp_transceiver::rtp_codec::{
-    RTCRtpCodecCapability, RTCRtpCodecParameters, RTPCodecType,
+    RTCRtpCodecCapability, RTCRtpCodecParameters, RTCRtpHeaderExtensionCapability, RTPCodecType,
 };
 use webrtc::rtp_transceiver::rtp_receiver::RTCRtpReceiver;
+use webrtc::rtp_transceiver::RTCPFeedback;
 use webrtc::track::track_remote::TrackRemote;
 
+// jitter buffer 에서 latency 가 지난 패킷을 꺼내기 위한 주기
+const JITTER_BUFFER_TICK: Duration = Duration::from_millis(10);
+const RTCP_REPORT_INTERVAL: Duration = Duration::from_secs(1);
+// missing packet 을 확인해서 NACK 을 보내는 주기
+const NACK_INTERVAL: Duration = Duration::from_millis(20);
+// keyframe 요청이 몰려도 이 간격보다 자주 PLI 를 보내지 않는다.
+const PLI_MIN_INTERVAL: Duration = Duration::from_millis(500);
+const H264_PAYLOAD_TYPE: u8 = 102;
+const RTX_PAYLOAD_TYPE: u8 = 103;
+
 pub struct WhipSession {
     pc: RTCPeerConnection,
     hub_stream: Arc<HubStream>,
     token: CancellationToken,
+    jitter_latency: Duration,
+    pli_interval: Option<Duration>,
+    // RTX track 에서 복원한 packet 을 원래 track reader 로 넘긴다.
+    // key 는 원래 track 의 (payload type, rid). simulcast layer 들은 payload type 이 같다.
+    repair_senders: RwLock<HashMap<(u8, String), mpsc::Sender<Packet>>>,
 }
 
 impl WhipSession {
-    pub async fn new() -> anyhow::Result<Arc<Self>> {
+    pub async fn new(
+        jitter_latency: Duration,
+        pli_interval: Option<Duration>,
+    ) -> anyhow::Result<Arc<Self>> {
         // let api = WebRtcApi::new();
         let mut media_engine = MediaEngine::default();
         media_engine.register_codec(
@@ -47,9 +72,32 @@ impl WhipSession {
                     sdp_fmtp_line:
                         "level-asymmetry-allowed=1;packetization-mode=1;profile-level-id=42001f"
                             .to_string(),
+                    rtcp_feedback: vec![
+                        RTCPFeedback {
+                            typ: "nack".to_string(),
+                            parameter: "".to_string(),
+                        },
+                        RTCPFeedback {
+                            typ: "nack".to_string(),
+                            parameter: "pli".to_string(),
+                        },
+                    ],
+                },
+                payload_type: H264_PAYLOAD_TYPE,
+                stats_id: "".to_string(),
+            },
+            RTPCodecType::Video,
+        )?;
+        media_engine.register_codec(
+            RTCRtpCodecParameters {
+                capability: RTCRtpCodecCapability {
+                    mime_type: rtx::MIME_TYPE_RTX.to_string(),
+                    clock_rate: 90000,
+                    channels: 0,
+                    sdp_fmtp_line: format!("apt={}", H264_PAYLOAD_TYPE),
                     rtcp_feedback: vec![],
                 },
-                payload_type: 111,
+                payload_type: RTX_PAYLOAD_TYPE,
                 stats_id: "".to_string(),
             },
             RTPCodecType::Video,
@@ -68,6 +116,16 @@ impl WhipSession {
             },
             RTPCodecType::Audio,
         )?;
+        // simulcast layer 들을 rid 로 구분한다.
+        for uri in simulcast::HEADER_EXTENSIONS {
+            media_engine.register_header_extension(
+                RTCRtpHeaderExtensionCapability {
+                    uri: uri.to_string(),
+                },
+                RTPCodecType::Video,
+                None,
+            )?;
+        }
 
         let api = WebRtcApi::new_with_media_engine(media_engine);
         let pc = api.new_peer_connection().await;
@@ -76,6 +134,9 @@ impl WhipSession {
             pc,
             hub_stream: HubStream::new(),
             token,
+            jitter_latency,
+            pli_interval,
+            repair_senders: RwLock::new(HashMap::new()),
         }))
     }
 
@@ -89,7 +150,18 @@ impl WhipSession {
         self.token.cancelled().await;
         let _ = self.pc.close().await;
     }
+    pub async fn patch(&self, sdpfrag: &str) -> anyhow::Result<Option<String>> {
+        ice::patch(&self.pc, sdpfrag).await
+    }
     pub async fn init(self: &Arc<Self>, offer: &str) -> anyhow::Result<String> {
+        let rids = simulcast::parse_send_rids(offer);
+        if rids.len() > simulcast::MAX_LAYERS {
+            return Err(anyhow!("too many simulcast layers: {}", rids.len()));
+        }
+        if !rids.is_empty() {
+            log::info!("whip simulcast layers: {:?}", rids);
+        }
+
         let (end_candidate, mut wait_candidate) = mpsc::channel(1);
         self.pc
             .on_ice_candidate(self.on_ice_candidate(end_candidate));
@@ -142,9 +214,8 @@ impl WhipSession {
 
             match state {
                 RTCPeerConnectionState::Connected => {}
-                RTCPeerConnectionState::Disconnected
-                | RTCPeerConnectionState::Failed
-                | RTCPeerConnectionState::Closed => {
+                // Disconnected 는 ICE restart 로 복구될 수 있으므로 종료하지 않는다.
+                RTCPeerConnectionState::Failed | RTCPeerConnectionState::Closed => {
                     arc.stop();
                 }
                 _ => {}
@@ -161,11 +232,17 @@ impl WhipSession {
                     return Box::pin(async move {});
                 };
                 let self_ = &arc;
+                if remote
+                    .codec()
+                    .capability
+                    .mime_type
+                    .eq_ignore_ascii_case(rtx::MIME_TYPE_RTX)
+                {
+                    self_.read_rtp_repair(&remote);
+                    return Box::pin(async move {});
+                }
                 let stats = Stats::new(remote.codec().capability.clock_rate);
 
-                if remote.kind() == RTPCodecType::Video {
-                    self_.send_pli(&remote);
-                }
                 self_.send_rtcp(&remote, &stats);
                 if remote.kind() == RTPCodecType::Audio {
                     self_.read_rtp_audio(&remote, &stats);
@@ -176,24 +253,45 @@ impl WhipSession {
             },
         )
     }
-    fn send_pli(self: &Arc<Self>, remote: &Arc<TrackRemote>) {
+    // egress 의 keyframe 요청이 있을 때 PLI 를 보낸다. pli_interval 은 요청과 상관없이 주기적으로 보내는 fallback
+    fn send_pli(self: &Arc<Self>, remote: &Arc<TrackRemote>, source: &Arc<HubSource>) {
         let remote_ = remote.clone();
         let self_ = self.clone();
+        let source_ = source.clone();
         tokio::spawn(async move {
+            let pli_interval = self_.pli_interval;
+            let mut fallback_ticker = time::interval(pli_interval.unwrap_or(PLI_MIN_INTERVAL));
+            let mut last_pli: Option<Instant> = None;
             loop {
                 tokio::select! {
                     _ = self_.token.cancelled() => {
                         break;
                     }
-                    _ = tokio::time::sleep(Duration::from_secs(1)) => {
-                        if let Err(e) = self_.clone().pc.write_rtcp(&[Box::new(PictureLossIndication {
-                            sender_ssrc: 0,
-                            media_ssrc: remote_.ssrc(),
-                        })]).await {
-                            log::warn!("failed to send PLI: {}", e);
-                        };
+                    _ = source_.keyframe_requested() => {
+                        // publisher 의 bitrate 가 튀지 않도록 PLI 간격을 제한한다.
+                        if let Some(elapsed) = last_pli.map(|last_pli| last_pli.elapsed()) {
+                            if elapsed < PLI_MIN_INTERVAL {
+                                tokio::select! {
+                                    _ = self_.token.cancelled() => break,
+                                    _ = time::sleep(PLI_MIN_INTERVAL - elapsed) => {}
+                                }
+                            }
+                        }
                     }
+                    _ = fallback_ticker.tick(), if pli_interval.is_some() => {}
                 }
+
+                last_pli = Some(Instant::now());
+                if let Err(e) = self_
+                    .pc
+                    .write_rtcp(&[Box::new(PictureLossIndication {
+                        sender_ssrc: 0,
+                        media_ssrc: remote_.ssrc(),
+                    })])
+                    .await
+                {
+                    log::warn!("failed to send PLI: {}", e);
+                };
             }
         });
     }
@@ -201,13 +299,32 @@ impl WhipSession {
         let remote_ = remote.clone();
         let self_ = self.clone();
         let stats_ = stats.clone();
+        let nack_enabled = remote
+            .codec()
+            .capability
+            .rtcp_feedback
+            .iter()
+            .any(|feedback| feedback.typ == "nack" && feedback.parameter.is_empty());
         tokio::spawn(async move {
+            let mut report_ticker = time::interval_at(
+                time::Instant::now() + RTCP_REPORT_INTERVAL,
+                RTCP_REPORT_INTERVAL,
+            );
+            let mut nack_ticker = time::interval(NACK_INTERVAL);
             loop {
                 tokio::select! {
                     _ = self_.token.cancelled() => {
                         break;
                     }
-                    _ = tokio::time::sleep(Duration::from_secs(1)) => {
+                    _ = nack_ticker.tick(), if nack_enabled => {
+                        let Some(nack) = stats_.make_nack(remote_.ssrc()).await else {
+                            continue;
+                        };
+                        if let Err(e) = self_.pc.write_rtcp(&[Box::new(nack)]).await {
+                            log::warn!("failed to send nack: {}", e);
+                        };
+                    }
+                    _ = report_ticker.tick() => {
                         let remb = ReceiverEstimatedMaximumBitrate{
                             sender_ssrc: 0,
                             bitrate: 3_000_000f32 ,
@@ -222,6 +339,37 @@ impl WhipSession {
             }
         });
     }
+    fn read_rtp_repair(self: &Arc<Self>, remote: &Arc<TrackRemote>) {
+        let Some(apt) = rtx::parse_apt(&remote.codec().capability.sdp_fmtp_line) else {
+            log::warn!(
+                "rtx without apt: {}",
+                remote.codec().capability.sdp_fmtp_line
+            );
+            return;
+        };
+        let self_ = self.clone();
+        let remote_ = remote.clone();
+        tokio::spawn(async move {
+            loop {
+                tokio::select! {
+                    _ = self_.token.cancelled() => {
+                        break;
+                    }
+                    result = remote_.read_rtp() => {
+                        let Ok((rtp_packet, _)) = result else { break };
+                        let Some(rtp_packet) = rtx::unwrap_rtx(rtp_packet, apt) else {
+                            continue;
+                        };
+                        let key = (apt, remote_.rid().to_string());
+                        let sender = self_.repair_senders.read().await.get(&key).cloned();
+                        if let Some(sender) = sender {
+                            let _ = sender.try_send(rtp_packet);
+                        }
+                    }
+                }
+            }
+        });
+    }
     fn read_rtp_audio(self: &Arc<Self>, remote: &Arc<TrackRemote>, stats: &Arc<Stats>) {
         let self_ = self.clone();
         let remote_ = remote.clone();
@@ -245,59 +393,59 @@ impl WhipSession {
             .unwrap();
             let mut unit_sn: u32 = 0;
             let mut start_first = true;
-            let mut start_sn = 0;
-            let mut cycle = 0;
-            let mut max_sn = 0;
             let mut start_ts = 0;
             let mut last_ts = 0;
             let mut duration: u32 = 0;
             let timebase = remote_.codec().capability.clock_rate;
+            let mut sequence = ExtendedSequence::new();
+            let mut jitter_buffer = JitterBuffer::new(self_.jitter_latency, false);
+            let mut ticker = time::interval(JITTER_BUFFER_TICK);
             loop {
                 tokio::select! {
                     _ = self_.token.cancelled() => {
                         break;
                     }
+                    _ = ticker.tick() => {}
                     result = remote_.read_rtp() => {
                         let Ok((rtp_packet, _)) = result else {
                             break
                          };
                         stats_.calc_rtp_stats(&rtp_packet).await;
-                        if rtp_packet.payload.len() == 0 {
-                            continue;
-                        }
-                        if start_first {
-                            start_first = false;
-                            start_ts = rtp_packet.header.timestamp;
-                            last_ts = rtp_packet.header.timestamp;
+                        let sn = sequence.update(rtp_packet.header.sequence_number);
+                        jitter_buffer.push(sn, rtp_packet, Instant::now());
+                    }
+                }
 
-                            start_sn = rtp_packet.header.sequence_number;
-                            max_sn = start_sn;
-                        } else if rtp_packet.header.sequence_number.wrapping_sub(max_sn) & 0x8000 == 0 {
-                            if rtp_packet.header.sequence_number < max_sn {
-                                cycle += u16::MAX as u64;
-                            }
-                            max_sn = rtp_packet.header.sequence_number;
-                        }
-                        let sn = (cycle | (rtp_packet.header.sequence_number as u64)) - start_sn as u64;
-                        let pts = rtp_packet.header.timestamp.wrapping_sub(start_ts);
-                        let dts = pts;
-                        if rtp_packet.header.timestamp != last_ts {
-                            duration = rtp_packet.header.timestamp.wrapping_sub(last_ts);
-                            last_ts = rtp_packet.header.timestamp;
-                        }
+                let (frames, _) = jitter_buffer.pop_frames(Instant::now());
+                for rtp_packet in frames.into_iter().flatten() {
+                    if rtp_packet.payload.len() == 0 {
+                        continue;
+                    }
+                    if start_first {
+                        start_first = false;
+                        start_ts = rtp_packet.header.timestamp;
+                        last_ts = rtp_packet.header.timestamp;
+                    }
+                    let pts = rtp_packet.header.timestamp.wrapping_sub(start_ts);
+                    let dts = pts;
+                    if rtp_packet.header.timestamp != last_ts {
+                        duration = rtp_packet.header.timestamp.wrapping_sub(last_ts);
+                        last_ts = rtp_packet.header.timestamp;
+                    }
 
-                        let Some((payloads, frame_info)) = parser.parse(rtp_packet.payload).await else {
-                            continue;
-                        };
+                    let Some((payloads, frame_info)) = parser.parse(rtp_packet.payload).await
+                    else {
+                        continue;
+                    };
 
-                        let len = payloads.len();
-                        for (index, payload) in payloads.into_iter().enumerate() {
-                            let marker = index == len - 1;
-                            let frame_info = frame_info.clone();
+                    let len = payloads.len();
+                    for (index, payload) in payloads.into_iter().enumerate() {
+                        let marker = index == len - 1;
+                        let frame_info = frame_info.clone();
 
-                            source
+                        source
                             .write_unit(HubUnit {
-                                sn:unit_sn,
+                                sn: unit_sn,
                                 payload,
                                 pts,
                                 dts,
@@ -305,13 +453,14 @@ impl WhipSession {
                                 timebase,
                                 marker,
                                 frame_info,
-                            }).await;
-                            unit_sn += 1;
-                        }
+                            })
+                            .await;
+                        unit_sn += 1;
                     }
                 }
             }
 
+            log::info!("whip jitter buffer stats: {:?}", jitter_buffer.stats());
             self_.hub_stream.remove_source(source.clone()).await;
             source.stop();
         });
@@ -322,8 +471,13 @@ impl WhipSession {
         let remote_ = remote.clone();
         let stats_ = stats.clone();
         tokio::spawn(async move {
-            let source = HubSource::new();
+            // simulcast 이면 layer 마다 따로 source 를 만든다.
+            let source = match remote_.rid() {
+                "" => HubSource::new(),
+                rid => HubSource::with_rid(rid),
+            };
             self_.hub_stream.add_source(source.clone()).await;
+            self_.send_pli(&remote_, &source);
 
             let source_1 = source.clone();
             let mut parser = RtpParser::new(
@@ -340,55 +494,74 @@ impl WhipSession {
             .unwrap();
             let mut unit_sn: u32 = 0;
             let mut start_first = true;
-            let mut start_sn = 0;
-            let mut cycle = 0;
-            let mut max_sn = 0;
             let mut start_ts = 0;
             let mut last_ts = 0;
             let mut duration = 0;
             let timebase = remote_.codec().capability.clock_rate;
+            let mut sequence = ExtendedSequence::new();
+            let mut jitter_buffer = JitterBuffer::new(self_.jitter_latency, true);
+            let mut keyframe_gate = KeyframeGate::default();
+            let mut ticker = time::interval(JITTER_BUFFER_TICK);
+            let (repair_tx, mut repair_rx) = mpsc::channel(100);
+            let repair_key = (remote_.payload_type(), remote_.rid().to_string());
+            self_
+                .repair_senders
+                .write()
+                .await
+                .insert(repair_key.clone(), repair_tx);
             loop {
                 tokio::select! {
                     _ = self_.token.cancelled() => {
                         break;
                     }
+                    _ = ticker.tick() => {}
                     result = remote_.read_rtp() => {
                         let Ok((rtp_packet, _)) = result else { break };
                         stats_.calc_rtp_stats(&rtp_packet).await;
-                        if rtp_packet.payload.len() == 0 {
-                            continue;
-                        }
-                        if start_first {
-                            start_first = false;
-                            start_ts = rtp_packet.header.timestamp;
-                            last_ts = rtp_packet.header.timestamp;
+                        let sn = sequence.update(rtp_packet.header.sequence_number);
+                        jitter_buffer.push(sn, rtp_packet, Instant::now());
+                    }
+                    Some(rtp_packet) = repair_rx.recv() => {
+                        stats_.calc_rtp_stats(&rtp_packet).await;
+                        let sn = sequence.update(rtp_packet.header.sequence_number);
+                        jitter_buffer.push(sn, rtp_packet, Instant::now());
+                    }
+                }
 
-                            start_sn = rtp_packet.header.sequence_number;
-                            max_sn = start_sn;
-                        } else if rtp_packet.header.sequence_number.wrapping_sub(max_sn) & 0x8000 == 0 {
-                            if rtp_packet.header.sequence_number < max_sn {
-                                cycle += u16::MAX as u64;
-                            }
-                            max_sn = rtp_packet.header.sequence_number;
-                        }
-                        let sn = (cycle | (rtp_packet.header.sequence_number as u64)) - start_sn as u64;
-                        let pts = rtp_packet.header.timestamp.wrapping_sub(start_ts);
-                        let dts = pts;
-                        if rtp_packet.header.timestamp != last_ts {
-                            duration = rtp_packet.header.timestamp.wrapping_sub(last_ts);
-                            last_ts = rtp_packet.header.timestamp;
-                        }
+                let (frames, dropped) = jitter_buffer.pop_frames(Instant::now());
+                if dropped {
+                    keyframe_gate.on_drop(&source);
+                }
+                for rtp_packet in frames.into_iter().flatten() {
+                    if rtp_packet.payload.len() == 0 {
+                        continue;
+                    }
+                    if start_first {
+                        start_first = false;
+                        start_ts = rtp_packet.header.timestamp;
+                        last_ts = rtp_packet.header.timestamp;
+                    }
+                    let pts = rtp_packet.header.timestamp.wrapping_sub(start_ts);
+                    let dts = pts;
+                    if rtp_packet.header.timestamp != last_ts {
+                        duration = rtp_packet.header.timestamp.wrapping_sub(last_ts);
+                        last_ts = rtp_packet.header.timestamp;
+                    }
 
-                        let Some((payloads, frame_info)) = parser.parse(rtp_packet.payload).await else {
-                            continue;
-                        };
+                    let Some((payloads, frame_info)) = parser.parse(rtp_packet.payload).await
+                    else {
+                        continue;
+                    };
+                    if !keyframe_gate.pass(&frame_info) {
+                        continue;
+                    }
 
-                        let len = payloads.len();
-                        for (index, payload) in payloads.into_iter().enumerate() {
-                            let marker = index == len - 1;
-                            let frame_info = frame_info.clone();
+                    let len = payloads.len();
+                    for (index, payload) in payloads.into_iter().enumerate() {
+                        let marker = index == len - 1;
+                        let frame_info = frame_info.clone();
 
-                            source
+                        source
                             .write_unit(HubUnit {
                                 sn: unit_sn,
                                 payload,
@@ -400,12 +573,13 @@ impl WhipSession {
                                 frame_info,
                             })
                             .await;
-                            unit_sn += 1;
-                        }
+                        unit_sn += 1;
                     }
                 }
             }
 
+            log::info!("whip jitter buffer stats: {:?}", jitter_buffer.stats());
+            self_.repair_senders.write().await.remove(&repair_key);
             self_.hub_stream.remove_source(source.clone()).await;
             source.stop();
         });
@@ -8,8 +8,18 @@ mod utils;
 mod webrtc_wrapper;
 
 use crate::hubs::hub::Hub;
+use crate::ingress::servers::rtmp::RtmpServer;
+use crate::ingress::servers::srt::SrtServer;
 use config::{Config, File, FileFormat};
+use serde::Deserialize;
 use std::sync::Arc;
+use std::time::Duration;
+
+#[derive(Deserialize)]
+struct SrtCaller {
+    address: String,
+    stream_id: String,
+}
 
 #[actix_web::main]
 async fn main() -> std::io::Result<()> {
@@ -36,7 +46,46 @@ async fn main() -> std::io::Result<()> {
         .expect("Failed to create Tokio runtime");
 
     let hub = Hub::new();
-    endpoints::build(hub.clone()).await
+
+    if let Ok(address) = config.get::<String>("rtmp.address") {
+        let rtmp_server = RtmpServer::new(hub.clone());
+        tokio::spawn(async move {
+            if let Err(err) = rtmp_server.listen(&address).await {
+                log::error!("rtmp server error: {}", err);
+            }
+        });
+    }
+
+    start_srt(config.clone(), hub.clone());
+
+    endpoints::build(hub.clone(), config.clone()).await
+}
+
+fn start_srt(config: Arc<Config>, hub: Arc<Hub>) {
+    let passphrase = config.get::<String>("srt.passphrase").ok();
+    let latency = Duration::from_millis(config.get::<u64>("srt.latency_ms").unwrap_or(120));
+    let srt_server = SrtServer::new(hub, passphrase, latency);
+
+    if let Ok(address) = config.get::<String>("srt.address") {
+        let srt_server = srt_server.clone();
+        tokio::spawn(async move {
+            if let Err(err) = srt_server.listen(&address).await {
+                log::error!("srt server error: {}", err);
+            }
+        });
+    }
+
+    let callers = config
+        .get::<Vec<SrtCaller>>("srt.callers")
+        .unwrap_or_default();
+    for caller in callers {
+        let srt_server = srt_server.clone();
+        tokio::spawn(async move {
+            if let Err(err) = srt_server.call(&caller.address, &caller.stream_id).await {
+                log::error!("srt caller error: {}", err);
+            }
+        });
+    }
 }
 
 fn init_log(config: Arc<Config>) {
@@ -0,0 +1,263 @@
+use chrono::{DateTime, SecondsFormat, Utc};
+use std::fmt::Write;
+
+// SegmentTemplate 의 timescale. 1 tick = 1ms
+pub const TIMESCALE: u64 = 1000;
+
+// SegmentTimeline 의 S. t, d 는 TIMESCALE 단위
+#[derive(Debug, Clone, PartialEq)]
+pub struct MpdSegment {
+    pub number: u64,
+    pub t: u64,
+    pub d: u64,
+}
+
+// 하나의 Period, 하나의 Representation 으로 된 MPD (ISO/IEC 23009-1)
+// HLS 의 fmp4 segment 는 audio, video 가 한 파일에 mux 되어 있어서 AdaptationSet 을 나눌 수 없다.
+// mux 된 Representation 하나에 ContentComponent 로 들어있는 track 을 알려준다.
+#[derive(Debug, Clone)]
+pub struct Mpd {
+    // false 이면 VOD 로 끝난 static MPD
+    pub dynamic: bool,
+    pub availability_start_time: DateTime<Utc>,
+    pub publish_time: DateTime<Utc>,
+    pub minimum_update_period: f64,
+    pub min_buffer_time: f64,
+    pub suggested_presentation_delay: f64,
+    pub time_shift_buffer_depth: f64,
+    pub max_segment_duration: f64,
+    // init segment 가 바뀌면 새 Period 로 시작한다. start 는 availabilityStartTime 부터(초)
+    pub period_id: u64,
+    pub period_start: f64,
+    // 0 이 아니면 LL-DASH. segment 가 끝나기 이만큼 전부터 chunked transfer 로 받을 수 있다.
+    pub availability_time_offset: f64,
+    // ServiceDescription 의 목표 latency(초). LL-DASH player 가 참고한다.
+    pub target_latency: Option<f64>,
+
+    pub mime_type: String,
+    pub codecs: String,
+    pub bandwidth: u64,
+    pub width: u64,
+    pub height: u64,
+    pub frame_rate: f64,
+    // mux 된 track 의 contentType. ex) ["video", "audio"]
+    pub content_types: Vec<String>,
+
+    // $Number$ 가 들어간 segment uri 와 init segment uri
+    pub media: String,
+    pub initialization: String,
+    pub presentation_time_offset: u64,
+    pub segments: Vec<MpdSegment>,
+}
+
+impl Mpd {
+    pub fn write(&self) -> String {
+        let mut mpd = String::new();
+        let _ = writeln!(mpd, r#"<?xml version="1.0" encoding="UTF-8"?>"#);
+        let profiles = if self.availability_time_offset > 0.0 {
+            "urn:mpeg:dash:profile:isoff-live:2011,urn:mpeg:dash:profile:cmaf:2019"
+        } else {
+            "urn:mpeg:dash:profile:isoff-live:2011"
+        };
+        let _ = write!(
+            mpd,
+            r#"<MPD xmlns="urn:mpeg:dash:schema:mpd:2011" profiles="{}" minBufferTime="{}""#,
+            profiles,
+            duration(self.min_buffer_time),
+        );
+        if self.dynamic {
+            let _ = write!(
+                mpd,
+                r#" type="dynamic" availabilityStartTime="{}" publishTime="{}" minimumUpdatePeriod="{}" suggestedPresentationDelay="{}" timeShiftBufferDepth="{}" maxSegmentDuration="{}">"#,
+                date_time(&self.availability_start_time),
+                date_time(&self.publish_time),
+                duration(self.minimum_update_period),
+                duration(self.suggested_presentation_delay),
+                duration(self.time_shift_buffer_depth),
+                duration(self.max_segment_duration),
+            );
+        } else {
+            let total = self.segments.iter().map(|segment| segment.d).sum::<u64>();
+            let _ = write!(
+                mpd,
+                r#" type="static" mediaPresentationDuration="{}" maxSegmentDuration="{}">"#,
+                duration(total as f64 / TIMESCALE as f64),
+                duration(self.max_segment_duration),
+            );
+        }
+        mpd.push('\n');
+
+        if let (true, Some(target_latency)) = (self.dynamic, self.target_latency) {
+            let _ = writeln!(
+                mpd,
+                r#"  <ServiceDescription id="0"><Latency referenceId="0" target="{}"/></ServiceDescription>"#,
+                (target_latency * 1000.0).round() as u64,
+            );
+        }
+
+        let _ = writeln!(
+            mpd,
+            r#"  <Period id="{}" start="{}">"#,
+            self.period_id,
+            duration(self.period_start),
+        );
+        let _ = writeln!(
+            mpd,
+            r#"    <AdaptationSet id="0" mimeType="{}" segmentAlignment="true" startWithSAP="1">"#,
+            self.mime_type,
+        );
+        if self.content_types.len() > 1 {
+            for (id, content_type) in self.content_types.iter().enumerate() {
+                let _ = writeln!(
+                    mpd,
+                    r#"      <ContentComponent id="{}" contentType="{}"/>"#,
+                    id + 1,
+                    content_type,
+                );
+            }
+        }
+        let _ = write!(
+            mpd,
+            r#"      <Representation id="0" codecs="{}" bandwidth="{}""#,
+            self.codecs, self.bandwidth,
+        );
+        if self.width > 0 && self.height > 0 {
+            let _ = write!(
+                mpd,
+                r#" width="{}" height="{}" frameRate="{}""#,
+                self.width, self.height, self.frame_rate,
+            );
+        }
+        mpd.push_str(">\n");
+
+        let _ = write!(
+            mpd,
+            r#"        <SegmentTemplate timescale="{}" presentationTimeOffset="{}" initialization="{}" media="{}" startNumber="{}""#,
+            TIMESCALE,
+            self.presentation_time_offset,
+            self.initialization,
+            self.media,
+            self.segments.first().map_or(0, |segment| segment.number),
+        );
+        if self.dynamic && self.availability_time_offset > 0.0 {
+            let _ = write!(
+                mpd,
+                r#" availabilityTimeOffset="{}" availabilityTimeComplete="false""#,
+                self.availability_time_offset,
+            );
+        }
+        mpd.push_str(">\n");
+        let _ = writeln!(mpd, "          <SegmentTimeline>");
+        let mut next = None;
+        for segment in &self.segments {
+            // 앞 segment 에 바로 이어지면 t 를 생략한다.
+            if next == Some(segment.t) {
+                let _ = writeln!(mpd, r#"            <S d="{}"/>"#, segment.d);
+            } else {
+                let _ = writeln!(
+                    mpd,
+                    r#"            <S t="{}" d="{}"/>"#,
+                    segment.t, segment.d
+                );
+            }
+            next = Some(segment.t + segment.d);
+        }
+        let _ = writeln!(mpd, "          </SegmentTimeline>");
+        let _ = writeln!(mpd, "        </SegmentTemplate>");
+        let _ = writeln!(mpd, "      </Representation>");
+        let _ = writeln!(mpd, "    </AdaptationSet>");
+        let _ = writeln!(mpd, "  </Period>");
+        if self.dynamic {
+            // player 가 server 시각에 맞춰 live edge 를 계산하도록 한다.
+            let _ = writeln!(
+                mpd,
+                r#"  <UTCTiming schemeIdUri="urn:mpeg:dash:utc:direct:2014" value="{}"/>"#,
+                date_time(&self.publish_time),
+            );
+        }
+        mpd.push_str("</MPD>\n");
+        mpd
+    }
+}
+
+fn date_time(time: &DateTime<Utc>) -> String {
+    time.to_rfc3339_opts(SecondsFormat::Millis, true)
+}
+
+// xs:duration. ex) PT2.5S
+fn duration(seconds: f64) -> String {
+    format!("PT{}S", (seconds * 1000.0).round() / 1000.0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_write_mpd() {
+        let start = DateTime::parse_from_rfc3339("2024-01-01T00:00:00Z")
+            .unwrap()
+            .with_timezone(&Utc);
+        let mut mpd = Mpd {
+            dynamic: true,
+            availability_start_time: start,
+            publish_time: start + chrono::Duration::seconds(10),
+            minimum_update_period: 2.0,
+            min_buffer_time: 1.0,
+            suggested_presentation_delay: 3.0,
+            time_shift_buffer_depth: 6.0,
+            max_segment_duration: 2.0,
+            period_id: 3,
+            period_start: 6.0,
+            availability_time_offset: 1.0,
+            target_latency: Some(3.0),
+            mime_type: "video/mp4".to_string(),
+            codecs: "avc1.42C01F,opus".to_string(),
+            bandwidth: 1000000,
+            width: 640,
+            height: 480,
+            frame_rate: 30.0,
+            content_types: vec!["video".to_string(), "audio".to_string()],
+            media: "video0/output_$Number$.m4s".to_string(),
+            initialization: "video0/init.mp4".to_string(),
+            presentation_time_offset: 500,
+            segments: vec![
+                MpdSegment {
+                    number: 3,
+                    t: 6500,
+                    d: 2000,
+                },
+                MpdSegment {
+                    number: 4,
+                    t: 8500,
+                    d: 1500,
+                },
+                MpdSegment {
+                    number: 5,
+                    t: 10100,
+                    d: 2000,
+                },
+            ],
+        };
+
+        let xml = mpd.write();
+        assert!(xml.contains(r#"type="dynamic" availabilityStartTime="2024-01-01T00:00:00.000Z""#));
+        assert!(xml.contains(r#"<Period id="3" start="PT6S">"#));
+        assert!(xml.contains(r#"presentationTimeOffset="500""#));
+        assert!(xml.contains(r#"startNumber="3""#));
+        assert!(xml.contains(r#"availabilityTimeOffset="1" availabilityTimeComplete="false""#));
+        assert!(xml.contains(
+            "<S t=\"6500\" d=\"2000\"/>\n            <S d=\"1500\"/>\n            <S t=\"10100\" d=\"2000\"/>"
+        ));
+        assert!(xml.contains(r#"<Latency referenceId="0" target="3000"/>"#));
+        assert!(xml.contains(
+            "<ContentComponent id=\"1\" contentType=\"video\"/>\n      <ContentComponent id=\"2\" contentType=\"audio\"/>"
+        ));
+
+        mpd.dynamic = false;
+        let xml = mpd.write();
+        assert!(xml.contains(r#"type="static" mediaPresentationDuration="PT5.5S""#));
+        assert!(!xml.contains("availabilityTimeOffset"));
+        assert!(!xml.contains("UTCTiming"));
+    }
+}
@@ -0,0 +1,109 @@
+use bytes::Bytes;
+
+const CODEC_ID_AVC: u8 = 7;
+const SOUND_FORMAT_AAC: u8 = 10;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum AvcPacketType {
+    SequenceHeader,
+    Nalu,
+    EndOfSequence,
+}
+
+#[derive(Debug, Clone)]
+pub struct VideoTag {
+    pub keyframe: bool,
+    pub packet_type: AvcPacketType,
+    pub composition_time: i32,
+    pub data: Bytes,
+}
+
+impl VideoTag {
+    // VIDEODATA + AVCVIDEOPACKET (FLV spec E.4.3)
+    pub fn from(data: Bytes) -> anyhow::Result<VideoTag> {
+        if data.len() < 5 {
+            anyhow::bail!("short video tag: {}", data.len());
+        }
+        let frame_type = data[0] >> 4;
+        let codec_id = data[0] & 0x0F;
+        if codec_id != CODEC_ID_AVC {
+            anyhow::bail!("unsupported video codec id: {}", codec_id);
+        }
+        let packet_type = match data[1] {
+            0 => AvcPacketType::SequenceHeader,
+            1 => AvcPacketType::Nalu,
+            2 => AvcPacketType::EndOfSequence,
+            v => anyhow::bail!("invalid avc packet type: {}", v),
+        };
+        // SI24
+        let composition_time =
+            ((((data[2] as u32) << 16) | ((data[3] as u32) << 8) | data[4] as u32) << 8) as i32
+                >> 8;
+
+        Ok(VideoTag {
+            keyframe: frame_type == 1,
+            packet_type,
+            composition_time,
+            data: data.slice(5..),
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum AacPacketType {
+    SequenceHeader,
+    Raw,
+}
+
+#[derive(Debug, Clone)]
+pub struct AudioTag {
+    pub packet_type: AacPacketType,
+    pub data: Bytes,
+}
+
+impl AudioTag {
+    // AUDIODATA + AACAUDIODATA (FLV spec E.4.2)
+    pub fn from(data: Bytes) -> anyhow::Result<AudioTag> {
+        if data.len() < 2 {
+            anyhow::bail!("short audio tag: {}", data.len());
+        }
+        let sound_format = data[0] >> 4;
+        if sound_format != SOUND_FORMAT_AAC {
+            anyhow::bail!("unsupported sound format: {}", sound_format);
+        }
+        let packet_type = match data[1] {
+            0 => AacPacketType::SequenceHeader,
+            _ => AacPacketType::Raw,
+        };
+
+        Ok(AudioTag {
+            packet_type,
+            data: data.slice(2..),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_video_tag_negative_composition_time() -> anyhow::Result<()> {
+        let data = Bytes::from_static(&[0x27, 0x01, 0xFF, 0xFF, 0xDF, 0x00, 0x00, 0x00, 0x01]);
+        let tag = VideoTag::from(data)?;
+        assert!(!tag.keyframe);
+        assert_eq!(tag.packet_type, AvcPacketType::Nalu);
+        assert_eq!(tag.composition_time, -33);
+        assert_eq!(tag.data.len(), 4);
+        Ok(())
+    }
+
+    #[test]
+    fn test_audio_tag_sequence_header() -> anyhow::Result<()> {
+        let data = Bytes::from_static(&[0xAF, 0x00, 0x12, 0x10]);
+        let tag = AudioTag::from(data)?;
+        assert_eq!(tag.packet_type, AacPacketType::SequenceHeader);
+        assert_eq!(tag.data.as_ref(), &[0x12, 0x10]);
+        Ok(())
+    }
+}
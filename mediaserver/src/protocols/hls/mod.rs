@@ -1,3 +1,101 @@
+use m3u8_rs::MediaPlaylist;
+
+// 다른 rendition 의 마지막 segment/part 위치 (EXT-X-RENDITION-REPORT)
+#[derive(Debug, Clone)]
+pub struct RenditionReport {
+    pub uri: String,
+    pub last_msn: u64,
+    pub last_part: Option<i32>,
+}
+
+// 끝에서부터 can_skip_until 초보다 앞선 segment 들을 뺀 delta playlist 와 뺀 segment 수.
+// EXT-X-MEDIA-SEQUENCE 는 원래 playlist 와 같아야 하므로 그대로 둔다. 마지막 segment 는 빼지 않는다.
+pub fn skip_segments(playlist: &MediaPlaylist, can_skip_until: f32) -> (MediaPlaylist, usize) {
+    let mut remaining: f32 = playlist
+        .segments
+        .iter()
+        .map(|segment| segment.duration)
+        .chain(playlist.parts.iter().map(|part| part.duration))
+        .sum();
+
+    let mut skipped = 0;
+    let last = playlist.segments.len().saturating_sub(1);
+    for segment in playlist.segments[..last].iter() {
+        if remaining <= can_skip_until {
+            break;
+        }
+        remaining -= segment.duration;
+        skipped += 1;
+    }
+
+    let mut delta = playlist.clone();
+    delta.segments.drain(..skipped);
+    (delta, skipped)
+}
+
+// m3u8-rs 가 쓰지 못하는 EXT-X-SKIP, EXT-X-RENDITION-REPORT 를 붙여서 playlist 를 쓴다.
+// todo m3u8-rs fork 의 MediaPlaylist 에 skip, rendition report 를 추가해서 writer 가 쓰게 하고 문자열 처리는 없앤다.
+pub fn write_playlist(
+    playlist: &MediaPlaylist,
+    skipped: usize,
+    reports: &[RenditionReport],
+) -> anyhow::Result<Vec<u8>> {
+    let mut m3u8 = playlist_string(playlist)?;
+    if skipped > 0 {
+        let offset = first_segment_offset(playlist, &m3u8)?;
+        m3u8.insert_str(
+            offset,
+            &format!("#EXT-X-SKIP:SKIPPED-SEGMENTS={}\n", skipped),
+        );
+    }
+    for report in reports {
+        m3u8.push_str(&rendition_report_tag(report));
+    }
+    Ok(m3u8.into_bytes())
+}
+
+fn playlist_string(playlist: &MediaPlaylist) -> anyhow::Result<String> {
+    let mut buffer = Vec::new();
+    playlist.write_to(&mut buffer)?;
+    Ok(String::from_utf8(buffer)?)
+}
+
+// EXT-X-SKIP 은 뺀 segment 자리, 즉 첫 segment 앞에 있어야 한다.
+// segment 가 어떤 tag(EXT-X-MAP, EXT-X-PART 등)로 시작하는지 writer 에 기대지 않도록,
+// 첫 segment 를 뺀 playlist 와 비교해서 첫 segment 가 시작하는 줄을 찾는다.
+fn first_segment_offset(playlist: &MediaPlaylist, m3u8: &str) -> anyhow::Result<usize> {
+    if playlist.segments.is_empty() {
+        return Err(anyhow::anyhow!("no segment to skip before"));
+    }
+    let mut rest = playlist.clone();
+    rest.segments.remove(0);
+    let rest = playlist_string(&rest)?;
+    let (m3u8, rest) = (m3u8.as_bytes(), rest.as_bytes());
+    let Some(segment_len) = m3u8.len().checked_sub(rest.len()) else {
+        return Err(anyhow::anyhow!("unexpected playlist length"));
+    };
+    // 첫 segment 의 uri 는 다른 곳에 없으므로 앞뒤가 모두 같은 줄은 하나뿐이다.
+    (0..=rest.len())
+        .filter(|&offset| offset == 0 || rest[offset - 1] == b'\n')
+        .find(|&offset| {
+            m3u8[..offset] == rest[..offset] && m3u8[offset + segment_len..] == rest[offset..]
+        })
+        .ok_or(anyhow::anyhow!("first segment not found in playlist"))
+}
+
+fn rendition_report_tag(report: &RenditionReport) -> String {
+    match report.last_part {
+        Some(last_part) => format!(
+            "#EXT-X-RENDITION-REPORT:URI=\"{}\",LAST-MSN={},LAST-PART={}\n",
+            report.uri, report.last_msn, last_part
+        ),
+        None => format!(
+            "#EXT-X-RENDITION-REPORT:URI=\"{}\",LAST-MSN={}\n",
+            report.uri, report.last_msn
+        ),
+    }
+}
+
 #[cfg(test)]
 mod tests {
     #![allow(unused_imports, dead_code)]
@@ -182,4 +280,67 @@ mod tests {
         let m3u8 = String::from_utf8(buffer).expect("Invalid UTF-8 sequence");
         println!("{}", m3u8);
     }
+
+    #[test]
+    fn skips_segments_older_than_can_skip_until() {
+        let playlist = create_test_playlist();
+        let (delta, skipped) = skip_segments(&playlist, 4.0);
+        assert_eq!(skipped, 2);
+        assert_eq!(delta.media_sequence, playlist.media_sequence);
+        assert_eq!(delta.segments.len(), 1);
+        assert_eq!(delta.segments[0].uri, "output_1_0.mp4");
+
+        // 마지막 segment 는 남긴다.
+        let (delta, skipped) = skip_segments(&playlist, 0.0);
+        assert_eq!(skipped, 2);
+        assert_eq!(delta.segments.len(), 1);
+
+        let (delta, skipped) = skip_segments(&playlist, 10.0);
+        assert_eq!(skipped, 0);
+        assert_eq!(delta.segments.len(), 3);
+    }
+
+    #[test]
+    fn writes_delta_playlist() {
+        let mut playlist = create_test_playlist_ext_x_part();
+        // codec 이 바뀌어서 첫 segment 가 새 init segment 로 시작한다.
+        playlist.segments[2].map = Some(m3u8_rs::Map {
+            uri: "init_1.mp4".to_string(),
+            ..Default::default()
+        });
+        let (delta, skipped) = skip_segments(&playlist, 4.0);
+        assert_eq!(skipped, 2);
+
+        let report = RenditionReport {
+            uri: "../video1/video.m3u8".to_string(),
+            last_msn: 100,
+            last_part: Some(0),
+        };
+        let m3u8 = String::from_utf8(write_playlist(&delta, skipped, &[report]).unwrap()).unwrap();
+        let lines: Vec<&str> = m3u8.lines().collect();
+        let position = |pattern: &str| lines.iter().position(|line| line.contains(pattern));
+        let skip = position("#EXT-X-SKIP:SKIPPED-SEGMENTS=2").unwrap();
+        // playlist 의 EXT-X-MAP 뒤, 첫 segment 의 EXT-X-MAP 앞
+        assert!(position("\"init.mp4\"").unwrap() < skip);
+        assert!(skip < position("init_1.mp4").unwrap());
+        assert!(skip < position("output_99_0.m4s").unwrap());
+        assert!(position("output_97").is_none() && position("output_98").is_none());
+        assert_eq!(
+            lines.last().copied(),
+            Some("#EXT-X-RENDITION-REPORT:URI=\"../video1/video.m3u8\",LAST-MSN=100,LAST-PART=0")
+        );
+    }
+
+    #[test]
+    fn writes_rendition_report_tag() {
+        let report = rendition_report_tag(&RenditionReport {
+            uri: "../video1/video.m3u8".to_string(),
+            last_msn: 12,
+            last_part: Some(1),
+        });
+        assert_eq!(
+            report,
+            "#EXT-X-RENDITION-REPORT:URI=\"../video1/video.m3u8\",LAST-MSN=12,LAST-PART=1\n"
+        );
+    }
 }
@@ -0,0 +1,127 @@
+// Matroska element id (RFC 9559). id 는 marker bit 를 포함한 값 그대로 쓴다.
+pub const EBML: u32 = 0x1A45DFA3;
+pub const EBML_VERSION: u32 = 0x4286;
+pub const EBML_READ_VERSION: u32 = 0x42F7;
+pub const EBML_MAX_ID_LENGTH: u32 = 0x42F2;
+pub const EBML_MAX_SIZE_LENGTH: u32 = 0x42F3;
+pub const DOC_TYPE: u32 = 0x4282;
+pub const DOC_TYPE_VERSION: u32 = 0x4287;
+pub const DOC_TYPE_READ_VERSION: u32 = 0x4285;
+pub const VOID: u32 = 0xEC;
+
+pub const SEGMENT: u32 = 0x18538067;
+pub const SEEK_HEAD: u32 = 0x114D9B74;
+pub const SEEK: u32 = 0x4DBB;
+pub const SEEK_ID: u32 = 0x53AB;
+pub const SEEK_POSITION: u32 = 0x53AC;
+
+pub const INFO: u32 = 0x1549A966;
+pub const TIMESTAMP_SCALE: u32 = 0x2AD7B1;
+pub const DURATION: u32 = 0x4489;
+pub const MUXING_APP: u32 = 0x4D80;
+pub const WRITING_APP: u32 = 0x5741;
+
+pub const TRACKS: u32 = 0x1654AE6B;
+pub const TRACK_ENTRY: u32 = 0xAE;
+pub const TRACK_NUMBER: u32 = 0xD7;
+pub const TRACK_UID: u32 = 0x73C5;
+pub const TRACK_TYPE: u32 = 0x83;
+pub const FLAG_LACING: u32 = 0x9C;
+pub const CODEC_ID: u32 = 0x86;
+pub const CODEC_PRIVATE: u32 = 0x63A2;
+pub const CODEC_DELAY: u32 = 0x56AA;
+pub const SEEK_PRE_ROLL: u32 = 0x56BB;
+pub const VIDEO: u32 = 0xE0;
+pub const PIXEL_WIDTH: u32 = 0xB0;
+pub const PIXEL_HEIGHT: u32 = 0xBA;
+pub const AUDIO: u32 = 0xE1;
+pub const SAMPLING_FREQUENCY: u32 = 0xB5;
+pub const CHANNELS: u32 = 0x9F;
+
+pub const CLUSTER: u32 = 0x1F43B675;
+pub const TIMESTAMP: u32 = 0xE7;
+pub const SIMPLE_BLOCK: u32 = 0xA3;
+
+pub const CUES: u32 = 0x1C53BB6B;
+pub const CUE_POINT: u32 = 0xBB;
+pub const CUE_TIME: u32 = 0xB3;
+pub const CUE_TRACK_POSITIONS: u32 = 0xB7;
+pub const CUE_TRACK: u32 = 0xF7;
+pub const CUE_CLUSTER_POSITION: u32 = 0xF1;
+
+// 크기를 모르는 element. 녹화 중에 끊겨도 파일을 읽을 수 있다.
+pub const UNKNOWN_SIZE: [u8; 8] = [0x01, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF];
+
+pub fn write_id(buf: &mut Vec<u8>, id: u32) {
+    let bytes = id.to_be_bytes();
+    let skip = bytes.iter().take_while(|b| **b == 0).count().min(3);
+    buf.extend_from_slice(&bytes[skip..]);
+}
+
+// 가장 짧은 길이의 vint. 모든 bit 가 1 인 값은 unknown size 이므로 피한다.
+pub fn write_size(buf: &mut Vec<u8>, size: u64) {
+    let mut len = 1;
+    while len < 8 && size >= (1u64 << (7 * len)) - 1 {
+        len += 1;
+    }
+    let value = size | (1u64 << (7 * len));
+    buf.extend_from_slice(&value.to_be_bytes()[8 - len..]);
+}
+
+// 나중에 덮어쓸 수 있도록 항상 8 byte 로 쓴다.
+pub fn size_8(size: u64) -> [u8; 8] {
+    (size | (1u64 << 56)).to_be_bytes()
+}
+
+pub fn write_element(buf: &mut Vec<u8>, id: u32, data: &[u8]) {
+    write_id(buf, id);
+    write_size(buf, data.len() as u64);
+    buf.extend_from_slice(data);
+}
+
+pub fn write_uint(buf: &mut Vec<u8>, id: u32, value: u64) {
+    let bytes = value.to_be_bytes();
+    let skip = bytes.iter().take_while(|b| **b == 0).count().min(7);
+    write_element(buf, id, &bytes[skip..]);
+}
+
+pub fn write_float(buf: &mut Vec<u8>, id: u32, value: f64) {
+    write_element(buf, id, &value.to_be_bytes());
+}
+
+pub fn write_string(buf: &mut Vec<u8>, id: u32, value: &str) {
+    write_element(buf, id, value.as_bytes());
+}
+
+// 전체 크기가 len byte 인 Void element. len 은 2 이상
+pub fn write_void(buf: &mut Vec<u8>, len: usize) {
+    write_id(buf, VOID);
+    if len - 2 < 0x7F {
+        write_size(buf, (len - 2) as u64);
+        buf.resize(buf.len() + len - 2, 0);
+    } else {
+        buf.extend_from_slice(&size_8((len - 9) as u64));
+        buf.resize(buf.len() + len - 9, 0);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_write_size() {
+        let mut buf = vec![];
+        write_size(&mut buf, 0);
+        write_size(&mut buf, 126);
+        write_size(&mut buf, 127);
+        write_size(&mut buf, 0x3FFE);
+        assert_eq!(buf, vec![0x80, 0xFE, 0x40, 0x7F, 0x7F, 0xFE]);
+
+        let mut buf = vec![];
+        write_void(&mut buf, 12);
+        assert_eq!(buf.len(), 12);
+        write_void(&mut buf, 200);
+        assert_eq!(buf.len(), 212);
+    }
+}
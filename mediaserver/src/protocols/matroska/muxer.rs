@@ -0,0 +1,478 @@
+use super::ebml;
+use bytes::Bytes;
+use std::io::{Seek, SeekFrom, Write};
+
+// 1 tick = 1ms
+const TIMESTAMP_SCALE: u64 = 1_000_000;
+// SimpleBlock 의 timestamp 는 cluster 기준 i16 이다.
+const MAX_CLUSTER_OFFSET: i64 = i16::MAX as i64;
+// audio only 일 때 cluster 를 나누는 간격(ms)
+const AUDIO_CLUSTER_DURATION: u64 = 5000;
+// SeekHead 를 나중에 쓰기 위해 비워두는 크기
+const SEEK_HEAD_RESERVED: usize = 96;
+const APP_NAME: &str = "mediaserver";
+
+#[derive(Debug, Clone)]
+pub enum TrackKind {
+    Video { width: u32, height: u32 },
+    Audio { sample_rate: u32, channels: u16 },
+}
+
+#[derive(Debug, Clone)]
+pub struct MkvTrack {
+    // 1 부터
+    pub number: u64,
+    pub kind: TrackKind,
+    // ex) V_MPEG4/ISO/AVC, A_OPUS
+    pub codec_id: String,
+    pub codec_private: Option<Bytes>,
+    pub codec_delay: Option<u64>,
+    pub seek_pre_roll: Option<u64>,
+}
+
+struct Cluster {
+    // size 를 덮어쓸 파일 위치
+    size_offset: u64,
+    timestamp: u64,
+}
+
+struct CuePoint {
+    time: u64,
+    track: u64,
+    cluster_position: u64,
+}
+
+// Matroska/WebM 을 한 번에 하나의 cluster 씩 이어서 쓴다.
+// Segment 와 쓰고 있는 Cluster 는 크기를 모르는 상태로 두기 때문에 중간에 끊겨도 재생할 수 있고,
+// finalize 에서 크기, Duration, SeekHead, Cues 를 채운다.
+pub struct MkvMuxer<W: Write + Seek> {
+    writer: W,
+    // Segment 의 size 위치와 data 시작 위치
+    segment_size_offset: u64,
+    segment_start: u64,
+    seek_head_offset: u64,
+    duration_offset: u64,
+    info_position: u64,
+    tracks_position: u64,
+
+    // cluster 를 나누는 기준 track. video 가 있으면 video
+    cue_track: u64,
+    cue_track_is_video: bool,
+    cluster: Option<Cluster>,
+    cues: Vec<CuePoint>,
+    // 파일의 첫 frame timestamp. block 과 Duration 은 이것을 0 으로 쓴다.
+    first_timestamp: Option<u64>,
+    end_timestamp: u64,
+}
+
+impl<W: Write + Seek> MkvMuxer<W> {
+    pub fn new(mut writer: W, doc_type: &str, tracks: &[MkvTrack]) -> anyhow::Result<Self> {
+        if tracks.is_empty() {
+            return Err(anyhow::anyhow!("no track"));
+        }
+        let start = writer.stream_position()?;
+
+        let mut buf = vec![];
+        let mut header = vec![];
+        ebml::write_uint(&mut header, ebml::EBML_VERSION, 1);
+        ebml::write_uint(&mut header, ebml::EBML_READ_VERSION, 1);
+        ebml::write_uint(&mut header, ebml::EBML_MAX_ID_LENGTH, 4);
+        ebml::write_uint(&mut header, ebml::EBML_MAX_SIZE_LENGTH, 8);
+        ebml::write_string(&mut header, ebml::DOC_TYPE, doc_type);
+        ebml::write_uint(&mut header, ebml::DOC_TYPE_VERSION, 4);
+        ebml::write_uint(&mut header, ebml::DOC_TYPE_READ_VERSION, 2);
+        ebml::write_element(&mut buf, ebml::EBML, &header);
+
+        ebml::write_id(&mut buf, ebml::SEGMENT);
+        let segment_size_offset = start + buf.len() as u64;
+        buf.extend_from_slice(&ebml::UNKNOWN_SIZE);
+        let segment_start = start + buf.len() as u64;
+
+        let seek_head_offset = start + buf.len() as u64;
+        ebml::write_void(&mut buf, SEEK_HEAD_RESERVED);
+
+        let info_position = start + buf.len() as u64 - segment_start;
+        let mut info = vec![];
+        ebml::write_uint(&mut info, ebml::TIMESTAMP_SCALE, TIMESTAMP_SCALE);
+        ebml::write_string(&mut info, ebml::MUXING_APP, APP_NAME);
+        ebml::write_string(&mut info, ebml::WRITING_APP, APP_NAME);
+        // Duration 은 8 byte float 로 고정해두고 finalize 에서 덮어쓴다.
+        let duration_in_info = info.len() as u64 + 3;
+        ebml::write_float(&mut info, ebml::DURATION, 0.0);
+        ebml::write_id(&mut buf, ebml::INFO);
+        ebml::write_size(&mut buf, info.len() as u64);
+        let duration_offset = start + buf.len() as u64 + duration_in_info;
+        buf.extend_from_slice(&info);
+
+        let tracks_position = start + buf.len() as u64 - segment_start;
+        let mut entries = vec![];
+        for track in tracks {
+            write_track_entry(&mut entries, track);
+        }
+        ebml::write_element(&mut buf, ebml::TRACKS, &entries);
+
+        writer.write_all(&buf)?;
+
+        let cue_track = tracks
+            .iter()
+            .find(|track| matches!(track.kind, TrackKind::Video { .. }))
+            .unwrap_or(&tracks[0]);
+        Ok(MkvMuxer {
+            writer,
+            segment_size_offset,
+            segment_start,
+            seek_head_offset,
+            duration_offset,
+            info_position,
+            tracks_position,
+            cue_track: cue_track.number,
+            cue_track_is_video: matches!(cue_track.kind, TrackKind::Video { .. }),
+            cluster: None,
+            cues: vec![],
+            first_timestamp: None,
+            end_timestamp: 0,
+        })
+    }
+
+    // timestamp 는 ms 단위 presentation time. 첫 frame 보다 앞선 frame 은 0 으로 쓴다.
+    pub fn write_frame(
+        &mut self,
+        track: u64,
+        timestamp: u64,
+        keyframe: bool,
+        data: &[u8],
+    ) -> anyhow::Result<()> {
+        let timestamp = timestamp.saturating_sub(*self.first_timestamp.get_or_insert(timestamp));
+        let is_cue = track == self.cue_track && keyframe;
+        let new_cluster = match &self.cluster {
+            None => true,
+            Some(cluster) => {
+                let offset = timestamp as i64 - cluster.timestamp as i64;
+                // video 는 GOP 마다 cluster 를 나눈다.
+                (is_cue
+                    && (self.cue_track_is_video
+                        || timestamp >= cluster.timestamp + AUDIO_CLUSTER_DURATION))
+                    || !(-MAX_CLUSTER_OFFSET..=MAX_CLUSTER_OFFSET).contains(&offset)
+            }
+        };
+        if new_cluster {
+            self.start_cluster(timestamp, is_cue.then_some(track))?;
+        }
+        let cluster_timestamp = self.cluster.as_ref().map_or(0, |c| c.timestamp);
+
+        let mut block = vec![];
+        ebml::write_size(&mut block, track);
+        block.extend_from_slice(
+            &((timestamp as i64 - cluster_timestamp as i64) as i16).to_be_bytes(),
+        );
+        block.push(if keyframe { 0x80 } else { 0x00 });
+
+        let mut buf = vec![];
+        ebml::write_id(&mut buf, ebml::SIMPLE_BLOCK);
+        ebml::write_size(&mut buf, (block.len() + data.len()) as u64);
+        buf.extend_from_slice(&block);
+        self.writer.write_all(&buf)?;
+        self.writer.write_all(data)?;
+
+        self.end_timestamp = self.end_timestamp.max(timestamp);
+        Ok(())
+    }
+
+    fn start_cluster(&mut self, timestamp: u64, cue_track: Option<u64>) -> anyhow::Result<()> {
+        self.close_cluster()?;
+
+        let offset = self.writer.stream_position()?;
+        let position = offset - self.segment_start;
+        let mut buf = vec![];
+        ebml::write_id(&mut buf, ebml::CLUSTER);
+        let size_offset = offset + buf.len() as u64;
+        buf.extend_from_slice(&ebml::UNKNOWN_SIZE);
+        ebml::write_uint(&mut buf, ebml::TIMESTAMP, timestamp);
+        self.writer.write_all(&buf)?;
+
+        if let Some(track) = cue_track {
+            self.cues.push(CuePoint {
+                time: timestamp,
+                track,
+                cluster_position: position,
+            });
+        }
+        self.cluster = Some(Cluster {
+            size_offset,
+            timestamp,
+        });
+        Ok(())
+    }
+
+    // 다 쓴 cluster 의 크기를 채우고 디스크로 내보낸다.
+    fn close_cluster(&mut self) -> anyhow::Result<()> {
+        let Some(cluster) = self.cluster.take() else {
+            return Ok(());
+        };
+        let end = self.writer.stream_position()?;
+        self.write_at(
+            cluster.size_offset,
+            &ebml::size_8(end - cluster.size_offset - 8),
+        )?;
+        self.writer.seek(SeekFrom::Start(end))?;
+        self.writer.flush()?;
+        Ok(())
+    }
+
+    fn write_at(&mut self, offset: u64, data: &[u8]) -> anyhow::Result<()> {
+        self.writer.seek(SeekFrom::Start(offset))?;
+        self.writer.write_all(data)?;
+        Ok(())
+    }
+
+    pub fn finalize(mut self) -> anyhow::Result<W> {
+        self.close_cluster()?;
+
+        let cues_position = self.writer.stream_position()? - self.segment_start;
+        let mut points = vec![];
+        for cue in &self.cues {
+            let mut positions = vec![];
+            ebml::write_uint(&mut positions, ebml::CUE_TRACK, cue.track);
+            ebml::write_uint(
+                &mut positions,
+                ebml::CUE_CLUSTER_POSITION,
+                cue.cluster_position,
+            );
+            let mut point = vec![];
+            ebml::write_uint(&mut point, ebml::CUE_TIME, cue.time);
+            ebml::write_element(&mut point, ebml::CUE_TRACK_POSITIONS, &positions);
+            ebml::write_element(&mut points, ebml::CUE_POINT, &point);
+        }
+        let mut buf = vec![];
+        let has_cues = !points.is_empty();
+        if has_cues {
+            ebml::write_element(&mut buf, ebml::CUES, &points);
+        }
+        self.writer.write_all(&buf)?;
+        let end = self.writer.stream_position()?;
+
+        let mut seeks = vec![];
+        let mut entries = vec![
+            (ebml::INFO, self.info_position),
+            (ebml::TRACKS, self.tracks_position),
+        ];
+        if has_cues {
+            entries.push((ebml::CUES, cues_position));
+        }
+        for (id, position) in entries {
+            let mut seek = vec![];
+            ebml::write_element(&mut seek, ebml::SEEK_ID, &id.to_be_bytes());
+            ebml::write_element(&mut seek, ebml::SEEK_POSITION, &position.to_be_bytes());
+            ebml::write_element(&mut seeks, ebml::SEEK, &seek);
+        }
+        let mut seek_head = vec![];
+        ebml::write_element(&mut seek_head, ebml::SEEK_HEAD, &seeks);
+        let void_len = SEEK_HEAD_RESERVED - seek_head.len();
+        ebml::write_void(&mut seek_head, void_len);
+
+        self.write_at(self.seek_head_offset, &seek_head)?;
+        self.write_at(
+            self.duration_offset,
+            &(self.end_timestamp as f64).to_be_bytes(),
+        )?;
+        self.write_at(
+            self.segment_size_offset,
+            &ebml::size_8(end - self.segment_start),
+        )?;
+        self.writer.seek(SeekFrom::Start(end))?;
+        self.writer.flush()?;
+        Ok(self.writer)
+    }
+}
+
+fn write_track_entry(buf: &mut Vec<u8>, track: &MkvTrack) {
+    let mut entry = vec![];
+    ebml::write_uint(&mut entry, ebml::TRACK_NUMBER, track.number);
+    ebml::write_uint(&mut entry, ebml::TRACK_UID, track.number);
+    ebml::write_uint(&mut entry, ebml::FLAG_LACING, 0);
+    ebml::write_string(&mut entry, ebml::CODEC_ID, &track.codec_id);
+    if let Some(codec_private) = &track.codec_private {
+        ebml::write_element(&mut entry, ebml::CODEC_PRIVATE, codec_private);
+    }
+    if let Some(codec_delay) = track.codec_delay {
+        ebml::write_uint(&mut entry, ebml::CODEC_DELAY, codec_delay);
+    }
+    if let Some(seek_pre_roll) = track.seek_pre_roll {
+        ebml::write_uint(&mut entry, ebml::SEEK_PRE_ROLL, seek_pre_roll);
+    }
+    match track.kind {
+        TrackKind::Video { width, height } => {
+            ebml::write_uint(&mut entry, ebml::TRACK_TYPE, 1);
+            let mut video = vec![];
+            ebml::write_uint(&mut video, ebml::PIXEL_WIDTH, width as u64);
+            ebml::write_uint(&mut video, ebml::PIXEL_HEIGHT, height as u64);
+            ebml::write_element(&mut entry, ebml::VIDEO, &video);
+        }
+        TrackKind::Audio {
+            sample_rate,
+            channels,
+        } => {
+            ebml::write_uint(&mut entry, ebml::TRACK_TYPE, 2);
+            let mut audio = vec![];
+            ebml::write_float(&mut audio, ebml::SAMPLING_FREQUENCY, sample_rate as f64);
+            ebml::write_uint(&mut audio, ebml::CHANNELS, channels as u64);
+            ebml::write_element(&mut entry, ebml::AUDIO, &audio);
+        }
+    }
+    ebml::write_element(buf, ebml::TRACK_ENTRY, &entry);
+}
+
+// Opus 의 CodecPrivate (RFC 7845 5.1)
+pub fn opus_head(channels: u16, sample_rate: u32) -> Bytes {
+    let mut head = b"OpusHead".to_vec();
+    head.push(1);
+    head.push(channels as u8);
+    head.extend_from_slice(&0u16.to_le_bytes()); // pre-skip
+    head.extend_from_slice(&sample_rate.to_le_bytes());
+    head.extend_from_slice(&0i16.to_le_bytes()); // output gain
+    head.push(0); // mapping family
+    Bytes::from(head)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    fn find(data: &[u8], pattern: &[u8]) -> Option<usize> {
+        data.windows(pattern.len()).position(|w| w == pattern)
+    }
+
+    // (id, data 시작 위치, data 크기)
+    fn read_element(data: &[u8], pos: usize) -> (u32, usize, usize) {
+        let id_len = data[pos].leading_zeros() as usize + 1;
+        let id = data[pos..pos + id_len]
+            .iter()
+            .fold(0u32, |acc, b| (acc << 8) | *b as u32);
+        let size_pos = pos + id_len;
+        let size_len = data[size_pos].leading_zeros() as usize + 1;
+        let size = data[size_pos..size_pos + size_len]
+            .iter()
+            .fold(0u64, |acc, b| (acc << 8) | *b as u64)
+            & ((1u64 << (7 * size_len)) - 1);
+        (id, size_pos + size_len, size as usize)
+    }
+
+    #[test]
+    fn test_mkv_muxer() {
+        let tracks = vec![
+            MkvTrack {
+                number: 1,
+                kind: TrackKind::Audio {
+                    sample_rate: 48000,
+                    channels: 2,
+                },
+                codec_id: "A_OPUS".to_string(),
+                codec_private: Some(opus_head(2, 48000)),
+                codec_delay: None,
+                seek_pre_roll: Some(80_000_000),
+            },
+            MkvTrack {
+                number: 2,
+                kind: TrackKind::Video {
+                    width: 640,
+                    height: 480,
+                },
+                codec_id: "V_MPEG4/ISO/AVC".to_string(),
+                codec_private: Some(Bytes::from_static(&[1, 0x42, 0, 0x1f, 0xff])),
+                codec_delay: None,
+                seek_pre_roll: None,
+            },
+        ];
+        let mut muxer = MkvMuxer::new(Cursor::new(vec![]), "matroska", &tracks).unwrap();
+        // 첫 keyframe 전의 audio 도 cluster 를 연다.
+        muxer.write_frame(1, 0, true, &[1, 2, 3]).unwrap();
+        muxer.write_frame(2, 10, true, &[0, 0, 0, 1, 0x65]).unwrap();
+        muxer.write_frame(1, 20, true, &[4, 5, 6]).unwrap();
+        muxer
+            .write_frame(2, 43, false, &[0, 0, 0, 1, 0x41])
+            .unwrap();
+        muxer
+            .write_frame(2, 2010, true, &[0, 0, 0, 1, 0x65])
+            .unwrap();
+        let data = muxer.finalize().unwrap().into_inner();
+
+        assert_eq!(&data[..4], &ebml::EBML.to_be_bytes());
+        let segment = find(&data, &ebml::SEGMENT.to_be_bytes()).unwrap();
+        // segment 크기가 파일 끝까지로 채워졌다.
+        let size = u64::from_be_bytes(data[segment + 4..segment + 12].try_into().unwrap());
+        assert_eq!(size & !(1 << 56), (data.len() - segment - 12) as u64);
+        assert!(find(&data, &ebml::SEEK_HEAD.to_be_bytes()).unwrap() == segment + 12);
+
+        // video keyframe 마다 cluster 가 나뉜다. (audio 로 시작한 cluster 포함 3개)
+        let clusters: Vec<usize> = data
+            .windows(4)
+            .enumerate()
+            .filter(|(_, w)| *w == ebml::CLUSTER.to_be_bytes())
+            .map(|(i, _)| i)
+            .collect();
+        assert_eq!(clusters.len(), 3);
+        for cluster in &clusters {
+            assert_ne!(&data[cluster + 4..cluster + 12], &ebml::UNKNOWN_SIZE);
+        }
+
+        // SeekHead 에도 Cues id 가 있으므로 마지막 것을 찾는다.
+        let cues = data
+            .windows(4)
+            .rposition(|w| w == ebml::CUES.to_be_bytes())
+            .unwrap();
+        assert!(cues > *clusters.last().unwrap());
+        // video keyframe cluster 두 개만 cue 가 된다.
+        let (id, mut pos, size) = read_element(&data, cues);
+        assert_eq!(id, ebml::CUES);
+        assert_eq!(pos + size, data.len());
+        let mut cue_points = 0;
+        while pos < data.len() {
+            let (id, start, size) = read_element(&data, pos);
+            assert_eq!(id, ebml::CUE_POINT);
+            cue_points += 1;
+            pos = start + size;
+        }
+        assert_eq!(cue_points, 2);
+    }
+
+    #[test]
+    fn test_mkv_muxer_rebase() {
+        let tracks = vec![MkvTrack {
+            number: 1,
+            kind: TrackKind::Video {
+                width: 640,
+                height: 480,
+            },
+            codec_id: "V_MPEG4/ISO/AVC".to_string(),
+            codec_private: Some(Bytes::from_static(&[1, 0x42, 0, 0x1f, 0xff])),
+            codec_delay: None,
+            seek_pre_roll: None,
+        }];
+        let mut muxer = MkvMuxer::new(Cursor::new(vec![]), "matroska", &tracks).unwrap();
+        // 방송 중간에 시작한 녹화 파일
+        muxer
+            .write_frame(1, 100_000, true, &[0, 0, 0, 1, 0x65])
+            .unwrap();
+        muxer
+            .write_frame(1, 100_033, false, &[0, 0, 0, 1, 0x41])
+            .unwrap();
+        muxer
+            .write_frame(1, 102_000, true, &[0, 0, 0, 1, 0x65])
+            .unwrap();
+        let data = muxer.finalize().unwrap().into_inner();
+
+        let duration = find(&data, &(ebml::DURATION as u16).to_be_bytes()).unwrap();
+        let (_, pos, size) = read_element(&data, duration);
+        assert_eq!(size, 8);
+        let duration = f64::from_be_bytes(data[pos..pos + 8].try_into().unwrap());
+        assert_eq!(duration, 2000.0);
+
+        // 첫 cluster 는 0 에서 시작한다.
+        let cluster = find(&data, &ebml::CLUSTER.to_be_bytes()).unwrap();
+        let (_, pos, _) = read_element(&data, cluster);
+        let (id, pos, size) = read_element(&data, pos);
+        assert_eq!(id, ebml::TIMESTAMP);
+        assert!(data[pos..pos + size].iter().all(|b| *b == 0));
+    }
+}
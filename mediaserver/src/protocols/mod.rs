@@ -1 +1,6 @@
+pub mod dash;
+pub mod flv;
 pub mod hls;
+pub mod matroska;
+pub mod mpegts;
+pub mod rtsp;
@@ -0,0 +1,16 @@
+pub const TS_PACKET_SIZE: usize = 188;
+pub const SYNC_BYTE: u8 = 0x47;
+
+pub const PAT_PID: u16 = 0x0000;
+
+pub const STREAM_TYPE_AAC: u8 = 0x0F;
+pub const STREAM_TYPE_H264: u8 = 0x1B;
+pub const STREAM_TYPE_PRIVATE_DATA: u8 = 0x06;
+
+pub const REGISTRATION_DESCRIPTOR: u8 = 0x05;
+
+pub const STREAM_ID_PRIVATE_STREAM_1: u8 = 0xBD;
+pub const STREAM_ID_AUDIO: u8 = 0xC0;
+pub const STREAM_ID_VIDEO: u8 = 0xE0;
+
+pub const EXTENSION_DESCRIPTOR: u8 = 0x7F;
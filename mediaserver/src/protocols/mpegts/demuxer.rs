@@ -0,0 +1,281 @@
+use crate::protocols::mpegts::consts::*;
+use bytes::{Bytes, BytesMut};
+use std::collections::HashMap;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum StreamKind {
+    H264,
+    Aac,
+    Opus,
+}
+
+#[derive(Debug, Clone)]
+pub struct Frame {
+    pub pid: u16,
+    pub kind: StreamKind,
+    pub pts: u64,
+    pub dts: u64,
+    pub payload: Bytes,
+}
+
+struct PesBuffer {
+    kind: StreamKind,
+    started: bool,
+    data: BytesMut,
+}
+
+pub struct Demuxer {
+    pending: BytesMut,
+    pmt_pid: Option<u16>,
+    streams: HashMap<u16, PesBuffer>,
+}
+
+impl Demuxer {
+    pub fn new() -> Self {
+        Demuxer {
+            pending: BytesMut::new(),
+            pmt_pid: None,
+            streams: HashMap::new(),
+        }
+    }
+
+    // 188 byte 단위로 잘리지 않은 입력도 받을 수 있도록 남은 데이터를 보관한다.
+    pub fn demux(&mut self, data: &[u8]) -> Vec<Frame> {
+        self.pending.extend_from_slice(data);
+
+        let mut frames = Vec::new();
+        loop {
+            let Some(sync) = self.pending.iter().position(|b| *b == SYNC_BYTE) else {
+                self.pending.clear();
+                break;
+            };
+            if sync > 0 {
+                let _ = self.pending.split_to(sync);
+            }
+            if self.pending.len() < TS_PACKET_SIZE {
+                break;
+            }
+            let packet = self.pending.split_to(TS_PACKET_SIZE).freeze();
+            if let Err(err) = self.demux_packet(&packet, &mut frames) {
+                log::warn!("mpegts demux error: {}", err);
+            }
+        }
+        frames
+    }
+
+    // 입력이 끝났을 때 남아있는 PES 를 내보낸다.
+    pub fn flush(&mut self) -> Vec<Frame> {
+        let mut frames = Vec::new();
+        let pids: Vec<u16> = self.streams.keys().cloned().collect();
+        for pid in pids {
+            if let Some(frame) = self.take_pes(pid) {
+                frames.push(frame);
+            }
+        }
+        frames
+    }
+
+    fn demux_packet(&mut self, packet: &Bytes, frames: &mut Vec<Frame>) -> anyhow::Result<()> {
+        let payload_unit_start = packet[1] & 0x40 != 0;
+        let pid = (((packet[1] & 0x1F) as u16) << 8) | packet[2] as u16;
+        let adaptation_field_control = (packet[3] >> 4) & 0x03;
+
+        let mut offset = 4;
+        if adaptation_field_control & 0x02 != 0 {
+            offset += 1 + packet[4] as usize;
+        }
+        if adaptation_field_control & 0x01 == 0 || offset >= TS_PACKET_SIZE {
+            return Ok(());
+        }
+        let payload = packet.slice(offset..);
+
+        if pid == PAT_PID {
+            return self.parse_pat(&payload, payload_unit_start);
+        }
+        if Some(pid) == self.pmt_pid {
+            return self.parse_pmt(&payload, payload_unit_start);
+        }
+        if !self.streams.contains_key(&pid) {
+            return Ok(());
+        }
+
+        if payload_unit_start {
+            if let Some(frame) = self.take_pes(pid) {
+                frames.push(frame);
+            }
+        }
+        if let Some(pes) = self.streams.get_mut(&pid) {
+            if payload_unit_start {
+                pes.started = true;
+            }
+            if pes.started {
+                pes.data.extend_from_slice(&payload);
+            }
+        }
+        Ok(())
+    }
+
+    fn parse_pat(&mut self, payload: &Bytes, payload_unit_start: bool) -> anyhow::Result<()> {
+        let section = psi_section(payload, payload_unit_start)?;
+        // program_number(16) reserved(3) pid(13) 의 반복, 마지막 4 byte 는 CRC
+        let mut offset = 8;
+        while offset + 4 <= section.len() - 4 {
+            let program_number = ((section[offset] as u16) << 8) | section[offset + 1] as u16;
+            let pid = (((section[offset + 2] & 0x1F) as u16) << 8) | section[offset + 3] as u16;
+            if program_number != 0 {
+                self.pmt_pid = Some(pid);
+                break;
+            }
+            offset += 4;
+        }
+        Ok(())
+    }
+
+    fn parse_pmt(&mut self, payload: &Bytes, payload_unit_start: bool) -> anyhow::Result<()> {
+        let section = psi_section(payload, payload_unit_start)?;
+        if section.len() < 12 + 4 {
+            anyhow::bail!("short pmt section");
+        }
+        let program_info_length = (((section[10] & 0x0F) as usize) << 8) | section[11] as usize;
+        let mut offset = 12 + program_info_length;
+        let end = section.len() - 4;
+        while offset + 5 <= end {
+            let stream_type = section[offset];
+            let pid = (((section[offset + 1] & 0x1F) as u16) << 8) | section[offset + 2] as u16;
+            let es_info_length =
+                (((section[offset + 3] & 0x0F) as usize) << 8) | section[offset + 4] as usize;
+            let descriptors_start = offset + 5;
+            let descriptors_end = (descriptors_start + es_info_length).min(end);
+            let descriptors = &section[descriptors_start..descriptors_end];
+            offset = descriptors_end;
+
+            let kind = match stream_type {
+                STREAM_TYPE_H264 => Some(StreamKind::H264),
+                STREAM_TYPE_AAC => Some(StreamKind::Aac),
+                STREAM_TYPE_PRIVATE_DATA if has_opus_registration(descriptors) => {
+                    Some(StreamKind::Opus)
+                }
+                _ => None,
+            };
+            let Some(kind) = kind else {
+                log::debug!("unsupported stream type pid:{}, type:{}", pid, stream_type);
+                continue;
+            };
+            self.streams.entry(pid).or_insert_with(|| PesBuffer {
+                kind,
+                started: false,
+                data: BytesMut::new(),
+            });
+        }
+        Ok(())
+    }
+
+    fn take_pes(&mut self, pid: u16) -> Option<Frame> {
+        let pes = self.streams.get_mut(&pid)?;
+        if pes.data.is_empty() {
+            return None;
+        }
+        let data = pes.data.split().freeze();
+        let kind = pes.kind;
+        match parse_pes(&data) {
+            Ok((pts, dts, payload)) => Some(Frame {
+                pid,
+                kind,
+                pts,
+                dts,
+                payload,
+            }),
+            Err(err) => {
+                log::warn!("invalid pes pid:{}, err:{}", pid, err);
+                None
+            }
+        }
+    }
+}
+
+fn psi_section(payload: &Bytes, payload_unit_start: bool) -> anyhow::Result<Bytes> {
+    let mut offset = 0;
+    if payload_unit_start {
+        offset = 1 + payload[0] as usize;
+    }
+    if payload.len() < offset + 3 {
+        anyhow::bail!("short psi section");
+    }
+    let section_length =
+        (((payload[offset + 1] & 0x0F) as usize) << 8) | payload[offset + 2] as usize;
+    let end = offset + 3 + section_length;
+    if payload.len() < end || section_length < 9 {
+        anyhow::bail!("psi section spans multiple packets");
+    }
+    Ok(payload.slice(offset..end))
+}
+
+fn has_opus_registration(descriptors: &[u8]) -> bool {
+    let mut offset = 0;
+    while offset + 2 <= descriptors.len() {
+        let tag = descriptors[offset];
+        let len = descriptors[offset + 1] as usize;
+        let body = &descriptors[offset + 2..(offset + 2 + len).min(descriptors.len())];
+        if tag == REGISTRATION_DESCRIPTOR && body.starts_with(b"Opus") {
+            return true;
+        }
+        offset += 2 + len;
+    }
+    false
+}
+
+fn parse_pes(data: &Bytes) -> anyhow::Result<(u64, u64, Bytes)> {
+    if data.len() < 9 || data[0] != 0 || data[1] != 0 || data[2] != 1 {
+        anyhow::bail!("invalid pes start code");
+    }
+    let pts_dts_flags = data[7] >> 6;
+    let header_length = data[8] as usize;
+    if data.len() < 9 + header_length {
+        anyhow::bail!("short pes header");
+    }
+
+    let mut pts = 0;
+    let mut dts = 0;
+    if pts_dts_flags & 0x02 != 0 && header_length >= 5 {
+        pts = read_timestamp(&data[9..14]);
+        dts = pts;
+    }
+    if pts_dts_flags == 0x03 && header_length >= 10 {
+        dts = read_timestamp(&data[14..19]);
+    }
+
+    Ok((pts, dts, data.slice(9 + header_length..)))
+}
+
+fn read_timestamp(b: &[u8]) -> u64 {
+    (((b[0] as u64 >> 1) & 0x07) << 30)
+        | ((b[1] as u64) << 22)
+        | (((b[2] as u64) >> 1) << 15)
+        | ((b[3] as u64) << 7)
+        | ((b[4] as u64) >> 1)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_read_timestamp() {
+        // PTS 90000 (1초), marker bit 포함
+        let b = [0x21, 0x00, 0x05, 0xBF, 0x21];
+        assert_eq!(read_timestamp(&b), 90000);
+    }
+
+    #[test]
+    fn test_parse_pes() -> anyhow::Result<()> {
+        let data = Bytes::from_static(&[
+            0x00, 0x00, 0x01, 0xE0, 0x00, 0x00, 0x80, 0x80, 0x05, 0x21, 0x00, 0x05, 0xBF, 0x21,
+            0x00, 0x00, 0x00, 0x01, 0x09,
+        ]);
+        let (pts, dts, payload) = parse_pes(&data)?;
+        assert_eq!(pts, 90000);
+        assert_eq!(dts, 90000);
+        assert_eq!(payload.as_ref(), &[0x00, 0x00, 0x00, 0x01, 0x09]);
+        Ok(())
+    }
+}
@@ -0,0 +1,4 @@
+pub mod consts;
+pub mod demuxer;
+pub mod muxer;
+pub mod opus;
@@ -0,0 +1,432 @@
+use crate::codecs::aac;
+use crate::protocols::mpegts::consts::*;
+use bytes::Bytes;
+
+const PMT_PID: u16 = 0x1000;
+const FIRST_ES_PID: u16 = 0x0100;
+const PROGRAM_NUMBER: u16 = 1;
+const TS_HEADER_SIZE: usize = 4;
+// PCR 을 DTS 보다 이만큼 앞세워서 decoder 가 buffer 를 채울 시간을 준다. (90kHz)
+const PCR_DELAY: u64 = 63000;
+// PTS, DTS 는 33 bit
+const TIMESTAMP_MASK: u64 = (1 << 33) - 1;
+const AUD: [u8; 6] = [0x00, 0x00, 0x00, 0x01, 0x09, 0xF0];
+const START_CODE: [u8; 4] = [0x00, 0x00, 0x00, 0x01];
+
+#[derive(Debug, Clone)]
+pub enum TsTrack {
+    // avcC 가 아닌 NAL unit 그대로
+    H264 { sps: Bytes, pps: Bytes },
+    Aac(aac::config::Config),
+    Opus { channels: u16 },
+}
+
+impl TsTrack {
+    fn stream_type(&self) -> u8 {
+        match self {
+            TsTrack::H264 { .. } => STREAM_TYPE_H264,
+            TsTrack::Aac(_) => STREAM_TYPE_AAC,
+            TsTrack::Opus { .. } => STREAM_TYPE_PRIVATE_DATA,
+        }
+    }
+
+    fn stream_id(&self) -> u8 {
+        match self {
+            TsTrack::H264 { .. } => STREAM_ID_VIDEO,
+            TsTrack::Aac(_) => STREAM_ID_AUDIO,
+            TsTrack::Opus { .. } => STREAM_ID_PRIVATE_STREAM_1,
+        }
+    }
+
+    // PMT 의 ES_info
+    fn descriptors(&self) -> Vec<u8> {
+        match self {
+            // registration descriptor 와 channel_config_code (ETSI TS 102 366 Opus in MPEG-TS)
+            TsTrack::Opus { channels } => vec![
+                REGISTRATION_DESCRIPTOR,
+                4,
+                b'O',
+                b'p',
+                b'u',
+                b's',
+                EXTENSION_DESCRIPTOR,
+                2,
+                0x80,
+                *channels as u8,
+            ],
+            _ => vec![],
+        }
+    }
+}
+
+struct TsStream {
+    pid: u16,
+    track: TsTrack,
+    continuity: u8,
+    // 같은 pts 의 NAL unit 들은 하나의 access unit 이므로 AUD 를 한 번만 넣는다.
+    last_pts: Option<u64>,
+}
+
+// 하나의 program 을 가진 MPEG-TS 를 만든다. 쓴 packet 은 take 로 가져간다.
+pub struct TsMuxer {
+    streams: Vec<TsStream>,
+    pcr_pid: u16,
+    pat_continuity: u8,
+    pmt_continuity: u8,
+    buffer: Vec<u8>,
+}
+
+impl TsMuxer {
+    // track 순서대로 pid 를 정한다. PCR 은 video 가 있으면 video 에 싣는다.
+    pub fn new(tracks: Vec<TsTrack>) -> anyhow::Result<Self> {
+        if tracks.is_empty() {
+            return Err(anyhow::anyhow!("no track"));
+        }
+        let streams: Vec<TsStream> = tracks
+            .into_iter()
+            .enumerate()
+            .map(|(idx, track)| TsStream {
+                pid: FIRST_ES_PID + idx as u16,
+                track,
+                continuity: 0,
+                last_pts: None,
+            })
+            .collect();
+        let pcr_pid = streams
+            .iter()
+            .find(|stream| matches!(stream.track, TsTrack::H264 { .. }))
+            .unwrap_or(&streams[0])
+            .pid;
+        Ok(TsMuxer {
+            streams,
+            pcr_pid,
+            pat_continuity: 0,
+            pmt_continuity: 0,
+            buffer: vec![],
+        })
+    }
+
+    // segment 마다 처음에 PAT, PMT 를 써서 어느 segment 에서든 재생을 시작할 수 있게 한다.
+    pub fn write_tables(&mut self) {
+        let mut pat = vec![];
+        pat.extend_from_slice(&PROGRAM_NUMBER.to_be_bytes());
+        pat.extend_from_slice(&(0xE000 | PMT_PID).to_be_bytes());
+        let section = psi_section(0x00, 1, &pat);
+        let continuity = next_continuity(&mut self.pat_continuity);
+        self.write_psi(PAT_PID, continuity, &section);
+
+        let mut pmt = vec![];
+        pmt.extend_from_slice(&(0xE000 | self.pcr_pid).to_be_bytes());
+        pmt.extend_from_slice(&0xF000u16.to_be_bytes()); // program_info_length
+        for stream in &self.streams {
+            let descriptors = stream.track.descriptors();
+            pmt.push(stream.track.stream_type());
+            pmt.extend_from_slice(&(0xE000 | stream.pid).to_be_bytes());
+            pmt.extend_from_slice(&(0xF000 | descriptors.len() as u16).to_be_bytes());
+            pmt.extend_from_slice(&descriptors);
+        }
+        let section = psi_section(0x02, PROGRAM_NUMBER, &pmt);
+        let continuity = next_continuity(&mut self.pmt_continuity);
+        self.write_psi(PMT_PID, continuity, &section);
+    }
+
+    // idx 는 new 에 넘긴 track 순서. pts, dts 는 90kHz.
+    // H.264 는 4 byte length prefix(avcC) 로 된 NAL unit 들, AAC 와 Opus 는 frame 하나를 받는다.
+    pub fn write_frame(
+        &mut self,
+        idx: usize,
+        pts: u64,
+        dts: u64,
+        keyframe: bool,
+        data: &[u8],
+    ) -> anyhow::Result<()> {
+        let stream = self
+            .streams
+            .get_mut(idx)
+            .ok_or(anyhow::anyhow!("unknown track: {}", idx))?;
+        let access_unit_start = stream.last_pts != Some(pts);
+        stream.last_pts = Some(pts);
+        let payload = match &stream.track {
+            TsTrack::H264 { sps, pps } => annexb(sps, pps, data, access_unit_start),
+            TsTrack::Aac(config) => {
+                let mut payload = aac::adts::make_header(config, data.len()).to_vec();
+                payload.extend_from_slice(data);
+                payload
+            }
+            TsTrack::Opus { .. } => opus_access_unit(data),
+        };
+        let pid = stream.pid;
+        let stream_id = stream.track.stream_id();
+
+        let pes = pes_packet(stream_id, pts + PCR_DELAY, dts + PCR_DELAY, &payload);
+        let pcr = (pid == self.pcr_pid).then_some(dts);
+        self.write_pes(idx, pcr, keyframe, &pes);
+        Ok(())
+    }
+
+    // 지금까지 쓴 TS packet 들
+    pub fn take(&mut self) -> Bytes {
+        Bytes::from(std::mem::take(&mut self.buffer))
+    }
+
+    fn write_psi(&mut self, pid: u16, continuity: u8, section: &[u8]) {
+        let mut packet = Vec::with_capacity(TS_PACKET_SIZE);
+        packet.push(SYNC_BYTE);
+        packet.extend_from_slice(&(0x4000 | pid).to_be_bytes());
+        packet.push(0x10 | continuity);
+        packet.push(0x00); // pointer_field
+        packet.extend_from_slice(section);
+        packet.resize(TS_PACKET_SIZE, 0xFF);
+        self.buffer.extend_from_slice(&packet);
+    }
+
+    fn write_pes(&mut self, idx: usize, pcr: Option<u64>, keyframe: bool, pes: &[u8]) {
+        let pid = self.streams[idx].pid;
+        let mut offset = 0;
+        while offset < pes.len() {
+            let first = offset == 0;
+            // adaptation_field_length 다음부터의 내용
+            let mut adaptation = None;
+            if first && (pcr.is_some() || keyframe) {
+                let mut field = vec![0x00];
+                if keyframe {
+                    field[0] |= 0x40; // random_access_indicator
+                }
+                if let Some(pcr) = pcr {
+                    field[0] |= 0x10;
+                    field.extend_from_slice(&encode_pcr(pcr));
+                }
+                adaptation = Some(field);
+            }
+
+            let adaptation_size = adaptation.as_ref().map_or(0, |field| 1 + field.len());
+            let space = TS_PACKET_SIZE - TS_HEADER_SIZE - adaptation_size;
+            let remaining = pes.len() - offset;
+            if remaining < space {
+                // 마지막 packet 은 adaptation field 의 stuffing 으로 188 byte 를 채운다.
+                let stuffing = space - remaining;
+                match adaptation.as_mut() {
+                    Some(field) => field.resize(field.len() + stuffing, 0xFF),
+                    None if stuffing == 1 => adaptation = Some(vec![]),
+                    None => {
+                        let mut field = vec![0x00];
+                        field.resize(stuffing - 1, 0xFF);
+                        adaptation = Some(field);
+                    }
+                }
+            }
+            let adaptation_size = adaptation.as_ref().map_or(0, |field| 1 + field.len());
+            let size = (TS_PACKET_SIZE - TS_HEADER_SIZE - adaptation_size).min(remaining);
+
+            let continuity = next_continuity(&mut self.streams[idx].continuity);
+            let start = if first { 0x4000 } else { 0 };
+            self.buffer.push(SYNC_BYTE);
+            self.buffer.extend_from_slice(&(start | pid).to_be_bytes());
+            match adaptation {
+                Some(field) => {
+                    self.buffer.push(0x30 | continuity);
+                    self.buffer.push(field.len() as u8);
+                    self.buffer.extend_from_slice(&field);
+                }
+                None => self.buffer.push(0x10 | continuity),
+            }
+            self.buffer.extend_from_slice(&pes[offset..offset + size]);
+            offset += size;
+        }
+    }
+}
+
+fn next_continuity(continuity: &mut u8) -> u8 {
+    let current = *continuity;
+    *continuity = (current + 1) & 0x0F;
+    current
+}
+
+// table_id 부터 CRC 까지. data 는 last_section_number 다음부터의 내용
+fn psi_section(table_id: u8, id: u16, data: &[u8]) -> Vec<u8> {
+    let section_length = 5 + data.len() + 4;
+    let mut section = vec![table_id];
+    section.extend_from_slice(&(0xB000 | section_length as u16).to_be_bytes());
+    section.extend_from_slice(&id.to_be_bytes());
+    section.push(0xC1); // version 0, current_next_indicator
+    section.push(0x00); // section_number
+    section.push(0x00); // last_section_number
+    section.extend_from_slice(data);
+    let crc = crc32_mpeg2(&section);
+    section.extend_from_slice(&crc.to_be_bytes());
+    section
+}
+
+fn crc32_mpeg2(data: &[u8]) -> u32 {
+    let mut crc = 0xFFFFFFFFu32;
+    for byte in data {
+        crc ^= (*byte as u32) << 24;
+        for _ in 0..8 {
+            crc = if crc & 0x80000000 != 0 {
+                (crc << 1) ^ 0x04C11DB7
+            } else {
+                crc << 1
+            };
+        }
+    }
+    crc
+}
+
+fn pes_packet(stream_id: u8, pts: u64, dts: u64, payload: &[u8]) -> Vec<u8> {
+    let pts = pts & TIMESTAMP_MASK;
+    let dts = dts & TIMESTAMP_MASK;
+    let mut header = vec![];
+    if pts == dts {
+        header.extend_from_slice(&encode_timestamp(0x02, pts));
+    } else {
+        header.extend_from_slice(&encode_timestamp(0x03, pts));
+        header.extend_from_slice(&encode_timestamp(0x01, dts));
+    }
+
+    let mut pes = vec![0x00, 0x00, 0x01, stream_id];
+    let length = 3 + header.len() + payload.len();
+    // video 는 길이가 65535 를 넘을 수 있으므로 0 (길이 없음) 으로 둔다.
+    let length = if stream_id == STREAM_ID_VIDEO || length > u16::MAX as usize {
+        0
+    } else {
+        length as u16
+    };
+    pes.extend_from_slice(&length.to_be_bytes());
+    pes.push(0x80);
+    pes.push(if pts == dts { 0x80 } else { 0xC0 });
+    pes.push(header.len() as u8);
+    pes.extend_from_slice(&header);
+    pes.extend_from_slice(payload);
+    pes
+}
+
+fn encode_timestamp(prefix: u8, ts: u64) -> [u8; 5] {
+    [
+        (prefix << 4) | ((((ts >> 30) & 0x07) as u8) << 1) | 1,
+        ((ts >> 22) & 0xFF) as u8,
+        ((((ts >> 15) & 0x7F) as u8) << 1) | 1,
+        ((ts >> 7) & 0xFF) as u8,
+        (((ts & 0x7F) as u8) << 1) | 1,
+    ]
+}
+
+// program_clock_reference_base(33) reserved(6) extension(9)
+fn encode_pcr(pcr: u64) -> [u8; 6] {
+    let base = pcr & TIMESTAMP_MASK;
+    [
+        (base >> 25) as u8,
+        (base >> 17) as u8,
+        (base >> 9) as u8,
+        (base >> 1) as u8,
+        (((base & 0x01) as u8) << 7) | 0x7E,
+        0x00,
+    ]
+}
+
+// access unit 의 시작이면 AUD 를 앞에 붙이고, IDR 앞에는 SPS/PPS 를 넣은 Annex-B
+fn annexb(sps: &[u8], pps: &[u8], data: &[u8], access_unit_start: bool) -> Vec<u8> {
+    let mut out = if access_unit_start {
+        AUD.to_vec()
+    } else {
+        vec![]
+    };
+    let mut parameter_sets = false;
+    let mut offset = 0;
+    while offset + 4 <= data.len() {
+        let size = u32::from_be_bytes(data[offset..offset + 4].try_into().unwrap()) as usize;
+        offset += 4;
+        if size == 0 || offset + size > data.len() {
+            break;
+        }
+        let nalu = &data[offset..offset + size];
+        offset += size;
+        match nalu[0] & 0x1F {
+            // AUD, SPS, PPS 는 여기서 다시 넣는다.
+            7..=9 => continue,
+            5 if !parameter_sets => {
+                parameter_sets = true;
+                for parameter_set in [sps, pps] {
+                    out.extend_from_slice(&START_CODE);
+                    out.extend_from_slice(parameter_set);
+                }
+            }
+            _ => {}
+        }
+        out.extend_from_slice(&START_CODE);
+        out.extend_from_slice(nalu);
+    }
+    out
+}
+
+// opus_control_header 로 감싼 access unit
+fn opus_access_unit(data: &[u8]) -> Vec<u8> {
+    let mut out = vec![0x7F, 0xE0];
+    let mut size = data.len();
+    while size >= 0xFF {
+        out.push(0xFF);
+        size -= 0xFF;
+    }
+    out.push(size as u8);
+    out.extend_from_slice(data);
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocols::mpegts::demuxer::{Demuxer, StreamKind};
+    use crate::protocols::mpegts::opus::split_access_units;
+
+    #[test]
+    fn test_muxer() -> anyhow::Result<()> {
+        let sps = Bytes::from_static(&[0x67, 0x42, 0x00, 0x1F]);
+        let pps = Bytes::from_static(&[0x68, 0xCE, 0x3C, 0x80]);
+        let aac = aac::config::Config::from(&[0x12, 0x10])?;
+        let mut muxer = TsMuxer::new(vec![
+            TsTrack::H264 { sps, pps },
+            TsTrack::Aac(aac),
+            TsTrack::Opus { channels: 2 },
+        ])?;
+        muxer.write_tables();
+
+        let mut idr = vec![0x00, 0x00, 0x01, 0x00, 0x65];
+        idr.resize(4 + 0x100, 0xAB);
+        muxer.write_frame(0, 6000, 3000, true, &idr)?;
+        muxer.write_frame(1, 3000, 3000, true, &[0x21; 10])?;
+        muxer.write_frame(2, 3000, 3000, true, &[0x0C; 300])?;
+        let data = muxer.take();
+        assert_eq!(data.len() % TS_PACKET_SIZE, 0);
+
+        let mut demuxer = Demuxer::new();
+        let mut frames = demuxer.demux(&data);
+        frames.extend(demuxer.flush());
+        frames.sort_by_key(|frame| frame.pid);
+        assert_eq!(frames.len(), 3);
+
+        assert_eq!(frames[0].kind, StreamKind::H264);
+        assert_eq!(frames[0].pts, 6000 + PCR_DELAY);
+        assert_eq!(frames[0].dts, 3000 + PCR_DELAY);
+        let mut expected = AUD.to_vec();
+        for nalu in [
+            &[0x67, 0x42, 0x00, 0x1F][..],
+            &[0x68, 0xCE, 0x3C, 0x80][..],
+            &idr[4..],
+        ] {
+            expected.extend_from_slice(&START_CODE);
+            expected.extend_from_slice(nalu);
+        }
+        assert_eq!(frames[0].payload.as_ref(), expected.as_slice());
+
+        assert_eq!(frames[1].kind, StreamKind::Aac);
+        let adts = aac::adts::split_frames(&frames[1].payload)?;
+        assert_eq!(adts.len(), 1);
+        assert_eq!(adts[0].config.payload.as_ref(), &[0x12, 0x10]);
+        assert_eq!(adts[0].payload.as_ref(), &[0x21; 10]);
+
+        assert_eq!(frames[2].kind, StreamKind::Opus);
+        let units = split_access_units(&frames[2].payload)?;
+        assert_eq!(units.len(), 1);
+        assert_eq!(units[0].as_ref(), &[0x0C; 300]);
+        Ok(())
+    }
+}
@@ -0,0 +1,47 @@
+use bytes::Bytes;
+
+// opus_control_header(Opus in MPEG-TS) 로 감싸진 access unit 들을 분리한다.
+pub fn split_access_units(data: &Bytes) -> anyhow::Result<Vec<Bytes>> {
+    let mut units = Vec::new();
+    let mut offset = 0;
+    while offset + 2 <= data.len() {
+        if data[offset] != 0x7F || data[offset + 1] & 0xE0 != 0xE0 {
+            anyhow::bail!("invalid opus control header");
+        }
+        let start_trim_flag = data[offset + 1] & 0x10 != 0;
+        let end_trim_flag = data[offset + 1] & 0x08 != 0;
+        let control_extension_flag = data[offset + 1] & 0x04 != 0;
+        offset += 2;
+
+        let mut au_size = 0usize;
+        loop {
+            if offset >= data.len() {
+                anyhow::bail!("short opus au size");
+            }
+            let b = data[offset];
+            offset += 1;
+            au_size += b as usize;
+            if b != 0xFF {
+                break;
+            }
+        }
+        if start_trim_flag {
+            offset += 2;
+        }
+        if end_trim_flag {
+            offset += 2;
+        }
+        if control_extension_flag {
+            if offset >= data.len() {
+                anyhow::bail!("short opus control extension");
+            }
+            offset += 1 + data[offset] as usize;
+        }
+        if offset + au_size > data.len() {
+            anyhow::bail!("short opus access unit");
+        }
+        units.push(data.slice(offset..offset + au_size));
+        offset += au_size;
+    }
+    Ok(units)
+}
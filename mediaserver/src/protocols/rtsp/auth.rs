@@ -0,0 +1,105 @@
+use base64::Engine;
+use md5::{Digest, Md5};
+
+#[derive(Debug, Clone)]
+pub struct Credentials {
+    pub username: String,
+    pub password: String,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Challenge {
+    Basic,
+    Digest { realm: String, nonce: String },
+}
+
+impl Challenge {
+    // WWW-Authenticate 헤더들 중 Digest 를 우선 사용한다.
+    pub fn parse<'a>(headers: impl Iterator<Item = &'a str>) -> Option<Challenge> {
+        let mut challenge = None;
+        for header in headers {
+            let (scheme, params) = header.trim().split_once(' ').unwrap_or((header, ""));
+            if scheme.eq_ignore_ascii_case("digest") {
+                let mut realm = String::new();
+                let mut nonce = String::new();
+                for param in params.split(',') {
+                    let Some((key, value)) = param.trim().split_once('=') else {
+                        continue;
+                    };
+                    let value = value.trim_matches('"').to_string();
+                    match key.trim() {
+                        "realm" => realm = value,
+                        "nonce" => nonce = value,
+                        _ => {}
+                    }
+                }
+                return Some(Challenge::Digest { realm, nonce });
+            } else if scheme.eq_ignore_ascii_case("basic") {
+                challenge = Some(Challenge::Basic);
+            }
+        }
+        challenge
+    }
+
+    pub fn authorization(&self, credentials: &Credentials, method: &str, uri: &str) -> String {
+        match self {
+            Challenge::Basic => {
+                let token = base64::engine::general_purpose::STANDARD
+                    .encode(format!("{}:{}", credentials.username, credentials.password));
+                format!("Basic {}", token)
+            }
+            Challenge::Digest { realm, nonce } => {
+                // RFC 2617 (qop 없음)
+                let ha1 = md5_hex(&format!(
+                    "{}:{}:{}",
+                    credentials.username, realm, credentials.password
+                ));
+                let ha2 = md5_hex(&format!("{}:{}", method, uri));
+                let response = md5_hex(&format!("{}:{}:{}", ha1, nonce, ha2));
+                format!(
+                    "Digest username=\"{}\", realm=\"{}\", nonce=\"{}\", uri=\"{}\", response=\"{}\"",
+                    credentials.username, realm, nonce, uri, response
+                )
+            }
+        }
+    }
+}
+
+fn md5_hex(value: &str) -> String {
+    hex::encode(Md5::digest(value.as_bytes()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_digest_authorization() {
+        let challenge = Challenge::parse(
+            [
+                "Basic realm=\"camera\"",
+                "Digest realm=\"camera\", nonce=\"abc123\"",
+            ]
+            .into_iter(),
+        )
+        .unwrap();
+        assert_eq!(
+            challenge,
+            Challenge::Digest {
+                realm: "camera".to_string(),
+                nonce: "abc123".to_string(),
+            }
+        );
+
+        let credentials = Credentials {
+            username: "admin".to_string(),
+            password: "pass".to_string(),
+        };
+        let authorization = challenge.authorization(&credentials, "DESCRIBE", "rtsp://camera/live");
+        let ha1 = md5_hex("admin:camera:pass");
+        let ha2 = md5_hex("DESCRIBE:rtsp://camera/live");
+        let response = md5_hex(&format!("{}:abc123:{}", ha1, ha2));
+        assert!(authorization.starts_with("Digest username=\"admin\""));
+        assert!(authorization.ends_with(&format!("response=\"{}\"", response)));
+    }
+}
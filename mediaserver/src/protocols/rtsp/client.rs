@@ -0,0 +1,159 @@
+use crate::protocols::rtsp::auth::Challenge;
+use crate::protocols::rtsp::message::{read_message, Message, Request, Response};
+use crate::protocols::rtsp::sdp::SessionDescription;
+use crate::protocols::rtsp::url::RtspUrl;
+use anyhow::anyhow;
+use std::time::Duration;
+use tokio::io::{AsyncWriteExt, BufReader};
+use tokio::net::tcp::{OwnedReadHalf, OwnedWriteHalf};
+use tokio::net::TcpStream;
+
+const USER_AGENT: &str = "mediaserver-rust";
+const DEFAULT_SESSION_TIMEOUT: Duration = Duration::from_secs(60);
+
+pub struct RtspClient {
+    url: RtspUrl,
+    reader: Option<BufReader<OwnedReadHalf>>,
+    writer: OwnedWriteHalf,
+    cseq: u32,
+    session: Option<String>,
+    session_timeout: Duration,
+    challenge: Option<Challenge>,
+}
+
+impl RtspClient {
+    pub async fn connect(url: &str) -> anyhow::Result<RtspClient> {
+        let url = RtspUrl::parse(url)?;
+        let stream = TcpStream::connect(url.address()).await?;
+        let (reader, writer) = stream.into_split();
+        Ok(RtspClient {
+            url,
+            reader: Some(BufReader::new(reader)),
+            writer,
+            cseq: 0,
+            session: None,
+            session_timeout: DEFAULT_SESSION_TIMEOUT,
+            challenge: None,
+        })
+    }
+
+    pub fn url(&self) -> &str {
+        &self.url.url
+    }
+
+    pub fn session_timeout(&self) -> Duration {
+        self.session_timeout
+    }
+
+    // PLAY 이후 interleaved 데이터와 응답을 별도의 task 에서 읽기 위해 reader 를 넘긴다.
+    pub fn take_reader(&mut self) -> Option<BufReader<OwnedReadHalf>> {
+        self.reader.take()
+    }
+
+    pub async fn describe(&mut self) -> anyhow::Result<(SessionDescription, String)> {
+        let url = self.url.url.clone();
+        let response = self
+            .request(Request::new("DESCRIBE", &url).header("Accept", "application/sdp"))
+            .await?;
+        let sdp = SessionDescription::parse(&String::from_utf8_lossy(&response.body))?;
+        let base = response
+            .header("Content-Base")
+            .or(response.header("Content-Location"))
+            .unwrap_or(&url)
+            .to_string();
+        Ok((sdp, base))
+    }
+
+    pub async fn setup(&mut self, uri: &str, transport: &str) -> anyhow::Result<Response> {
+        let response = self
+            .request(Request::new("SETUP", uri).header("Transport", transport))
+            .await?;
+
+        // Session: <id>[;timeout=<seconds>]
+        let header = response
+            .header("Session")
+            .ok_or(anyhow!("no session in setup response"))?;
+        let mut params = header.split(';');
+        self.session = params.next().map(|id| id.trim().to_string());
+        for param in params {
+            if let Some(timeout) = param.trim().strip_prefix("timeout=") {
+                self.session_timeout = Duration::from_secs(timeout.parse().unwrap_or(60));
+            }
+        }
+        Ok(response)
+    }
+
+    pub async fn play(&mut self, uri: &str) -> anyhow::Result<()> {
+        self.request(Request::new("PLAY", uri).header("Range", "npt=0.000-"))
+            .await?;
+        Ok(())
+    }
+
+    pub async fn request(&mut self, request: Request) -> anyhow::Result<Response> {
+        let method = request.method.clone();
+        let uri = request.uri.clone();
+        let headers = request.headers.clone();
+
+        let response = self.exchange(request).await?;
+        if response.status != 401 || self.url.credentials.is_none() || self.challenge.is_some() {
+            return check_response(&method, response);
+        }
+
+        // 인증이 필요한 경우 challenge 를 저장하고 한번 더 요청한다.
+        self.challenge = Challenge::parse(response.headers("WWW-Authenticate"));
+        let mut request = Request::new(&method, &uri);
+        request.headers = headers;
+        check_response(&method, self.exchange(request).await?)
+    }
+
+    // 응답을 기다리지 않고 요청만 보낸다. (keepalive, teardown)
+    pub async fn send(&mut self, request: Request) -> anyhow::Result<()> {
+        let request = self.prepare(request);
+        self.writer.write_all(&request.to_bytes()).await?;
+        Ok(())
+    }
+
+    async fn exchange(&mut self, request: Request) -> anyhow::Result<Response> {
+        let request = self.prepare(request);
+        self.writer.write_all(&request.to_bytes()).await?;
+
+        let reader = self
+            .reader
+            .as_mut()
+            .ok_or(anyhow!("rtsp reader already taken"))?;
+        loop {
+            match read_message(reader).await? {
+                Message::Response(response) => return Ok(response),
+                // PLAY 응답 전에 도착한 interleaved 데이터는 버린다.
+                Message::Interleaved { .. } => continue,
+            }
+        }
+    }
+
+    fn prepare(&mut self, mut request: Request) -> Request {
+        self.cseq += 1;
+        request = request
+            .header("CSeq", &self.cseq.to_string())
+            .header("User-Agent", USER_AGENT);
+        if let Some(ref session) = self.session {
+            request = request.header("Session", session);
+        }
+        if let (Some(challenge), Some(credentials)) = (&self.challenge, &self.url.credentials) {
+            let authorization = challenge.authorization(credentials, &request.method, &request.uri);
+            request = request.header("Authorization", &authorization);
+        }
+        request
+    }
+}
+
+fn check_response(method: &str, response: Response) -> anyhow::Result<Response> {
+    if !response.is_success() {
+        return Err(anyhow!(
+            "rtsp {} failed: {} {}",
+            method,
+            response.status,
+            response.reason
+        ));
+    }
+    Ok(response)
+}
@@ -0,0 +1,129 @@
+use anyhow::anyhow;
+use bytes::Bytes;
+use tokio::io::{AsyncBufRead, AsyncBufReadExt, AsyncReadExt};
+
+pub const RTSP_VERSION: &str = "RTSP/1.0";
+
+pub struct Request {
+    pub method: String,
+    pub uri: String,
+    pub headers: Vec<(String, String)>,
+}
+
+impl Request {
+    pub fn new(method: &str, uri: &str) -> Self {
+        Request {
+            method: method.to_string(),
+            uri: uri.to_string(),
+            headers: Vec::new(),
+        }
+    }
+
+    pub fn header(mut self, name: &str, value: &str) -> Self {
+        self.headers.push((name.to_string(), value.to_string()));
+        self
+    }
+
+    pub fn to_bytes(&self) -> Bytes {
+        let mut message = format!("{} {} {}\r\n", self.method, self.uri, RTSP_VERSION);
+        for (name, value) in &self.headers {
+            message.push_str(&format!("{}: {}\r\n", name, value));
+        }
+        message.push_str("\r\n");
+        Bytes::from(message)
+    }
+}
+
+#[derive(Debug)]
+pub struct Response {
+    pub status: u16,
+    pub reason: String,
+    pub headers: Vec<(String, String)>,
+    pub body: Bytes,
+}
+
+impl Response {
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(key, _)| key.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+
+    pub fn headers<'a>(&'a self, name: &'a str) -> impl Iterator<Item = &'a str> + 'a {
+        self.headers
+            .iter()
+            .filter(move |(key, _)| key.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+
+    pub fn is_success(&self) -> bool {
+        (200..300).contains(&self.status)
+    }
+}
+
+pub enum Message {
+    Response(Response),
+    // RTP/RTCP over TCP (RFC 2326 10.12)
+    Interleaved { channel: u8, payload: Bytes },
+}
+
+pub async fn read_message<R: AsyncBufRead + Unpin>(reader: &mut R) -> anyhow::Result<Message> {
+    let buffer = reader.fill_buf().await?;
+    if buffer.is_empty() {
+        return Err(anyhow!("rtsp connection closed"));
+    }
+
+    if buffer[0] == b'$' {
+        let mut header = [0u8; 4];
+        reader.read_exact(&mut header).await?;
+        let len = u16::from_be_bytes([header[2], header[3]]) as usize;
+        let mut payload = vec![0u8; len];
+        reader.read_exact(&mut payload).await?;
+        return Ok(Message::Interleaved {
+            channel: header[1],
+            payload: Bytes::from(payload),
+        });
+    }
+
+    let mut line = String::new();
+    reader.read_line(&mut line).await?;
+    let mut parts = line.trim_end().splitn(3, ' ');
+    let version = parts.next().unwrap_or_default();
+    if version != RTSP_VERSION {
+        return Err(anyhow!("invalid rtsp status line: {}", line.trim_end()));
+    }
+    let status = parts
+        .next()
+        .ok_or(anyhow!("no rtsp status code"))?
+        .parse::<u16>()?;
+    let reason = parts.next().unwrap_or_default().to_string();
+
+    let mut headers = Vec::new();
+    loop {
+        line.clear();
+        if reader.read_line(&mut line).await? == 0 {
+            return Err(anyhow!("rtsp connection closed"));
+        }
+        let header = line.trim_end();
+        if header.is_empty() {
+            break;
+        }
+        if let Some((name, value)) = header.split_once(':') {
+            headers.push((name.trim().to_string(), value.trim().to_string()));
+        }
+    }
+
+    let mut response = Response {
+        status,
+        reason,
+        headers,
+        body: Bytes::new(),
+    };
+    if let Some(len) = response.header("Content-Length") {
+        let mut body = vec![0u8; len.parse::<usize>()?];
+        reader.read_exact(&mut body).await?;
+        response.body = Bytes::from(body);
+    }
+    Ok(Message::Response(response))
+}
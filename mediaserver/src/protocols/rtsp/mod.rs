@@ -0,0 +1,5 @@
+pub mod auth;
+pub mod client;
+pub mod message;
+pub mod sdp;
+pub mod url;
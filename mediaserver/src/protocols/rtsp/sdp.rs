@@ -0,0 +1,146 @@
+use anyhow::anyhow;
+use base64::Engine;
+use bytes::Bytes;
+use std::collections::HashMap;
+
+#[derive(Debug, Clone, Default)]
+pub struct MediaDescription {
+    pub kind: String,
+    pub payload_type: u8,
+    // "video/H264" 와 같이 RtpParser 가 받는 형태
+    pub mime_type: String,
+    pub clock_rate: u32,
+    pub channels: u16,
+    pub control: Option<String>,
+    pub fmtp: HashMap<String, String>,
+}
+
+impl MediaDescription {
+    // RFC 6184 sprop-parameter-sets
+    pub fn sprop_parameter_sets(&self) -> anyhow::Result<Vec<Bytes>> {
+        let Some(sets) = self.fmtp.get("sprop-parameter-sets") else {
+            return Ok(vec![]);
+        };
+        sets.split(',')
+            .filter(|set| !set.is_empty())
+            .map(|set| {
+                base64::engine::general_purpose::STANDARD
+                    .decode(set)
+                    .map(Bytes::from)
+                    .map_err(|err| anyhow!("invalid sprop-parameter-sets: {}", err))
+            })
+            .collect()
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct SessionDescription {
+    pub control: Option<String>,
+    pub medias: Vec<MediaDescription>,
+}
+
+impl SessionDescription {
+    pub fn parse(sdp: &str) -> anyhow::Result<SessionDescription> {
+        let mut session = SessionDescription::default();
+        for line in sdp.lines() {
+            let Some((kind, value)) = line.trim().split_once('=') else {
+                continue;
+            };
+            match kind {
+                "m" => {
+                    // m=<media> <port> <proto> <fmt> ...
+                    let fields: Vec<&str> = value.split_whitespace().collect();
+                    if fields.len() < 4 {
+                        return Err(anyhow!("invalid media line: {}", line));
+                    }
+                    session.medias.push(MediaDescription {
+                        kind: fields[0].to_string(),
+                        payload_type: fields[3].parse()?,
+                        ..Default::default()
+                    });
+                }
+                "a" => {
+                    let (name, value) = value.split_once(':').unwrap_or((value, ""));
+                    match session.medias.last_mut() {
+                        Some(media) => parse_media_attribute(media, name, value),
+                        None if name == "control" => session.control = Some(value.to_string()),
+                        None => {}
+                    }
+                }
+                _ => {}
+            }
+        }
+        Ok(session)
+    }
+}
+
+fn parse_media_attribute(media: &mut MediaDescription, name: &str, value: &str) {
+    match name {
+        "control" => media.control = Some(value.to_string()),
+        "rtpmap" => {
+            // a=rtpmap:<payload type> <encoding name>/<clock rate>[/<channels>]
+            let Some((payload_type, encoding)) = value.split_once(' ') else {
+                return;
+            };
+            if payload_type.parse::<u8>().ok() != Some(media.payload_type) {
+                return;
+            }
+            let mut fields = encoding.split('/');
+            let name = fields.next().unwrap_or_default();
+            media.mime_type = format!("{}/{}", media.kind, name);
+            media.clock_rate = fields.next().and_then(|v| v.parse().ok()).unwrap_or(0);
+            media.channels = fields.next().and_then(|v| v.parse().ok()).unwrap_or(0);
+        }
+        "fmtp" => {
+            let Some((payload_type, params)) = value.split_once(' ') else {
+                return;
+            };
+            if payload_type.parse::<u8>().ok() != Some(media.payload_type) {
+                return;
+            }
+            for param in params.split(';') {
+                if let Some((key, value)) = param.trim().split_once('=') {
+                    media.fmtp.insert(key.to_string(), value.to_string());
+                }
+            }
+        }
+        _ => {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_sdp() -> anyhow::Result<()> {
+        let sdp = "v=0\r\n\
+            o=- 0 0 IN IP4 127.0.0.1\r\n\
+            s=camera\r\n\
+            a=control:*\r\n\
+            m=video 0 RTP/AVP 96\r\n\
+            a=rtpmap:96 H264/90000\r\n\
+            a=fmtp:96 packetization-mode=1;sprop-parameter-sets=Z0LAHtoFB+Q=,aM48gA==\r\n\
+            a=control:trackID=0\r\n\
+            m=audio 0 RTP/AVP 97\r\n\
+            a=rtpmap:97 opus/48000/2\r\n\
+            a=control:trackID=1\r\n";
+        let session = SessionDescription::parse(sdp)?;
+        assert_eq!(session.control.as_deref(), Some("*"));
+        assert_eq!(session.medias.len(), 2);
+
+        let video = &session.medias[0];
+        assert_eq!(video.mime_type, "video/H264");
+        assert_eq!(video.clock_rate, 90000);
+        assert_eq!(video.control.as_deref(), Some("trackID=0"));
+        let sets = video.sprop_parameter_sets()?;
+        assert_eq!(sets.len(), 2);
+        assert_eq!(sets[0][0] & 0x1F, 7);
+        assert_eq!(sets[1][0] & 0x1F, 8);
+
+        let audio = &session.medias[1];
+        assert_eq!(audio.mime_type, "audio/opus");
+        assert_eq!(audio.channels, 2);
+        Ok(())
+    }
+}
@@ -1,4 +1,4 @@
-#[derive(PartialEq, Debug, Hash)]
+#[derive(Clone, Copy, PartialEq, Debug, Hash)]
 pub enum MediaKind {
     Audio,
     Video,
@@ -0,0 +1,165 @@
+use anyhow::anyhow;
+use std::time::Duration;
+use tokio::time;
+use webrtc::ice_transport::ice_candidate::RTCIceCandidateInit;
+use webrtc::peer_connection::sdp::session_description::RTCSessionDescription;
+use webrtc::peer_connection::RTCPeerConnection;
+
+pub const SDPFRAG_CONTENT_TYPE: &str = "application/trickle-ice-sdpfrag";
+
+// application/trickle-ice-sdpfrag (RFC 8840)
+#[derive(Debug, Default)]
+pub struct SdpFrag {
+    pub ice_ufrag: Option<String>,
+    pub ice_pwd: Option<String>,
+    pub candidates: Vec<RTCIceCandidateInit>,
+}
+
+impl SdpFrag {
+    pub fn parse(body: &str) -> SdpFrag {
+        let mut frag = SdpFrag::default();
+        let mut mid: Option<String> = None;
+        let mut mline_index: Option<u16> = None;
+        for line in body.lines().map(|line| line.trim()) {
+            if let Some(value) = line.strip_prefix("a=ice-ufrag:") {
+                frag.ice_ufrag = Some(value.to_string());
+            } else if let Some(value) = line.strip_prefix("a=ice-pwd:") {
+                frag.ice_pwd = Some(value.to_string());
+            } else if line.starts_with("m=") {
+                mline_index = Some(mline_index.map_or(0, |index| index + 1));
+                mid = None;
+            } else if let Some(value) = line.strip_prefix("a=mid:") {
+                mid = Some(value.to_string());
+            } else if let Some(candidate) = line.strip_prefix("a=") {
+                if !candidate.starts_with("candidate:") {
+                    continue;
+                }
+                frag.candidates.push(RTCIceCandidateInit {
+                    candidate: candidate.to_string(),
+                    sdp_mid: mid.clone(),
+                    sdp_mline_index: mline_index,
+                    username_fragment: None,
+                });
+            }
+        }
+        for candidate in frag.candidates.iter_mut() {
+            candidate.username_fragment = frag.ice_ufrag.clone();
+        }
+        frag
+    }
+}
+
+// WHIP/WHEP PATCH 를 처리한다.
+// ice-ufrag 가 바뀌었으면 ICE restart 로 보고 새 local credential 을 담은 sdpfrag 를 돌려준다.
+pub async fn patch(pc: &RTCPeerConnection, body: &str) -> anyhow::Result<Option<String>> {
+    let frag = SdpFrag::parse(body);
+    let remote = pc
+        .remote_description()
+        .await
+        .ok_or(anyhow!("no remote description"))?;
+
+    let answer = match (&frag.ice_ufrag, &frag.ice_pwd) {
+        (Some(ufrag), Some(pwd)) if ice_ufrag(&remote.sdp) != Some(ufrag.as_str()) => {
+            Some(restart(pc, &remote.sdp, ufrag, pwd).await?)
+        }
+        _ => None,
+    };
+
+    for candidate in frag.candidates {
+        pc.add_ice_candidate(candidate).await?;
+    }
+    Ok(answer)
+}
+
+async fn restart(
+    pc: &RTCPeerConnection,
+    remote_sdp: &str,
+    ufrag: &str,
+    pwd: &str,
+) -> anyhow::Result<String> {
+    log::info!("ice restart ufrag:{}", ufrag);
+    let offer: String = remote_sdp
+        .lines()
+        .filter(|line| {
+            !line.starts_with("a=candidate:") && !line.starts_with("a=end-of-candidates")
+        })
+        .map(|line| {
+            if line.starts_with("a=ice-ufrag:") {
+                format!("a=ice-ufrag:{}\r\n", ufrag)
+            } else if line.starts_with("a=ice-pwd:") {
+                format!("a=ice-pwd:{}\r\n", pwd)
+            } else {
+                format!("{}\r\n", line)
+            }
+        })
+        .collect();
+
+    pc.set_remote_description(RTCSessionDescription::offer(offer)?)
+        .await?;
+    let answer = pc.create_answer(None).await?;
+    let mut gathering_complete = pc.gathering_complete_promise().await;
+    pc.set_local_description(answer).await?;
+    if time::timeout(Duration::from_secs(2), gathering_complete.recv())
+        .await
+        .is_err()
+    {
+        return Err(anyhow!("wait candidate timeout"));
+    }
+
+    let local = pc
+        .local_description()
+        .await
+        .ok_or(anyhow!("no local description"))?;
+    Ok(to_sdpfrag(&local.sdp))
+}
+
+fn ice_ufrag(sdp: &str) -> Option<&str> {
+    sdp.lines()
+        .find_map(|line| line.trim().strip_prefix("a=ice-ufrag:"))
+}
+
+fn to_sdpfrag(sdp: &str) -> String {
+    let mut frag = String::new();
+    for prefix in ["a=ice-ufrag:", "a=ice-pwd:"] {
+        if let Some(line) = sdp.lines().find(|line| line.starts_with(prefix)) {
+            frag.push_str(line);
+            frag.push_str("\r\n");
+        }
+    }
+    for line in sdp.lines() {
+        if line.starts_with("m=")
+            || line.starts_with("a=mid:")
+            || line.starts_with("a=candidate:")
+            || line.starts_with("a=end-of-candidates")
+        {
+            frag.push_str(line);
+            frag.push_str("\r\n");
+        }
+    }
+    frag
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_sdpfrag() {
+        let body = "a=ice-ufrag:EsAw\r\n\
+            a=ice-pwd:P2uYro0UCOQ4zxjKXaWCBui1\r\n\
+            m=audio 9 RTP/AVP 0\r\n\
+            a=mid:0\r\n\
+            a=candidate:1387637174 1 udp 2122260223 192.0.2.1 61764 typ host generation 0\r\n\
+            a=end-of-candidates\r\n";
+        let frag = SdpFrag::parse(body);
+        assert_eq!(frag.ice_ufrag.as_deref(), Some("EsAw"));
+        assert_eq!(frag.ice_pwd.as_deref(), Some("P2uYro0UCOQ4zxjKXaWCBui1"));
+        assert_eq!(frag.candidates.len(), 1);
+
+        let candidate = &frag.candidates[0];
+        assert!(candidate.candidate.starts_with("candidate:1387637174"));
+        assert_eq!(candidate.sdp_mid.as_deref(), Some("0"));
+        assert_eq!(candidate.sdp_mline_index, Some(0));
+        assert_eq!(candidate.username_fragment.as_deref(), Some("EsAw"));
+    }
+}
@@ -1 +1,2 @@
+pub mod ice;
 pub mod webrtc_api;